@@ -11,6 +11,7 @@ use crate::cpu::CPU;
 use crate::apu::channel::{DMC, Noise, SquareWave, TriangleWave, Voice};
 use crate::apu::filter::{Filter, HighPassFilter, LowPassFilter};
 use crate::console::NES_APU_CHANNELS;
+use crate::mapper::Mapper;
 use crate::mem::Memory;
 use crate::serde;
 
@@ -47,6 +48,10 @@ pub struct APU {
     frame_irq: bool,
 
     filters: [Box<dyn Filter>; 3],
+
+    // Some cartridges have their own sound hardware, which is mixed in with
+    // the APU's output
+    cartridge: Option<Rc<RefCell<Box<dyn Mapper>>>>,
 }
 
 impl Memory for APU {
@@ -178,6 +183,8 @@ impl APU {
                 Box::new(HighPassFilter::new_filter(44_100.0, 440.0)),
                 Box::new(LowPassFilter::new_filter(44_100.0, 14_000.0)),
             ],
+
+            cartridge: None,
         }
     }
 
@@ -193,6 +200,10 @@ impl APU {
         self.dmc.cpu = Some(cpu);
     }
 
+    pub fn attach_cartridge(&mut self, cartridge: Rc<RefCell<Box<dyn Mapper>>>) {
+        self.cartridge = Some(cartridge);
+    }
+
     //  $4015   if-d nt21   DMC IRQ, frame IRQ, length counter statuses
     fn read_status(&mut self) -> u8 {
         let mut rv = 0;
@@ -334,7 +345,12 @@ impl APU {
                                           + (n as f32 / 12241.0)
                                           + (dmc as f32 / 22638.0))));
 
-        let signal = pulse_val + tnd_val;
+        let expansion = match &self.cartridge {
+            Some(cartridge) if *NES_APU_CHANNELS & 32 != 0 => cartridge.borrow().signal(),
+            _ => 0.0,
+        };
+
+        let signal = pulse_val + tnd_val + expansion;
 
         self.filters
             .iter_mut()
@@ -11,11 +11,15 @@ use std::time::{Duration, Instant};
 use crate::apu::APU;
 use crate::controller::Controller;
 use crate::cpu::CPU;
-use crate::mapper::{Mapper, MapperEvent};
-use crate::mem::{Memory, NESMemory};
-use crate::ppu::PPU;
+use crate::mapper::{Mapper, MapperEvent, MapperRegistry};
+use crate::mem::{Memory, NESMemory, RAMInit};
+use crate::ppu::{Filter, NTSCFilter, NTSCPreset, PaletteFilter, PPU, SCREEN_WIDTH, SCREEN_HEIGHT};
+use crate::ppu::{PostProcessor, SCALERS};
+use crate::settings::{Overscan, VideoSettings, MAX_SCALE};
 use crate::ines::CartridgeError;
 use crate::ines;
+use crate::palette;
+use crate::palette::{NTSCSettings, PALETTE};
 
 use sdl2::audio::AudioSpecDesired;
 use sdl2::pixels::Color;
@@ -23,6 +27,7 @@ use sdl2::pixels::PixelFormatEnum;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::rect::Rect;
+use sdl2::video::FullscreenType;
 
 lazy_static!{
     pub static ref NES_PPU_DEBUG: bool = match env::var("NES_PPU_DEBUG") {
@@ -30,10 +35,81 @@ lazy_static!{
         Err(_)  => false,
     };
 
+    // Draws every sprite on a line, rather than the hardware's 8
+    pub static ref NES_NO_SPRITE_LIMIT: bool = match env::var("NES_NO_SPRITE_LIMIT") {
+        Ok(val) => val != "" && val != "0",
+        Err(_)  => false,
+    };
+
     pub static ref NES_APU_CHANNELS: u8 = match env::var("NES_APU_CHANNELS") {
         Ok(val) => val.parse().expect("invalid NES_APU_CHANNELS value"),
         Err(_)  => std::u8::MAX,
     };
+
+    pub static ref NES_FDS_BIOS: String = match env::var("NES_FDS_BIOS") {
+        Ok(val) => val,
+        Err(_)  => "disksys.rom".to_string(),
+    };
+
+    // What CPU RAM holds at power on: zeros, ones, random or random:SEED
+    pub static ref NES_RAM_INIT: RAMInit = match env::var("NES_RAM_INIT") {
+        Ok(val) => RAMInit::parse(&val).expect("invalid NES_RAM_INIT value"),
+        Err(_)  => RAMInit::Zeros,
+    };
+
+    // The palette to start with: "default", "ntsc" or the path to a .pal file
+    pub static ref NES_PALETTE: String = match env::var("NES_PALETTE") {
+        Ok(val) => val,
+        Err(_)  => "default".to_string(),
+    };
+
+    pub static ref NES_NTSC_PALETTE: NTSCSettings = match env::var("NES_NTSC_PALETTE") {
+        Ok(val) => NTSCSettings::parse(&val).expect("invalid NES_NTSC_PALETTE value"),
+        Err(_)  => NTSCSettings::new_ntsc_settings(),
+    };
+
+    // The post-processing to start with, like "hq2x,crt,8:7", see
+    // PostProcessor::parse()
+    pub static ref NES_SCALE: String = match env::var("NES_SCALE") {
+        Ok(val) => val,
+        Err(_)  => "3x".to_string(),
+    };
+
+    // Overrides the remembered overscan, as "top,bottom,left,right"
+    pub static ref NES_OVERSCAN: Option<Overscan> = match env::var("NES_OVERSCAN") {
+        Ok(val) => Some(Overscan::parse(&val).expect("invalid NES_OVERSCAN value")),
+        Err(_)  => None,
+    };
+
+    // Overrides the remembered window scale
+    pub static ref NES_WINDOW_SCALE: Option<u32> = match env::var("NES_WINDOW_SCALE") {
+        Ok(val) => Some(val.parse().ok().filter(|s| (1 ..= MAX_SCALE).contains(s)).expect("invalid NES_WINDOW_SCALE value")),
+        Err(_)  => None,
+    };
+
+    // The video filter to start with, one of FILTERS
+    pub static ref NES_FILTER: usize = match env::var("NES_FILTER") {
+        Ok(val) => FILTERS.iter().position(|&f| f == val).expect("invalid NES_FILTER value"),
+        Err(_)  => 0,
+    };
+}
+
+// The video filters that can be switched between. The NTSC ones simulate the
+// different cables from the console to the TV, using the NES_NTSC_PALETTE
+// settings.
+const FILTERS: [&str; 4] = ["none", "composite", "svideo", "rgb"];
+
+// The path on disk to persist the window and overscan settings to, which
+// apply to every ROM.
+const VIDEO_SETTINGS_PATH: &str = "video.cfg";
+
+fn new_filter(i: usize) -> Box<dyn Filter> {
+    match FILTERS[i] {
+        "composite" => Box::new(NTSCFilter::new_filter(NTSCPreset::Composite, *NES_NTSC_PALETTE)),
+        "svideo"    => Box::new(NTSCFilter::new_filter(NTSCPreset::SVideo, *NES_NTSC_PALETTE)),
+        "rgb"       => Box::new(NTSCFilter::new_filter(NTSCPreset::RGB, *NES_NTSC_PALETTE)),
+        _           => Box::new(PaletteFilter::new_filter()),
+    }
 }
 
 const NES_FPS: f64 = 60.0;
@@ -53,28 +129,89 @@ pub struct Console {
 
     // The absolute path on disk to save state to
     save_path:  String,
+
+    // The path on disk to persist battery-backed RAM to
+    battery_path: String,
+
+    // The path on disk to persist the cartridge's DIP switch setting to
+    dip_path: String,
+
+    // The palettes that can be switched between, by name, and the one in use
+    palettes: Vec<(String, Vec<Color>)>,
+    palette: usize,
+
+    // Turns the PPU's palette indexes into RGB for the screen, and which of
+    // FILTERS it is.
+    filter: Box<dyn Filter>,
+    filter_index: usize,
+
+    // Scales up the filter's output, which is kept in `filtered'
+    post: PostProcessor,
+    filtered: Vec<u8>,
+
+    video: VideoSettings,
 }
 
 impl Console {
-    pub fn new_nes_console(rom_path: &String) -> Result<Self, CartridgeError> {
+    pub fn new_nes_console(rom_path: &String, registry: &MapperRegistry)
+        -> Result<Self, CartridgeError>
+    {
         let full_path = fs::canonicalize(rom_path).map_err(CartridgeError::IO)?;
         info!("loading cartridge: {}", full_path.display());
         let basename_path = full_path.file_name().unwrap().to_str().unwrap();
         let save_path = format!("{:x}.data", md5::compute(basename_path)).into();
+        let battery_path = format!("{:x}.sav", md5::compute(basename_path)).into();
+        let dip_path = format!("{:x}.dip", md5::compute(basename_path)).into();
 
         let mut fh = File::open(full_path).map_err(CartridgeError::IO)?;
-        let cartridge = ines::load_file_into_memory(&mut fh)?;
+        let cartridge = ines::load_file_into_memory(&mut fh, registry)?;
 
         let ppu = Rc::new(RefCell::new(PPU::new_nes_ppu(cartridge.clone())));
+        ppu.borrow_mut().set_sprite_limit(!*NES_NO_SPRITE_LIMIT);
         let apu = Rc::new(RefCell::new(APU::new_nes_apu()));
         let controller = Rc::new(RefCell::new(Controller::new_controller()));
         let mem = NESMemory::new_nes_mem(
             ppu.clone(),
             apu.clone(),
-            controller.clone()
+            controller.clone(),
+            *NES_RAM_INIT
         );
         let cpu = Rc::new(RefCell::new(CPU::new_cpu(Box::new(mem))));
         apu.borrow_mut().attach_cpu(cpu.clone());
+        apu.borrow_mut().attach_cartridge(cartridge.clone());
+
+        let mut palettes = vec![
+            ("default".to_string(), PALETTE.clone()),
+            ("ntsc".to_string(), palette::generate_ntsc_palette(&NES_NTSC_PALETTE)),
+        ];
+
+        if *NES_PALETTE != "default" && *NES_PALETTE != "ntsc" {
+            match palette::load_palette_file(&NES_PALETTE) {
+                Ok(colors) => palettes.push((NES_PALETTE.to_string(), colors)),
+                Err(e)     => {
+                    println!("There was an error reading the palette from {}: {}", *NES_PALETTE, e);
+                    process::exit(1);
+                },
+            }
+        }
+
+        // Settings given in the environment are remembered too
+        let mut video = VideoSettings::load(VIDEO_SETTINGS_PATH);
+        if let Some(overscan) = *NES_OVERSCAN {
+            video.overscan = overscan;
+        }
+        if let Some(scale) = *NES_WINDOW_SCALE {
+            video.scale = scale;
+        }
+        if NES_OVERSCAN.is_some() || NES_WINDOW_SCALE.is_some() {
+            if let Err(e) = video.save(VIDEO_SETTINGS_PATH) {
+                warn!("unable to save video settings to {}: {}", VIDEO_SETTINGS_PATH, e);
+            }
+        }
+
+        let palette = palettes.iter()
+            .position(|(name, _)| *name == *NES_PALETTE)
+            .unwrap();
 
         Ok(Self {
             cpu:        cpu,
@@ -83,6 +220,15 @@ impl Console {
             cartridge:  cartridge,
             controller: controller,
             save_path:  save_path,
+            battery_path: battery_path,
+            dip_path: dip_path,
+            palettes: palettes,
+            palette: palette,
+            filter: new_filter(*NES_FILTER),
+            filter_index: *NES_FILTER,
+            post: PostProcessor::parse(&NES_SCALE).expect("invalid NES_SCALE value"),
+            filtered: vec![],
+            video: video,
         })
     }
 
@@ -101,7 +247,7 @@ impl Console {
 
             for x in 0 ..= 0x1fff {
                 let b = self.cartridge.borrow_mut().read(x);
-                chr[x as usize] = b;
+                chr[x as usize] = b.unwrap_or(0);
             }
 
             fh.write(&chr).unwrap();
@@ -169,33 +315,223 @@ impl Console {
         }
     }
 
-    pub fn power_up(&mut self) {
-        info!("powering up");
+    fn save_battery(&mut self) {
+        if !self.cartridge.borrow().has_battery() {
+            return;
+        }
 
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+        let mut fh = File::create(&self.battery_path).unwrap();
+        self.cartridge.borrow().save_battery(&mut fh).expect("unable to save battery-backed RAM");
+        info!("saved battery-backed RAM to {}", self.battery_path);
+    }
+
+    fn load_battery(&mut self) {
+        if !self.cartridge.borrow().has_battery() {
+            return;
+        }
+
+        if let Ok(mut fh) = File::open(&self.battery_path) {
+            self.cartridge.borrow_mut().load_battery(&mut fh).expect("unable to load battery-backed RAM");
+            info!("loaded battery-backed RAM from {}", self.battery_path);
+        }
+    }
+
+    fn load_dip_switches(&mut self) {
+        if self.cartridge.borrow().dip_switch_settings() <= 1 {
+            return;
+        }
 
-        let mut width = 256 * 3;
-        let height = 240 * 3;
+        if let Ok(val) = fs::read(&self.dip_path) {
+            if let Some(&val) = val.first() {
+                self.cartridge.borrow_mut().set_dip_switches(val);
+                info!("loaded DIP switches from {}: {}", self.dip_path, val);
+            }
+        }
+    }
+
+    // Moves the DIP switches on to the next setting, and resets, since most
+    // cartridges only look at them on startup.
+    fn cycle_dip_switches(&mut self) {
+        let settings = self.cartridge.borrow().dip_switch_settings();
+        if settings <= 1 {
+            return;
+        }
+
+        let val = (self.cartridge.borrow().dip_switches() + 1) % settings;
+        self.cartridge.borrow_mut().set_dip_switches(val);
+
+        let mut fh = File::create(&self.dip_path).unwrap();
+        fh.write(&[val]).expect("unable to save DIP switches");
+        println!("DIP switches set to {}", val);
+
+        self.reset();
+    }
+
+    // Switches to the next palette, between the default, the generated NTSC
+    // palette and any palette file given at startup.
+    fn cycle_palette(&mut self) {
+        self.palette = (self.palette + 1) % self.palettes.len();
+
+        println!("palette set to {}", self.palettes[self.palette].0);
+    }
+
+    // Switches to the next video filter. The caller needs to make a new
+    // texture, since the filters output different sizes.
+    fn cycle_filter(&mut self) {
+        self.filter_index = (self.filter_index + 1) % FILTERS.len();
+        self.filter = new_filter(self.filter_index);
+        println!("video filter set to {}", FILTERS[self.filter_index]);
+    }
+
+    fn cycle_scaler(&mut self) {
+        let i = SCALERS.iter().position(|&s| s == self.post.scaler).unwrap_or(0);
+        self.post.scaler = SCALERS[(i + 1) % SCALERS.len()];
+        println!("scaler set to {}", self.post.scaler.name());
+    }
+
+    fn cycle_scanlines(&mut self) {
+        self.post.scanlines = self.post.scanlines.next();
+        println!("scanlines set to {}", self.post.scanlines.name());
+    }
+
+    fn cycle_overscan(&mut self) {
+        self.video.overscan = self.video.overscan.next();
+        println!("overscan set to {}", self.video.overscan);
+    }
+
+    fn save_video_settings(&self) {
+        if let Err(e) = self.video.save(VIDEO_SETTINGS_PATH) {
+            warn!("unable to save video settings to {}: {}", VIDEO_SETTINGS_PATH, e);
+        }
+    }
+
+    // The size of the picture at a scale, after the overscan's been cut off.
+    // Pixels on a TV are a little wider than they are tall, at 8:7, which can
+    // be corrected for.
+    fn scaled_size(&self, scale: u32) -> (u32, u32) {
+        let (width, height) = self.video.overscan.visible_size();
+        let mut width = width as u32 * scale;
+        let height = height as u32 * scale;
+
+        if self.post.aspect_correction {
+            width = width * 8 / 7;
+        }
+
+        (width, height)
+    }
+
+    fn screen_size(&self) -> (u32, u32) {
+        self.scaled_size(self.video.scale)
+    }
+
+    fn window_size(&self) -> (u32, u32) {
+        let (mut width, height) = self.screen_size();
 
         if *NES_PPU_DEBUG {
             // Make room for the two pattern tables, side by side
             width += 2 * 144 + 20;
         }
 
-        let window = video_subsystem.window("nes", width, height)
+        (width, height)
+    }
+
+    // Where the picture goes on the screen. In a window it's in the top left,
+    // with any debugging information to its right, and in fullscreen it's
+    // centered at the largest whole number scale that fits the display.
+    fn screen_rect(&self, output_size: (u32, u32)) -> Rect {
+        if !self.video.fullscreen {
+            let (width, height) = self.screen_size();
+            return Rect::new(0, 0, width, height);
+        }
+
+        let (output_width, output_height) = output_size;
+        let (width, height) = self.scaled_size(1);
+        let scale = std::cmp::max(1, std::cmp::min(output_width / width, output_height / height));
+        let (width, height) = self.scaled_size(scale);
+
+        Rect::new(
+            (output_width as i32 - width as i32) / 2,
+            (output_height as i32 - height as i32) / 2,
+            width,
+            height,
+        )
+    }
+
+    // The part of the texture that's left after cutting off the overscan
+    fn crop_rect(&self) -> Rect {
+        let (width, height) = self.post.output_size(self.filter.output_size());
+        let x_scale = width / SCREEN_WIDTH;
+        let y_scale = height / SCREEN_HEIGHT;
+
+        let overscan = &self.video.overscan;
+        let (visible_width, visible_height) = overscan.visible_size();
+
+        Rect::new(
+            (overscan.left * x_scale) as i32,
+            (overscan.top * y_scale) as i32,
+            (visible_width * x_scale) as u32,
+            (visible_height * y_scale) as u32,
+        )
+    }
+
+    // Shrinks the window scale until the window fits on the desktop
+    fn fit_scale(&mut self, desktop: (u32, u32)) {
+        while self.video.scale > 1 {
+            let (width, height) = self.window_size();
+            if width <= desktop.0 && height <= desktop.1 {
+                break;
+            }
+
+            self.video.scale -= 1;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.cpu.borrow_mut().reset();
+        self.ppu.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
+        self.cartridge.borrow_mut().notify(MapperEvent::Reset);
+    }
+
+    pub fn power_up(&mut self) {
+        info!("powering up");
+
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let desktop = match video_subsystem.desktop_display_mode(0) {
+            Ok(mode) => (mode.w as u32, mode.h as u32),
+            Err(_)   => (u32::MAX, u32::MAX),
+        };
+        debug!("desktop: {}x{}", desktop.0, desktop.1);
+        self.fit_scale(desktop);
+
+        let (width, height) = self.window_size();
+
+        let mut window = video_subsystem.window("nes", width, height)
             .position_centered()
             .build()
             .unwrap();
 
+        if self.video.fullscreen {
+            window.set_fullscreen(FullscreenType::Desktop).unwrap();
+        }
+
         let mut canvas = window.into_canvas()
             .target_texture()
             .build()
             .unwrap();
         debug!("canvas: {}", canvas.info().name);
         let texture_creator = canvas.texture_creator();
-        let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width, height)
-            .unwrap();
+        let new_texture = |console: &Self| {
+            let (texture_width, texture_height) = console.post.output_size(console.filter.output_size());
+            texture_creator.create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                texture_width as u32,
+                texture_height as u32,
+            ).unwrap()
+        };
+        let mut texture = new_texture(self);
 
         for _ in 0 .. 2 {
             canvas.clear();
@@ -217,6 +553,14 @@ impl Console {
         let mut samples = Vec::new();
         let mut audio_sampling = true;
 
+        self.load_battery();
+        self.load_dip_switches();
+        if let RAMInit::Random(seed) = *NES_RAM_INIT {
+            info!("RAM initialised from seed {}", seed);
+        }
+
+        self.cartridge.borrow_mut().notify(MapperEvent::PowerOn);
+        self.ppu.borrow_mut().power_on();
         self.cpu.borrow_mut().reset();
 
         let mut event_pump = sdl_context.event_pump().unwrap();
@@ -301,33 +645,28 @@ impl Console {
 
                     let mut ppu = self.ppu.borrow_mut();
                     let pixels  = ppu.get_pixels();
+                    let phases  = ppu.get_line_phases();
+                    let palette = &self.palettes[self.palette].1;
+
+                    let size = self.filter.output_size();
+                    self.filtered.resize(size.0 * size.1 * 3, 0);
+                    self.filter.apply(pixels, phases, palette, &mut self.filtered, size.0 * 3);
+
+                    let filtered = &self.filtered;
+                    let post     = &mut self.post;
 
                     texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                        for y in 0 .. 240 {
-                            for x in 0 .. 256 {
-                                let color  = pixels[y][x];
-                                let offset = 3*(y*pitch) + 3*(x*3);
-
-                                for y2 in 0 .. 3 {
-                                    let offset = offset + (y2 * pitch);
-
-                                    for x2 in 0 .. 3 {
-                                        let offset = offset + (x2 * 3);
-
-                                        buffer[offset]   = color.r;
-                                        buffer[offset+1] = color.g;
-                                        buffer[offset+2] = color.b;
-                                    }
-                                }
-                            }
-                        }
+                        post.apply(filtered, size, buffer, pitch);
                     }).unwrap();
 
+                    let screen = self.screen_rect(canvas.output_size().unwrap());
+
+                    canvas.set_draw_color(Color::RGB(0, 0, 0));
                     canvas.clear();
-                    canvas.copy(&texture, None, None).unwrap();
+                    canvas.copy(&texture, self.crop_rect(), screen).unwrap();
 
                     if *NES_PPU_DEBUG {
-                        ppu.render_tile_data(&mut canvas);
+                        ppu.render_tile_data(&mut canvas, palette);
                         ppu.render_tile_borders(&mut canvas);
                     }
 
@@ -369,13 +708,70 @@ impl Console {
                                 Keycode::F2 => { self.save() },
                                 Keycode::F3 => { self.load() },
 
+                                Keycode::F5 => {
+                                    self.cartridge.borrow_mut().notify(MapperEvent::SwitchDiskSide);
+                                },
+
                                 Keycode::F9 => { self.dump_chr() },
 
-                                Keycode::F12 => {
-                                    self.cpu.borrow_mut().reset();
-                                    self.apu.borrow_mut().reset();
+                                Keycode::F6 => { self.cycle_dip_switches() },
+
+                                Keycode::F7 => { self.cycle_palette() },
+
+                                Keycode::F8 => {
+                                    self.cycle_filter();
+                                    texture = new_texture(self);
+                                },
+
+                                Keycode::F10 => {
+                                    self.cycle_scaler();
+                                    texture = new_texture(self);
                                 },
 
+                                Keycode::F4 => { self.cycle_scanlines() },
+
+                                Keycode::F1 => {
+                                    self.post.aspect_correction = !self.post.aspect_correction;
+                                    self.fit_scale(desktop);
+                                    let (width, height) = self.window_size();
+                                    canvas.window_mut().set_size(width, height).unwrap();
+                                },
+
+                                Keycode::F11 => {
+                                    self.video.fullscreen = !self.video.fullscreen;
+                                    let fullscreen = if self.video.fullscreen {
+                                        FullscreenType::Desktop
+                                    } else {
+                                        FullscreenType::Off
+                                    };
+                                    canvas.window_mut().set_fullscreen(fullscreen).unwrap();
+                                    self.save_video_settings();
+                                },
+
+                                Keycode::Minus | Keycode::Equals => {
+                                    let scale = match key {
+                                        Keycode::Minus => self.video.scale - 1,
+                                        _              => self.video.scale + 1,
+                                    };
+                                    self.video.scale = scale.clamp(1, MAX_SCALE);
+                                    self.fit_scale(desktop);
+
+                                    let (width, height) = self.window_size();
+                                    canvas.window_mut().set_size(width, height).unwrap();
+                                    self.save_video_settings();
+                                },
+
+                                Keycode::O => {
+                                    self.cycle_overscan();
+                                    self.fit_scale(desktop);
+
+                                    let (width, height) = self.window_size();
+                                    canvas.window_mut().set_size(width, height).unwrap();
+                                    self.save_video_settings();
+                                },
+
+                                Keycode::F12 => { self.reset() },
+
                                 _ => {},
                             }
                         },
@@ -404,6 +800,8 @@ impl Console {
             }
         }
 
+        self.save_battery();
+
         info!("powering down");
     }
 }
@@ -64,6 +64,10 @@ pub struct CPU {
 
     // Total number of cycles executed
     cycles: u64,
+
+    // The cycle count at the start of the current instruction, for working
+    // out which cycle of it a write happens on.
+    instruction_start: u64,
 }
 
 impl Memory for CPU {
@@ -75,6 +79,9 @@ impl Memory for CPU {
         if addr == 0x4014 {
             self.dma(val);
         } else {
+            // The cycles for the whole instruction have already been counted,
+            // and writes happen on its last cycle.
+            self.mem.set_cycle_offset(self.cycles.saturating_sub(self.instruction_start + 1));
             self.mem.write(addr, val);
         }
     }
@@ -154,6 +161,7 @@ impl CPU {
 
             stall: None,
             cycles: 0,
+            instruction_start: 0,
         }
     }
 
@@ -278,6 +286,14 @@ impl CPU {
         self.pc = addr;
     }
 
+    // Read-modify-write instructions write the unmodified value back on the
+    // cycle before writing the result, which mappers like the MMC1 can see.
+    fn write_rmw(&mut self, addr: u16, val: u8, n: u8) {
+        self.mem.set_cycle_offset(self.cycles.saturating_sub(self.instruction_start + 2));
+        self.mem.write(addr, val);
+        self.write(addr, n);
+    }
+
     fn stack_push8(&mut self, val: u8) {
         // The stack page exists from 0x0100 to 0x01FF
         let addr = 0x0100 | (self.sp as u16);
@@ -347,6 +363,7 @@ impl CPU {
         }
 
         let start_cycles = self.cycles;
+        self.instruction_start = start_cycles;
 
         // Process pending interrupts.
         match self.interrupt {
@@ -420,7 +437,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
 
         self.update_sz(n);
@@ -556,7 +573,7 @@ impl CPU {
         let val = self.read(addr);
         let n = val.wrapping_sub(1);
         self.update_sz(n);
-        self.write(addr, n);
+        self.write_rmw(addr, val, n);
     }
 
     pub fn dex(&mut self) {
@@ -581,7 +598,7 @@ impl CPU {
     pub fn inc(&mut self, addr: u16) {
         let val = self.read(addr);
         let n = val.wrapping_add(1);
-        self.write(addr, n);
+        self.write_rmw(addr, val, n);
         self.update_sz(n);
     }
 
@@ -637,7 +654,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
     }
 
@@ -687,7 +704,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
     }
 
@@ -703,7 +720,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
     }
 
@@ -828,7 +845,7 @@ impl CPU {
         let val = self.read(addr);
         let n = val.wrapping_sub(1);
         self.update_sz(n);
-        self.write(addr, n);
+        self.write_rmw(addr, val, n);
 
         // Copied from cmp
         let n = self.a.wrapping_sub(n);
@@ -840,7 +857,7 @@ impl CPU {
         // Copied from inc
         let val = self.read(addr);
         let n = val.wrapping_add(1);
-        self.write(addr, n);
+        self.write_rmw(addr, val, n);
         self.update_sz(n);
 
         // Copied from sbc
@@ -864,7 +881,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
 
         self.update_sz(n);
@@ -886,7 +903,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
 
         // Copied from and
@@ -905,7 +922,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
 
         // Copied from eor
@@ -925,7 +942,7 @@ impl CPU {
 
         match *addr_mode {
             AddressingMode::Accumulator => { self.a = n; },
-            _ => { self.write(addr, n); }
+            _ => { self.write_rmw(addr, val, n); }
         };
 
         // Copied from adc
@@ -946,15 +963,11 @@ impl CPU {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ppu::PPU;
-    use crate::controller::Controller;
+    use crate::mem::NESMemory;
 
     #[test]
     fn test_stack_pop_empty() {
-        let ppu = PPU::new_nes_ppu();
-        let ctrl = Controller::new_controller();
-        let mem = NESMemory::new_nes_mem(ppu, ctrl);
-        let mut cpu = CPU::new_cpu(mem);
+        let mut cpu = CPU::new_cpu(Box::new(NESMemory::new_test_mem(vec![0; 0x8000])));
         let _ = cpu.stack_pop8();
         assert_eq!(cpu.sp, STACK_INIT + 1);
 
@@ -968,10 +981,7 @@ mod tests {
 
     #[test]
     fn test_stack_push_full() {
-        let ppu = PPU::new_nes_ppu();
-        let ctrl = Controller::new_controller();
-        let mem = NESMemory::new_nes_mem(ppu, ctrl);
-        let mut cpu = CPU::new_cpu(mem);
+        let mut cpu = CPU::new_cpu(Box::new(NESMemory::new_test_mem(vec![0; 0x8000])));
 
         for _ in 0 .. STACK_INIT {
             cpu.stack_push8(0xff);
@@ -986,19 +996,16 @@ mod tests {
 
     #[test]
     fn test_stack() {
-        let ppu = PPU::new_nes_ppu();
-        let ctrl = Controller::new_controller();
-        let mem = NESMemory::new_nes_mem(ppu, ctrl);
-        let mut cpu = CPU::new_cpu(mem);
+        let mut cpu = CPU::new_cpu(Box::new(NESMemory::new_test_mem(vec![0; 0x8000])));
 
         cpu.stack_push8(0xff);
         assert_eq!(cpu.sp, 0xfc);
-        assert_eq!(cpu.mem.ram[0x0100 + (cpu.sp as usize) + 1], 0xff);
+        assert_eq!(cpu.read(0x0100 + (cpu.sp as u16) + 1), 0xff);
 
         cpu.stack_push16(0xdead);
         assert_eq!(cpu.sp, 0xfa);
-        assert_eq!(cpu.mem.ram[0x100 + (cpu.sp as usize) + 1], 0xad);
-        assert_eq!(cpu.mem.ram[0x100 + (cpu.sp as usize) + 2], 0xde);
+        assert_eq!(cpu.read(0x0100 + (cpu.sp as u16) + 1), 0xad);
+        assert_eq!(cpu.read(0x0100 + (cpu.sp as u16) + 2), 0xde);
 
         let rv = cpu.stack_pop16();
         assert_eq!(cpu.sp, 0xfc);
@@ -1011,10 +1018,7 @@ mod tests {
 
     #[test]
     fn test_flags() {
-        let ppu = PPU::new_nes_ppu();
-        let ctrl = Controller::new_controller();
-        let mem = NESMemory::new_nes_mem(ppu, ctrl);
-        let mut cpu = CPU::new_cpu(mem);
+        let mut cpu = CPU::new_cpu(Box::new(NESMemory::new_test_mem(vec![0; 0x8000])));
 
         assert_eq!(cpu.flags(), 0x00);
 
@@ -1030,15 +1034,11 @@ mod tests {
 
     #[test]
     fn test_nmi() {
-        let ppu = PPU::new_nes_ppu();
-        let ctrl = Controller::new_controller();
-        let mem = NESMemory::new_nes_mem(ppu, ctrl);
-        let mut cpu = CPU::new_cpu(mem);
-
-        let mut rom = vec![0; 0xffff];
-        rom[0xfffa] = 0xad;
-        rom[0xfffb] = 0xde;
-        cpu.mem.load_rom(&rom);
+        let mut rom = vec![0; 0x8000];
+        rom[0x7ffa] = 0xad;
+        rom[0x7ffb] = 0xde;
+
+        let mut cpu = CPU::new_cpu(Box::new(NESMemory::new_test_mem(rom)));
         cpu.nmi();
         assert_eq!(cpu.pc, 0xdead);
         assert!(cpu.i);
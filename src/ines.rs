@@ -1,36 +1,84 @@
 use crate::mapper::Mapper;
-use crate::mapper::Mapper0;
-use crate::mapper::Mapper1;
-use crate::mapper::Mapper2;
-use crate::mapper::Mapper3;
-use crate::mapper::Mapper4;
-use crate::mapper::Mapper7;
-use crate::mapper::Mapper66;
-use crate::mapper::Mapper69;
+use crate::mapper::MapperRegistry;
+use crate::mapper::{FDS, DISK_SIDE_SIZE, FDS_MAGIC};
+use crate::console::NES_FDS_BIOS;
 
 use std::cell::RefCell;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::io;
 use std::rc::Rc;
 
 const INES_MAGIC: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
 
+// Headerless .fds images start straight away with the disk info block
+const FDS_DISK_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+
 #[derive(Debug)]
 pub enum CartridgeError {
     IO(io::Error),
     InvalidMagic,
+    MissingBIOS(io::Error),
+    InvalidDisk(usize),
     // InvalidZeroes,
     UnsupportedCartridge,
-    UnsupportedMapper(u8),
+    UnsupportedMapper(u16, u8),
+}
+
+// The contents of a cartridge, as read from the header and ROM data, and
+// handed to the mapper's constructor.
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mirror_mode: u8,
+    pub battery: bool,
+    pub submapper: u8,
+
+    // The amount of PRG-RAM at $6000-$7FFF, and CHR-RAM for boards without
+    // CHR-ROM, in bytes.
+    pub prg_ram_size: usize,
+    pub chr_ram_size: usize,
+}
+
+#[cfg(test)]
+impl Cartridge {
+    // A cartridge with the iNES defaults of 8KB of PRG-RAM, and 8KB of CHR-RAM
+    // if there's no CHR-ROM.
+    pub fn new_cartridge(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirror_mode: u8) -> Self {
+        let chr_ram_size = if chr_rom.len() == 0 { 0x2000 } else { 0 };
+
+        Self {
+            prg_rom: prg_rom,
+            chr_rom: chr_rom,
+            mirror_mode: mirror_mode,
+            battery: false,
+            submapper: 0,
+            prg_ram_size: 0x2000,
+            chr_ram_size: chr_ram_size,
+        }
+    }
 }
 
-pub fn load_file_into_memory(fh: &mut File)
+// NES 2.0 gives RAM sizes as a shift count in each nibble, for volatile and
+// battery-backed RAM, with 0 meaning none and n meaning 64 << n bytes.
+fn ram_size(shifts: u8) -> usize {
+    [shifts & 0x0f, shifts >> 4].iter()
+        .filter(|&&shift| shift != 0)
+        .map(|&shift| 64 << shift)
+        .sum()
+}
+
+pub fn load_file_into_memory(fh: &mut File, registry: &MapperRegistry)
     -> Result<Rc<RefCell<Box<dyn Mapper>>>, CartridgeError>
 {
     let mut header = [0; 16];
     let _ = fh.read(&mut header).map_err(CartridgeError::IO)?;
 
+    // FDS^Z, or the start of a disk
+    if header[0 .. 4] == FDS_MAGIC || header.starts_with(FDS_DISK_MAGIC) {
+        return load_disk_into_memory(fh, &header);
+    }
+
     // NES^Z
     let magic = &header[0 .. 4];
     if magic != INES_MAGIC {
@@ -51,15 +99,43 @@ pub fn load_file_into_memory(fh: &mut File)
     let battery_backed = (header[7] & 0x02) != 0;
     debug!("battery backed RAM: {}", if battery_backed { "yes" } else { "no" });
 
-    // Get the mapper
-    let mapper_low =  (header[6] & 0xf0) >> 4;
-    let mapper_high = (header[7] & 0xf0) >> 4;
-    let mapper = (mapper_high << 4) | mapper_low;
-    debug!("mapper: {}", mapper);
+    // NES 2.0 headers are flagged by bits 2-3 of byte 7 being 10, and reuse
+    // byte 8 for another four bits of mapper number, and the submapper, which
+    // picks between board variants that share a mapper number.
+    let nes2 = (header[7] & 0x0c) == 0x08;
+    debug!("NES 2.0: {}", if nes2 { "yes" } else { "no" });
 
-    // Get the number of 8KB RAM banks
-    let n_ram_banks = header[8];
-    debug!("8KB RAM banks: {}", n_ram_banks);
+    // Get the mapper
+    let mapper_low =  ((header[6] & 0xf0) >> 4) as u16;
+    let mapper_mid =  ((header[7] & 0xf0) >> 4) as u16;
+    let mapper_high = if nes2 { (header[8] & 0x0f) as u16 } else { 0 };
+    let mapper = (mapper_high << 8) | (mapper_mid << 4) | mapper_low;
+    let submapper = if nes2 { header[8] >> 4 } else { 0 };
+    debug!("mapper: {}, submapper: {}", mapper, submapper);
+
+    // Find the board before reading any ROM data, so unsupported ones fail
+    // early.
+    let entry = registry.find(mapper, submapper)
+        .ok_or(CartridgeError::UnsupportedMapper(mapper, submapper))?;
+    info!("board: {}", entry.name);
+
+    // Get the amount of PRG-RAM. iNES gives it in 8KB banks, with 0 meaning
+    // 8KB for compatibility with older headers, and NES 2.0 has separate
+    // fields for PRG-RAM and CHR-RAM.
+    let (prg_ram_size, mut chr_ram_size) = if nes2 {
+        (ram_size(header[10]), ram_size(header[11]))
+    } else {
+        (std::cmp::max(1, header[8] as usize) * 8 * 1024, 8 * 1024)
+    };
+
+    // Boards without CHR-ROM always have CHR-RAM, even if the header forgot
+    // to mention it.
+    if n_vrom_banks > 0 {
+        chr_ram_size = 0;
+    } else if chr_ram_size == 0 {
+        chr_ram_size = 8 * 1024;
+    }
+    debug!("PRG-RAM: {} bytes, CHR-RAM: {} bytes", prg_ram_size, chr_ram_size);
 
     // Get the cartridge type, 1 for PAL, anything else means NTSC
     let cartridge_type = header[9] >> 7;
@@ -68,9 +144,9 @@ pub fn load_file_into_memory(fh: &mut File)
         return Err(CartridgeError::UnsupportedCartridge);
     }
 
-    // Reserved bytes, must all be zeroes
+    // Reserved bytes in iNES, must all be zeroes
     let zeroes = &header[10 .. 16];
-    if zeroes != [0, 0, 0, 0, 0, 0] {
+    if !nes2 && zeroes != [0, 0, 0, 0, 0, 0] {
         warn!("Header section should be full of zeroes, but contains {:?}",
               zeroes);
 
@@ -97,20 +173,46 @@ pub fn load_file_into_memory(fh: &mut File)
         debug!("read {} banks ({} bytes) of 8KB CHR-ROM data", n_vrom_banks, bytes);
     }
 
-    if n_vrom_banks == 0 {
-        vrom = vec![0; 8192];
-        debug!("making 8KB of CHR-RAM");
-    }
+    let cartridge = Cartridge {
+        prg_rom: rom,
+        chr_rom: vrom,
+        mirror_mode: mirror_mode,
+        battery: battery_backed,
+        submapper: submapper,
+        prg_ram_size: prg_ram_size,
+        chr_ram_size: chr_ram_size,
+    };
+
+    Ok(Rc::new(RefCell::new((entry.new)(cartridge))))
+}
 
-    match mapper {
-        0 => Ok(Rc::new(RefCell::new(Box::new(Mapper0::new_mapper(rom, vrom, mirror_mode))))),
-        1 => Ok(Rc::new(RefCell::new(Box::new(Mapper1::new_mapper(rom, vrom, mirror_mode))))),
-        2 => Ok(Rc::new(RefCell::new(Box::new(Mapper2::new_mapper(rom, vrom, mirror_mode))))),
-        3 => Ok(Rc::new(RefCell::new(Box::new(Mapper3::new_mapper(rom, vrom, mirror_mode))))),
-        4 => Ok(Rc::new(RefCell::new(Box::new(Mapper4::new_mapper(rom, vrom, mirror_mode))))),
-        7 => Ok(Rc::new(RefCell::new(Box::new(Mapper7::new_mapper(rom, vrom, mirror_mode))))),
-        66 => Ok(Rc::new(RefCell::new(Box::new(Mapper66::new_mapper(rom, vrom, mirror_mode))))),
-        69 => Ok(Rc::new(RefCell::new(Box::new(Mapper69::new_mapper(rom, vrom, mirror_mode))))),
-        _ => Err(CartridgeError::UnsupportedMapper(mapper)),
+fn load_disk_into_memory(fh: &mut File, header: &[u8; 16])
+    -> Result<Rc<RefCell<Box<dyn Mapper>>>, CartridgeError>
+{
+    // The fwNES header is optional, so only skip over it if it's there
+    let offset = if header[0 .. 4] == FDS_MAGIC { 16 } else { 0 };
+    fh.seek(SeekFrom::Start(offset)).map_err(CartridgeError::IO)?;
+
+    let mut data = vec![];
+    fh.read_to_end(&mut data).map_err(CartridgeError::IO)?;
+
+    // Each side of the disk is a fixed size, so anything else is a truncated
+    // or corrupt image.
+    if data.is_empty() || data.len() % DISK_SIDE_SIZE != 0 {
+        return Err(CartridgeError::InvalidDisk(data.len()));
     }
+
+    let sides: Vec<Vec<u8>> = data.chunks(DISK_SIDE_SIZE)
+        .map(|side| side.to_vec())
+        .collect();
+    debug!("disk sides: {}", sides.len());
+
+    // The disk system's BIOS isn't included in disk images, so has to be
+    // provided separately.
+    let mut bios = vec![0; 8192];
+    let mut bios_fh = File::open(&*NES_FDS_BIOS).map_err(CartridgeError::MissingBIOS)?;
+    bios_fh.read_exact(&mut bios).map_err(CartridgeError::MissingBIOS)?;
+    debug!("read 8KB of FDS BIOS from {}", *NES_FDS_BIOS);
+
+    Ok(Rc::new(RefCell::new(Box::new(FDS::new_mapper(bios, sides)))))
 }
@@ -0,0 +1,19 @@
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+
+mod apu;
+mod console;
+mod controller;
+mod cpu;
+mod mapper;
+mod mem;
+mod ines;
+mod ppu;
+mod palette;
+mod serde;
+mod settings;
+
+pub use crate::console::{Console, NES_FDS_BIOS};
+pub use crate::ines::{Cartridge, CartridgeError};
+pub use crate::mapper::{Mapper, MapperConstructor, MapperEntry, MapperEvent, MapperRegistry};
+pub use crate::mapper::{MirrorMode, DISK_SIDE_SIZE};
@@ -1,28 +1,25 @@
-#[macro_use] extern crate log;
-#[macro_use] extern crate lazy_static;
-
-mod apu;
-mod console;
-mod controller;
-mod cpu;
-mod mapper;
-mod mem;
-mod ines;
-mod ppu;
-mod palette;
-mod serde;
-
 use std::env;
 use std::process;
 
-use crate::console::Console;
-use crate::ines::CartridgeError;
+use nes::{CartridgeError, Console, MapperRegistry, DISK_SIDE_SIZE, NES_FDS_BIOS};
 
 fn main() {
     env_logger::init();
 
-    if let Some(rom) = env::args().skip(1).next() {
-        match Console::new_nes_console(&rom) {
+    let registry = MapperRegistry::new_default_registry();
+
+    if let Some(rom) = env::args().nth(1) {
+        if rom == "--list-mappers" {
+            for entry in registry.entries() {
+                match entry.submapper {
+                    Some(submapper) => println!("{}.{}\t{}", entry.mapper, submapper, entry.name),
+                    None            => println!("{}\t{}", entry.mapper, entry.name),
+                }
+            }
+            return;
+        }
+
+        match Console::new_nes_console(&rom, &registry) {
             Ok(mut console) => {
                 console.power_up();
             },
@@ -31,17 +28,29 @@ fn main() {
                 process::exit(1);
             },
             Err(CartridgeError::InvalidMagic) => {
-                println!("File {} is invalid. Expected iNES formatted ROM.", rom);
+                println!("File {} is invalid. Expected iNES formatted ROM, or FDS disk image.", rom);
+                process::exit(1);
+            },
+            Err(CartridgeError::MissingBIOS(io_e)) => {
+                println!("There was an error reading the FDS BIOS from {}: {}", *NES_FDS_BIOS, io_e);
+                process::exit(1);
+            },
+            Err(CartridgeError::InvalidDisk(size)) => {
+                println!("Disk image {} is invalid. Expected sides of {} bytes, but read {} bytes.", rom, DISK_SIDE_SIZE, size);
                 process::exit(1);
             },
             Err(CartridgeError::UnsupportedCartridge) => {
                 println!("Unsupported cartridge type. Only supports NTSC for now.");
                 process::exit(1);
             },
-            Err(CartridgeError::UnsupportedMapper(m)) => {
+            Err(CartridgeError::UnsupportedMapper(m, 0)) => {
                 println!("Unsupported mapper type: {}", m);
                 process::exit(1);
             },
+            Err(CartridgeError::UnsupportedMapper(m, s)) => {
+                println!("Unsupported mapper type: {} (submapper {})", m, s);
+                process::exit(1);
+            },
         }
     } else {
         println!("Missing required parameter: a path to a ROM file.");
@@ -4,20 +4,55 @@ mod mapper2;
 mod mapper3;
 mod mapper4;
 mod mapper7;
+mod mapper11;
+mod mapper13;
+mod mapper15;
+mod mapper19;
+mod mapper34;
 mod mapper66;
 mod mapper69;
+mod mapper71;
+mod mapper79;
+mod mapper87;
+mod mapper140;
+mod mapper180;
+mod mapper206;
+mod mapper225;
+mod mapper226;
+mod mapper227;
+mod mapper228;
+mod fds;
+mod banks;
+mod registry;
 
 use std::io;
 use std::fs::File;
 
 pub use mapper0::Mapper0;
-pub use mapper1::Mapper1;
+pub use mapper1::{Mapper1, MMC1Board};
 pub use mapper2::Mapper2;
 pub use mapper3::Mapper3;
-pub use mapper4::Mapper4;
+pub use mapper4::{Mapper4, MMC3Board};
 pub use mapper7::Mapper7;
+pub use mapper11::Mapper11;
+pub use mapper13::Mapper13;
+pub use mapper15::Mapper15;
+pub use mapper19::Mapper19;
+pub use mapper34::{Mapper34, Mapper34Board};
 pub use mapper66::Mapper66;
 pub use mapper69::Mapper69;
+pub use mapper71::Mapper71;
+pub use mapper79::Mapper79;
+pub use mapper87::Mapper87;
+pub use mapper140::Mapper140;
+pub use mapper180::Mapper180;
+pub use mapper206::Mapper206;
+pub use mapper225::Mapper225;
+pub use mapper226::Mapper226;
+pub use mapper227::Mapper227;
+pub use mapper228::Mapper228;
+pub use fds::{FDS, DISK_SIDE_SIZE, FDS_MAGIC};
+pub use registry::{MapperConstructor, MapperEntry, MapperRegistry};
 
 #[derive(Clone, Copy)]
 pub enum MirrorMode {
@@ -64,18 +99,44 @@ impl MirrorMode {
 
 pub enum MapperEvent {
     CPUTick(u64),
-    HBlank,
     VRAMAddressChange(u16),
+    SwitchDiskSide,
+
+    // The console being switched on, or the reset button being pressed
+    PowerOn,
+    Reset,
+
+    // Every dot the PPU runs, and every access it makes to its bus outside of
+    // the palette, including the pattern fetches made while rendering.
+    PPUTick,
+    PPURead(u16),
+    #[allow(dead_code)]
+    PPUWrite(u16, u8),
 }
 
 pub trait Mapper {
     // The mirroring mode to use
     fn mirror_mode(&self) -> &MirrorMode { &MirrorMode::Vertical }
 
-    // Memory read/write
-    fn read(&mut self, address: u16) -> u8;
+    // Memory read/write. This covers the pattern tables at $0000-$1FFF, and
+    // everything the CPU can see of the cartridge, starting from the
+    // expansion area at $4020-$5FFF. Reads give None where nothing on the
+    // board drives the data bus, leaving it open.
+    fn read(&mut self, address: u16) -> Option<u8>;
     fn write(&mut self, address: u16, val: u8);
 
+    // Which 1KB page of the console's nametable RAM each of the four
+    // nametables uses. Pages 2 and 3 only exist with four-screen VRAM.
+    fn nametable_page(&self, table: usize) -> usize {
+        self.mirror_mode().coefficients()[table]
+    }
+
+    // Nametable reads and writes. Most cartridges leave the nametables to the
+    // console's internal VRAM, using the mirroring mode above, but some map
+    // CHR-ROM or their own RAM into the nametables, and handle them here.
+    fn read_nametable(&mut self, _address: u16) -> Option<u8> { None }
+    fn write_nametable(&mut self, _address: u16, _val: u8) -> bool { false }
+
     // Called after every PPU execution, to determine whether or not an
     // interrupt should be raised.
     fn irq_flag(&self) -> bool { false }
@@ -83,6 +144,20 @@ pub trait Mapper {
     // Called on particular events, resulting in an observer-like pattern.
     fn notify(&mut self, _event: MapperEvent) { }
 
+    // Expansion audio, which gets mixed in with the APU's output
+    fn signal(&self) -> f32 { 0.0 }
+
+    // DIP switches or solder pads on the board, which are set per ROM. The
+    // number of settings is how many different values the switches can take.
+    fn dip_switches(&self) -> u8 { 0 }
+    fn dip_switch_settings(&self) -> u8 { 1 }
+    fn set_dip_switches(&mut self, _val: u8) { }
+
+    // Battery-backed RAM, which is persisted between runs
+    fn has_battery(&self) -> bool { false }
+    fn save_battery(&self, _output: &mut File) -> io::Result<()> { Ok(()) }
+    fn load_battery(&mut self, _input: &mut File) -> io::Result<()> { Ok(()) }
+
     // Serialisation and deserialisation to save states
     fn save(&self, output: &mut File) -> io::Result<()>;
     fn load(&mut self, input: &mut File) -> io::Result<()>;
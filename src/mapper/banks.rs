@@ -0,0 +1,264 @@
+use std::io;
+use std::fs::File;
+
+use crate::serde;
+
+// Marks an unmapped page in save states
+const UNMAPPED: usize = usize::MAX;
+
+//
+// A window of the CPU or PPU address space that's switched between banks of
+// ROM or RAM.
+//
+// The window is split up into pages of the smallest bank size that the board
+// switches, and each page holds the offset into the data that it points at.
+// Switching a bank just rewrites the pages it covers, so reads and writes
+// don't need to work out which register applies to the address.
+//
+pub struct Banks {
+    data: Vec<u8>,
+
+    // The first address of the window
+    base: usize,
+    page_size: usize,
+    pages: Vec<Option<usize>>,
+
+    // RAM is saved along with the page table, ROM only needs the page table.
+    ram: bool,
+
+    // RAM can be write-protected, ROM is never writable.
+    writable: bool,
+}
+
+impl Banks {
+    pub fn new_rom(data: Vec<u8>, base: u16, size: usize, page_size: usize) -> Self {
+        Self::new_banks(data, false, base, size, page_size)
+    }
+
+    pub fn new_ram(data: Vec<u8>, base: u16, size: usize, page_size: usize) -> Self {
+        Self::new_banks(data, true, base, size, page_size)
+    }
+
+    // Pattern tables are CHR-ROM, or CHR-RAM when the cartridge has none.
+    pub fn new_chr(chr_rom: Vec<u8>, chr_ram_size: usize, page_size: usize) -> Self {
+        if chr_rom.len() == 0 {
+            Self::new_ram(vec![0; chr_ram_size], 0x0000, 0x2000, page_size)
+        } else {
+            Self::new_rom(chr_rom, 0x0000, 0x2000, page_size)
+        }
+    }
+
+    fn new_banks(data: Vec<u8>, ram: bool, base: u16, size: usize, page_size: usize) -> Self {
+        let mut banks = Self {
+            data: data,
+            base: base as usize,
+            page_size: page_size,
+            pages: vec![None; size / page_size],
+            ram: ram,
+            writable: ram,
+        };
+
+        // Until the board says otherwise, the data is mapped in order and
+        // mirrored across the whole window.
+        banks.map(base, size, 0);
+        banks
+    }
+
+    // Points the window of the given size at an address to a bank. Banks past
+    // the end of the data wrap around, as the unused upper lines of the bank
+    // registers aren't connected to anything.
+    pub fn map(&mut self, address: u16, size: usize, bank: usize) {
+        if self.data.len() == 0 {
+            return self.unmap(address, size);
+        }
+
+        let first = match self.first_page(address) {
+            Some(first) => first,
+            None => return,
+        };
+        let offset = bank * size;
+
+        for i in 0 .. size / self.page_size {
+            let page = (offset + i * self.page_size) % self.data.len();
+            self.pages[first + i] = Some(page);
+        }
+    }
+
+    // Disconnects a window, so that reads from it give open bus
+    pub fn unmap(&mut self, address: u16, size: usize) {
+        let first = match self.first_page(address) {
+            Some(first) => first,
+            None => return,
+        };
+
+        for i in 0 .. size / self.page_size {
+            self.pages[first + i] = None;
+        }
+    }
+
+    // The page a window starts at. Addresses below the window are a bug in the
+    // board, and are ignored outside of debug builds.
+    fn first_page(&self, address: u16) -> Option<usize> {
+        debug_assert!(address as usize >= self.base,
+                      "${:04X} is below the window at ${:04X}", address, self.base);
+
+        (address as usize).checked_sub(self.base).map(|offset| offset / self.page_size)
+    }
+
+    // The number of banks of the given size, counting a partial bank as a
+    // whole one.
+    pub fn n_banks(&self, size: usize) -> usize {
+        std::cmp::max(1, (self.data.len() + size - 1) / size)
+    }
+
+    pub fn last_bank(&self, size: usize) -> usize {
+        self.n_banks(size) - 1
+    }
+
+    pub fn set_writable(&mut self, writable: bool) {
+        self.writable = self.ram && writable;
+    }
+
+    pub fn is_mapped(&self, address: u16) -> bool {
+        self.index(address).is_some()
+    }
+
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+
+    fn index(&self, address: u16) -> Option<usize> {
+        let offset = (address as usize).checked_sub(self.base)?;
+        let page = self.pages.get(offset / self.page_size)?;
+
+        // Pages can be larger than the data, like 16KB of PRG-ROM mirrored
+        // through a 32KB window.
+        page.map(|p| (p + offset % self.page_size) % self.data.len())
+    }
+
+    // Nothing drives the data bus for unmapped pages, so there's no value,
+    // and the CPU sees whatever was last on the bus instead.
+    pub fn read(&self, address: u16) -> Option<u8> {
+        self.index(address).map(|index| self.data[index])
+    }
+
+    pub fn write(&mut self, address: u16, val: u8) {
+        if !self.writable {
+            return;
+        }
+
+        if let Some(index) = self.index(address) {
+            self.data[index] = val;
+        }
+    }
+
+    pub fn save(&self, output: &mut File) -> io::Result<()> {
+        for page in self.pages.iter() {
+            serde::encode_usize(output, page.unwrap_or(UNMAPPED))?;
+        }
+
+        serde::encode_u8(output, self.writable as u8)?;
+
+        if self.ram {
+            serde::encode_vec(output, &self.data)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load(&mut self, input: &mut File) -> io::Result<()> {
+        for i in 0 .. self.pages.len() {
+            self.pages[i] = match serde::decode_usize(input)? {
+                UNMAPPED => None,
+                page     => Some(page),
+            };
+        }
+
+        self.writable = serde::decode_u8(input)? != 0;
+
+        if self.ram {
+            self.data = serde::decode_vec(input)?;
+        }
+
+        Ok(())
+    }
+}
+
+// ROM for tests, made up of n banks of the given size, where every byte holds
+// the number of its bank. Reads then show which bank is mapped.
+#[cfg(test)]
+pub(crate) fn banked(n: usize, size: usize) -> Vec<u8> {
+    (0 .. n).flat_map(|bank| vec![bank as u8; size]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map() {
+        let mut prg = Banks::new_rom(banked(4, 0x2000), 0x8000, 0x8000, 0x2000);
+        assert_eq!(prg.read(0x8000), Some(0));
+        assert_eq!(prg.read(0xffff), Some(3));
+
+        prg.map(0x8000, 0x4000, 1);
+        assert_eq!(prg.read(0x8000), Some(2));
+        assert_eq!(prg.read(0xa000), Some(3));
+
+        // Banks past the end wrap around
+        prg.map(0xe000, 0x2000, 5);
+        assert_eq!(prg.read(0xe000), Some(1));
+        assert_eq!(prg.last_bank(0x2000), 3);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let prg = Banks::new_rom(banked(1, 0x4000), 0x8000, 0x8000, 0x4000);
+        assert_eq!(prg.read(0xc000), Some(0));
+        assert_eq!(prg.n_banks(0x8000), 1);
+
+        let mut prg = Banks::new_rom(banked(2, 0x2000), 0x8000, 0x8000, 0x8000);
+        assert_eq!(prg.read(0xa000), Some(1));
+        assert_eq!(prg.read(0xc000), Some(0));
+
+        prg.map(0x8000, 0x8000, 1);
+        assert_eq!(prg.read(0xe000), Some(1));
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut sram = Banks::new_ram(vec![], 0x6000, 0x2000, 0x2000);
+        sram.write(0x6000, 1);
+        assert_eq!(sram.read(0x6123), None);
+
+        let mut prg = Banks::new_rom(banked(2, 0x4000), 0x8000, 0x8000, 0x4000);
+        prg.unmap(0xc000, 0x4000);
+        assert_eq!(prg.read(0xc000), None);
+        assert!(!prg.is_mapped(0xc000));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut chr = Banks::new_rom(vec![0; 0x2000], 0x0000, 0x2000, 0x0400);
+        chr.write(0x0000, 1);
+        assert_eq!(chr.read(0x0000), Some(0));
+
+        let mut sram = Banks::new_ram(vec![0; 0x2000], 0x6000, 0x2000, 0x2000);
+        sram.write(0x6000, 1);
+        assert_eq!(sram.read(0x6000), Some(1));
+
+        sram.set_writable(false);
+        sram.write(0x6000, 2);
+        assert_eq!(sram.read(0x6000), Some(1));
+    }
+    #[test]
+    #[should_panic(expected = "below the window")]
+    fn test_map_below_window() {
+        let mut prg = Banks::new_rom(banked(2, 0x4000), 0x8000, 0x8000, 0x4000);
+        prg.map(0x6000, 0x4000, 1);
+    }
+}
@@ -0,0 +1,790 @@
+mod audio;
+
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mapper::{Mapper, MapperEvent};
+use crate::mapper::MirrorMode;
+use crate::mapper::banks::Banks;
+use crate::mapper::fds::audio::FDSAudio;
+use crate::mem::Memory;
+use crate::serde;
+
+// The size of a single disk side in an .fds image, without gaps or CRCs
+pub const DISK_SIDE_SIZE: usize = 65500;
+
+// fwNES .fds header magic
+pub const FDS_MAGIC: [u8; 4] = [0x46, 0x44, 0x53, 0x1a];
+
+// The gap at the start of each disk side is 28300 bits long, and the gaps
+// between blocks are at least 976 bits long.
+const LEAD_IN_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+
+// The disk transfers a byte roughly every 150 CPU cycles (96.4kHz / 8), and
+// takes a while to spin back up to the start once it reaches the end.
+const BYTE_TRANSFER_CYCLES: u32 = 150;
+const REWIND_CYCLES: u32 = 50000;
+
+// How long the disk stays ejected when switching sides, about a second
+const DISK_SWITCH_CYCLES: u32 = 1_789_773;
+
+// Converts an .fds disk side into the raw stream of bytes that the drive
+// reads, with the gaps, gap-end markers and (fake) CRCs that the BIOS
+// expects to see.
+fn side_to_raw(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEAD_IN_GAP];
+    let mut pos = 0;
+    let mut file_size = 0;
+
+    while pos < side.len() {
+        let len = match side[pos] {
+            1 => 56,
+            2 => 2,
+            3 => {
+                if pos + 15 <= side.len() {
+                    file_size = side[pos + 13] as usize | ((side[pos + 14] as usize) << 8);
+                }
+                16
+            },
+            4 => 1 + file_size,
+            _ => break,
+        };
+
+        if pos + len > side.len() {
+            break;
+        }
+
+        // The gap ends with a single 1 bit, then the block and its CRC
+        raw.push(0x80);
+        raw.extend_from_slice(&side[pos .. pos + len]);
+        raw.extend_from_slice(&[0x4d, 0x62]);
+        raw.extend_from_slice(&[0; BLOCK_GAP]);
+
+        pos += len;
+    }
+
+    // Pad the rest of the disk out, as the drive keeps reading zeroes until
+    // it reaches the end.
+    let size = raw.len().max(DISK_SIDE_SIZE + LEAD_IN_GAP);
+    raw.resize(size, 0);
+
+    raw
+}
+
+// The reverse of the above, which is used when writing modified disks back
+// out to an .fds image.
+fn raw_to_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(DISK_SIDE_SIZE);
+    let mut pos = 0;
+    let mut file_size = 0;
+
+    loop {
+        // Skip the gap
+        while pos < raw.len() && raw[pos] == 0 {
+            pos += 1;
+        }
+
+        if pos >= raw.len() || raw[pos] != 0x80 {
+            break;
+        }
+
+        pos += 1;
+        if pos >= raw.len() {
+            break;
+        }
+
+        let len = match raw[pos] {
+            1 => 56,
+            2 => 2,
+            3 => {
+                if pos + 15 <= raw.len() {
+                    file_size = raw[pos + 13] as usize | ((raw[pos + 14] as usize) << 8);
+                }
+                16
+            },
+            4 => 1 + file_size,
+            _ => break,
+        };
+
+        if pos + len > raw.len() {
+            break;
+        }
+
+        side.extend_from_slice(&raw[pos .. pos + len]);
+
+        // Skip the CRC
+        pos += len + 2;
+    }
+
+    side.resize(DISK_SIDE_SIZE, 0);
+    side
+}
+
+//
+// Famicom Disk System
+//
+// https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+//
+pub struct FDS {
+    bios: Banks,
+    prg_ram: Banks,
+    chr_ram: Banks,
+    mirror_mode: MirrorMode,
+
+    // Each disk side, as the raw stream of bytes seen by the drive
+    sides: Vec<Vec<u8>>,
+    side: usize,
+
+    // While switching sides, the disk is ejected for a little while
+    eject_cycles: u32,
+
+    // $4023 master I/O enable
+    disk_io_enabled: bool,
+
+    // Timer IRQ
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+
+    // Disk drive
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    gap_scan: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    transfer_complete: bool,
+    read_data: u8,
+    write_data: u8,
+
+    audio: FDSAudio,
+}
+
+impl FDS {
+    pub fn new_mapper(bios: Vec<u8>, sides: Vec<Vec<u8>>) -> Self {
+        let sides = sides.iter().map(|s| side_to_raw(s)).collect();
+
+        Self {
+            bios: Banks::new_rom(bios, 0xe000, 0x2000, 0x2000),
+            prg_ram: Banks::new_ram(vec![0; 0x8000], 0x6000, 0x8000, 0x8000),
+            chr_ram: Banks::new_ram(vec![0; 0x2000], 0x0000, 0x2000, 0x2000),
+            mirror_mode: MirrorMode::Horizontal,
+
+            sides: sides,
+            side: 0,
+
+            eject_cycles: 0,
+
+            disk_io_enabled: false,
+
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            gap_scan: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            transfer_complete: false,
+            read_data: 0,
+            write_data: 0,
+
+            audio: FDSAudio::new_audio(),
+        }
+    }
+
+    fn disk_inserted(&self) -> bool {
+        self.eject_cycles == 0 && !self.sides.is_empty()
+    }
+
+    fn switch_side(&mut self) {
+        if self.sides.is_empty() {
+            return;
+        }
+
+        self.side = (self.side + 1) % self.sides.len();
+        self.eject_cycles = DISK_SWITCH_CYCLES;
+
+        info!("inserting disk {} side {}",
+              self.side / 2 + 1,
+              if self.side % 2 == 0 { "A" } else { "B" });
+    }
+
+    fn step_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn step_drive(&mut self) {
+        if self.eject_cycles > 0 {
+            self.eject_cycles -= 1;
+        }
+
+        if !self.disk_inserted() || !self.motor_on {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+
+        if self.end_of_head {
+            // Rewind back to the start of the disk
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+
+        let mut need_irq = self.disk_irq_enabled;
+
+        if self.read_mode {
+            let data = self.sides[self.side][self.position];
+
+            if !self.gap_scan {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                // This is the gap-end marker, which isn't transferred
+                self.gap_ended = true;
+                need_irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+
+                if need_irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut data = 0;
+
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+
+                if need_irq {
+                    self.disk_irq = true;
+                }
+            }
+
+            // Outside of a block, the drive writes the gap. The CRC isn't
+            // checked when the disk is read back, so it's written as zeroes.
+            if !self.gap_scan || self.crc_control {
+                data = 0;
+            }
+
+            self.sides[self.side][self.position] = data;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+
+        if self.position >= self.sides[self.side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_TRANSFER_CYCLES;
+        }
+    }
+
+    fn read_register(&mut self, address: u16) -> u8 {
+        match address {
+            0x4030 => {
+                // 7  bit  0
+                // ---- ----
+                // .... ..DT
+                //        ||
+                //        |+- Timer IRQ occurred
+                //        +-- Byte transfer complete
+                //
+                // Reading this register acknowledges both IRQs
+                let val = (self.timer_irq as u8)
+                    | ((self.transfer_complete as u8) << 1);
+
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+
+                val
+            },
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            },
+            0x4032 => {
+                // 7  bit  0
+                // ---- ----
+                // .... .PRS
+                //       |||
+                //       ||+- Disk not inserted
+                //       |+-- Disk not ready
+                //       +--- Disk write protected
+                let inserted = self.disk_inserted();
+
+                0x40
+                    | (!inserted as u8)
+                    | (((!inserted || !self.scanning) as u8) << 1)
+                    | ((!inserted as u8) << 2)
+            },
+
+            // External connector. Bit 7 is the battery status, which is good.
+            0x4033 => 0x80,
+
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, val: u8) {
+        match address {
+            // Timer IRQ reload value
+            0x4020 => { self.irq_reload = (self.irq_reload & 0xff00) | val as u16 },
+            0x4021 => { self.irq_reload = (self.irq_reload & 0x00ff) | ((val as u16) << 8) },
+
+            0x4022 => {
+                // 7  bit  0
+                // ---- ----
+                // .... ..ER
+                //        ||
+                //        |+- Timer IRQ repeat
+                //        +-- Timer IRQ enabled
+                self.irq_repeat  = (val & 0b0000_0001) != 0;
+                self.irq_enabled = (val & 0b0000_0010) != 0 && self.disk_io_enabled;
+
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            },
+
+            0x4023 => {
+                // 7  bit  0
+                // ---- ----
+                // .... ..SD
+                //        ||
+                //        |+- Enable disk I/O registers
+                //        +-- Enable sound I/O registers
+                self.disk_io_enabled = (val & 0b0000_0001) != 0;
+                self.audio.enabled   = (val & 0b0000_0010) != 0;
+
+                if !self.disk_io_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            },
+
+            // Write data register
+            0x4024 => {
+                self.write_data = val;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            },
+
+            0x4025 => {
+                // 7  bit  0
+                // ---- ----
+                // IS.C MRTD
+                // || | ||||
+                // || | |||+- Drive motor on
+                // || | ||+-- Transfer reset
+                // || | |+--- Transfer mode (0: write, 1: read)
+                // || | +---- Mirroring (0: vertical, 1: horizontal)
+                // || +------ CRC control
+                // |+-------- Start transferring once the gap ends
+                // +--------- Raise an IRQ on every byte transferred
+                self.motor_on         = (val & 0b0000_0001) != 0;
+                self.reset_transfer   = (val & 0b0000_0010) != 0;
+                self.read_mode        = (val & 0b0000_0100) != 0;
+                self.mirror_mode      = MirrorMode::from_vh01((val >> 3) & 1);
+                self.crc_control      = (val & 0b0001_0000) != 0;
+                self.gap_scan         = (val & 0b0100_0000) != 0;
+                self.disk_irq_enabled = (val & 0b1000_0000) != 0;
+
+                self.disk_irq = false;
+            },
+
+            _ => { },
+        }
+    }
+}
+
+impl Mapper for FDS {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr_ram.read(address),
+
+            // Disk registers
+            0x4030 ..= 0x4033 => {
+                if self.disk_io_enabled {
+                    Some(self.read_register(address))
+                } else {
+                    None
+                }
+            },
+
+            // Sound registers
+            0x4040 ..= 0x4092 => Some(self.audio.read(address)),
+
+            // PRG-RAM
+            0x6000 ..= 0xdfff => self.prg_ram.read(address),
+
+            // BIOS
+            0xe000 ..= 0xffff => self.bios.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr_ram.write(address, val),
+
+            // Timer IRQ and master I/O enable
+            0x4020 ..= 0x4023 => self.write_register(address, val),
+
+            // Disk registers
+            0x4024 ..= 0x4026 => {
+                if self.disk_io_enabled {
+                    self.write_register(address, val);
+                }
+            },
+
+            // Sound registers
+            0x4040 ..= 0x408a => {
+                if self.audio.enabled {
+                    self.audio.write(address, val);
+                }
+            },
+
+            // PRG-RAM
+            0x6000 ..= 0xdfff => self.prg_ram.write(address, val),
+
+            _ => { },
+        }
+    }
+
+    fn irq_flag(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::CPUTick(cycles) => {
+                for _ in 0 .. cycles {
+                    self.step_timer();
+                    self.step_drive();
+                    self.audio.step();
+                }
+            },
+            MapperEvent::SwitchDiskSide => { self.switch_side() },
+            _ => { },
+        }
+    }
+
+    fn signal(&self) -> f32 {
+        self.audio.signal()
+    }
+
+    // The disk itself is the "battery-backed" RAM, so any changes made to it
+    // are written out to a separate .fds image, leaving the original intact.
+    fn has_battery(&self) -> bool {
+        true
+    }
+
+    fn save_battery(&self, output: &mut File) -> io::Result<()> {
+        let mut header = [0; 16];
+        header[0 .. 4].copy_from_slice(&FDS_MAGIC);
+        header[4] = self.sides.len() as u8;
+        output.write_all(&header)?;
+
+        for side in self.sides.iter() {
+            output.write_all(&raw_to_side(side))?;
+        }
+
+        Ok(())
+    }
+
+    fn load_battery(&mut self, input: &mut File) -> io::Result<()> {
+        let mut header = [0; 16];
+        input.read_exact(&mut header)?;
+
+        if header[0 .. 4] != FDS_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad .fds header"));
+        }
+
+        if header[4] as usize != self.sides.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "wrong number of disk sides"));
+        }
+
+        let mut sides = vec![];
+        for _ in 0 .. header[4] {
+            let mut side = vec![0; DISK_SIDE_SIZE];
+            input.read_exact(&mut side)?;
+            sides.push(side_to_raw(&side));
+        }
+
+        self.sides = sides;
+        self.side = 0;
+        Ok(())
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.prg_ram.save(output)?;
+        self.chr_ram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+
+        serde::encode_usize(output, self.sides.len())?;
+        for side in self.sides.iter() {
+            serde::encode_vec(output, side)?;
+        }
+        serde::encode_usize(output, self.side)?;
+        serde::encode_u32(output, self.eject_cycles)?;
+
+        serde::encode_u8(output, self.disk_io_enabled as u8)?;
+
+        serde::encode_u16(output, self.irq_reload)?;
+        serde::encode_u16(output, self.irq_counter)?;
+        serde::encode_u8(output, self.irq_repeat as u8)?;
+        serde::encode_u8(output, self.irq_enabled as u8)?;
+        serde::encode_u8(output, self.timer_irq as u8)?;
+
+        serde::encode_u8(output, self.motor_on as u8)?;
+        serde::encode_u8(output, self.reset_transfer as u8)?;
+        serde::encode_u8(output, self.read_mode as u8)?;
+        serde::encode_u8(output, self.crc_control as u8)?;
+        serde::encode_u8(output, self.gap_scan as u8)?;
+        serde::encode_u8(output, self.disk_irq_enabled as u8)?;
+        serde::encode_u8(output, self.disk_irq as u8)?;
+
+        serde::encode_usize(output, self.position)?;
+        serde::encode_u32(output, self.delay)?;
+        serde::encode_u8(output, self.end_of_head as u8)?;
+        serde::encode_u8(output, self.scanning as u8)?;
+        serde::encode_u8(output, self.gap_ended as u8)?;
+        serde::encode_u8(output, self.transfer_complete as u8)?;
+        serde::encode_u8(output, self.read_data)?;
+        serde::encode_u8(output, self.write_data)?;
+
+        self.audio.save(output)?;
+
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.prg_ram.load(input)?;
+        self.chr_ram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+
+        let n_sides = serde::decode_usize(input)?;
+        self.sides = vec![];
+        for _ in 0 .. n_sides {
+            self.sides.push(serde::decode_vec(input)?);
+        }
+        self.side = serde::decode_usize(input)?;
+        self.eject_cycles = serde::decode_u32(input)?;
+
+        self.disk_io_enabled = serde::decode_u8(input)? != 0;
+
+        self.irq_reload = serde::decode_u16(input)?;
+        self.irq_counter = serde::decode_u16(input)?;
+        self.irq_repeat = serde::decode_u8(input)? != 0;
+        self.irq_enabled = serde::decode_u8(input)? != 0;
+        self.timer_irq = serde::decode_u8(input)? != 0;
+
+        self.motor_on = serde::decode_u8(input)? != 0;
+        self.reset_transfer = serde::decode_u8(input)? != 0;
+        self.read_mode = serde::decode_u8(input)? != 0;
+        self.crc_control = serde::decode_u8(input)? != 0;
+        self.gap_scan = serde::decode_u8(input)? != 0;
+        self.disk_irq_enabled = serde::decode_u8(input)? != 0;
+        self.disk_irq = serde::decode_u8(input)? != 0;
+
+        self.position = serde::decode_usize(input)?;
+        self.delay = serde::decode_u32(input)?;
+        self.end_of_head = serde::decode_u8(input)? != 0;
+        self.scanning = serde::decode_u8(input)? != 0;
+        self.gap_ended = serde::decode_u8(input)? != 0;
+        self.transfer_complete = serde::decode_u8(input)? != 0;
+        self.read_data = serde::decode_u8(input)?;
+        self.write_data = serde::decode_u8(input)?;
+
+        self.audio.load(input)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_round_trip() {
+        let mut side = vec![0; DISK_SIDE_SIZE];
+
+        // Disk info block, file amount block, and a single 4 byte file
+        side[0] = 1;
+        side[56] = 2;
+        side[57] = 1;
+        side[58] = 3;
+        side[58 + 13] = 4;
+        side[74] = 4;
+        side[75 .. 79].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let raw = side_to_raw(&side);
+        assert_eq!(raw[LEAD_IN_GAP], 0x80);
+        assert_eq!(raw[LEAD_IN_GAP + 1], 1);
+
+        assert_eq!(raw_to_side(&raw), side);
+    }
+
+    fn new_fds() -> FDS {
+        let mut fds = FDS::new_mapper(vec![0; 0x2000], vec![vec![0; DISK_SIDE_SIZE]]);
+        fds.write(0x4023, 0b11);
+        fds
+    }
+
+    #[test]
+    fn test_timer_irq() {
+        let mut fds = new_fds();
+
+        // Fires once the counter has run down from the reload value, and
+        // reloads it when repeating
+        fds.write(0x4020, 10);
+        fds.write(0x4021, 0);
+        fds.write(0x4022, 0b11);
+
+        for _ in 0 .. 2 {
+            fds.notify(MapperEvent::CPUTick(10));
+            assert!(!fds.irq_flag());
+            fds.notify(MapperEvent::CPUTick(1));
+            assert!(fds.irq_flag());
+
+            // Reading $4030 acknowledges it
+            assert_eq!(fds.read(0x4030), Some(0b01));
+            assert!(!fds.irq_flag());
+        }
+
+        // Without repeat, it only fires once
+        fds.write(0x4022, 0b10);
+        fds.notify(MapperEvent::CPUTick(11));
+        assert!(fds.irq_flag());
+        fds.read(0x4030);
+        fds.notify(MapperEvent::CPUTick(100));
+        assert!(!fds.irq_flag());
+
+        // Disabling the timer or disk I/O acknowledges it too
+        fds.write(0x4022, 0b10);
+        fds.notify(MapperEvent::CPUTick(11));
+        fds.write(0x4022, 0);
+        assert!(!fds.irq_flag());
+
+        fds.write(0x4022, 0b10);
+        fds.notify(MapperEvent::CPUTick(11));
+        fds.write(0x4023, 0);
+        assert!(!fds.irq_flag());
+
+        // The timer can't be enabled while disk I/O is off
+        fds.write(0x4022, 0b10);
+        fds.notify(MapperEvent::CPUTick(100));
+        assert!(!fds.irq_flag());
+    }
+
+    #[test]
+    fn test_status() {
+        let mut fds = new_fds();
+
+        fds.timer_irq = true;
+        fds.transfer_complete = true;
+        fds.disk_irq = true;
+        assert_eq!(fds.read(0x4030), Some(0b11));
+        assert!(!fds.irq_flag());
+        assert_eq!(fds.read(0x4030), Some(0b00));
+
+        fds.transfer_complete = true;
+        assert_eq!(fds.read(0x4030), Some(0b10));
+
+        // The disk registers are only readable with disk I/O enabled
+        fds.write(0x4023, 0b10);
+        assert_eq!(fds.read(0x4030), None);
+    }
+
+    #[test]
+    fn test_wave_write_protect() {
+        let mut fds = new_fds();
+
+        fds.write(0x4040, 0x3f);
+        assert_eq!(fds.read(0x4040), Some(0));
+
+        // Only 6 bits wide
+        fds.write(0x4089, 0x80);
+        fds.write(0x4040, 0xff);
+        fds.write(0x407f, 0x15);
+        assert_eq!(fds.read(0x4040), Some(0x3f));
+        assert_eq!(fds.read(0x407f), Some(0x15));
+
+        fds.write(0x4089, 0x00);
+        fds.write(0x4040, 0x00);
+        assert_eq!(fds.read(0x4040), Some(0x3f));
+
+        // Nor with sound I/O disabled
+        fds.write(0x4089, 0x80);
+        fds.write(0x4023, 0b01);
+        fds.write(0x4040, 0x00);
+        assert_eq!(fds.read(0x4040), Some(0x3f));
+    }
+}
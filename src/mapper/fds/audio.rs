@@ -0,0 +1,460 @@
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mem::Memory;
+use crate::serde;
+
+// https://wiki.nesdev.com/w/index.php/FDS_audio
+//
+// The FDS has a single wavetable channel, with a 64-step 6-bit waveform, a
+// volume envelope, and a frequency modulation unit that has its own 64-step
+// table of pitch adjustments and its own envelope.
+
+// The amount each modulation table entry adjusts the modulation counter by,
+// where 4 resets the counter to 0.
+const MOD_ADJUSTMENTS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+
+// The master volume is one of 2/2, 2/3, 2/4 or 2/5, scaled to fixed point
+const MASTER_VOLUME_TABLE: [u32; 4] = [36, 24, 17, 14];
+
+// The volume and modulation units both have an envelope that works the same
+// way, controlled by $4080 and $4084 respectively.
+struct Envelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+}
+
+impl Memory for Envelope {
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        serde::encode_u8(output, self.speed)?;
+        serde::encode_u8(output, self.gain)?;
+        serde::encode_u8(output, self.increase as u8)?;
+        serde::encode_u8(output, self.disabled as u8)?;
+        serde::encode_u32(output, self.timer)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.speed = serde::decode_u8(input)?;
+        self.gain = serde::decode_u8(input)?;
+        self.increase = serde::decode_u8(input)? != 0;
+        self.disabled = serde::decode_u8(input)? != 0;
+        self.timer = serde::decode_u32(input)?;
+        Ok(())
+    }
+}
+
+impl Envelope {
+    fn new_envelope() -> Self {
+        Self {
+            speed: 0,
+            gain: 0,
+            increase: false,
+            disabled: true,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, val: u8, master_speed: u8) {
+        // 7  bit  0
+        // ---- ----
+        // MDVV VVVV
+        // |||| ||||
+        // ||++-++++- Envelope speed, or gain if the envelope is disabled
+        // |+-------- Direction (0: decrease, 1: increase)
+        // +--------- Disable envelope, and use the gain directly
+        self.speed    =  val & 0b0011_1111;
+        self.increase = (val & 0b0100_0000) != 0;
+        self.disabled = (val & 0b1000_0000) != 0;
+
+        if self.disabled {
+            self.gain = self.speed;
+        }
+
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    // Returns true if the gain was clocked
+    fn step(&mut self, master_speed: u8) -> bool {
+        if self.disabled || master_speed == 0 {
+            return false;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer > 0 {
+            return false;
+        }
+
+        self.reset_timer(master_speed);
+
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+
+        true
+    }
+}
+
+pub struct FDSAudio {
+    // $4023 bit 1 enables the sound registers
+    pub enabled: bool,
+
+    wave_table: [u8; 64],
+    wave_write_enabled: bool,
+    wave_position: u8,
+    wave_accumulator: u16,
+    wave_frequency: u16,
+    wave_halted: bool,
+
+    volume: Envelope,
+    envelopes_halted: bool,
+    master_speed: u8,
+    master_volume: u8,
+
+    mod_envelope: Envelope,
+    mod_table: [u8; 64],
+    mod_position: u8,
+    mod_accumulator: u16,
+    mod_frequency: u16,
+    mod_disabled: bool,
+    mod_counter: i8,
+    mod_output: i32,
+
+    output: u8,
+}
+
+impl Memory for FDSAudio {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4040 ..= 0x407f => self.wave_table[address as usize & 0x3f],
+            0x4090            => self.volume.gain | 0x40,
+            0x4092            => self.mod_envelope.gain | 0x40,
+            _                 => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // Waveform RAM, only writable while the waveform is halted
+            0x4040 ..= 0x407f => {
+                if self.wave_write_enabled {
+                    self.wave_table[address as usize & 0x3f] = val & 0x3f;
+                }
+            },
+
+            // Volume envelope
+            0x4080 => self.volume.write(val, self.master_speed),
+
+            // Frequency, low 8 bits
+            0x4082 => {
+                self.wave_frequency = (self.wave_frequency & 0x0f00) | val as u16;
+                self.update_mod_output();
+            },
+
+            0x4083 => {
+                // 7  bit  0
+                // ---- ----
+                // HE.. FFFF
+                // ||   ||||
+                // ||   ++++- Frequency, high 4 bits
+                // |+-------- Halt both envelopes
+                // +--------- Halt the waveform, and reset its phase
+                self.wave_frequency = (self.wave_frequency & 0x00ff)
+                    | (((val & 0x0f) as u16) << 8);
+                self.envelopes_halted = (val & 0b0100_0000) != 0;
+                self.wave_halted      = (val & 0b1000_0000) != 0;
+
+                if self.wave_halted {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.master_speed);
+                    self.mod_envelope.reset_timer(self.master_speed);
+                }
+
+                self.update_mod_output();
+            },
+
+            // Modulation envelope
+            0x4084 => {
+                self.mod_envelope.write(val, self.master_speed);
+                self.update_mod_output();
+            },
+
+            // Modulation counter, a 7-bit signed value
+            0x4085 => {
+                self.set_mod_counter((val & 0x7f) as i32);
+                self.update_mod_output();
+            },
+
+            // Modulation frequency, low 8 bits
+            0x4086 => {
+                self.mod_frequency = (self.mod_frequency & 0x0f00) | val as u16;
+            },
+
+            0x4087 => {
+                // 7  bit  0
+                // ---- ----
+                // D... FFFF
+                // |    ||||
+                // |    ++++- Modulation frequency, high 4 bits
+                // +--------- Disable modulation, allowing table writes
+                self.mod_frequency = (self.mod_frequency & 0x00ff)
+                    | (((val & 0x0f) as u16) << 8);
+                self.mod_disabled = (val & 0b1000_0000) != 0;
+
+                if self.mod_disabled {
+                    self.mod_accumulator = 0;
+                }
+            },
+
+            // Modulation table. Each write fills two consecutive entries.
+            0x4088 => {
+                if self.mod_disabled {
+                    let pos = self.mod_position as usize;
+                    self.mod_table[pos] = val & 0x07;
+                    self.mod_table[(pos + 1) & 0x3f] = val & 0x07;
+                    self.mod_position = (self.mod_position + 2) & 0x3f;
+                }
+            },
+
+            0x4089 => {
+                // 7  bit  0
+                // ---- ----
+                // W... ..VV
+                // |      ||
+                // |      ++- Master volume (0: 2/2, 1: 2/3, 2: 2/4, 3: 2/5)
+                // +--------- Enable waveform RAM writes, halting the output
+                self.master_volume      =  val & 0b0000_0011;
+                self.wave_write_enabled = (val & 0b1000_0000) != 0;
+            },
+
+            // Envelope speed, shared by both envelopes
+            0x408a => {
+                self.master_speed = val;
+                self.volume.reset_timer(self.master_speed);
+                self.mod_envelope.reset_timer(self.master_speed);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        serde::encode_u8(output, self.enabled as u8)?;
+
+        output.write_all(&self.wave_table)?;
+        serde::encode_u8(output, self.wave_write_enabled as u8)?;
+        serde::encode_u8(output, self.wave_position)?;
+        serde::encode_u16(output, self.wave_accumulator)?;
+        serde::encode_u16(output, self.wave_frequency)?;
+        serde::encode_u8(output, self.wave_halted as u8)?;
+
+        self.volume.save(output)?;
+        serde::encode_u8(output, self.envelopes_halted as u8)?;
+        serde::encode_u8(output, self.master_speed)?;
+        serde::encode_u8(output, self.master_volume)?;
+
+        self.mod_envelope.save(output)?;
+        output.write_all(&self.mod_table)?;
+        serde::encode_u8(output, self.mod_position)?;
+        serde::encode_u16(output, self.mod_accumulator)?;
+        serde::encode_u16(output, self.mod_frequency)?;
+        serde::encode_u8(output, self.mod_disabled as u8)?;
+        serde::encode_u8(output, self.mod_counter as u8)?;
+        serde::encode_u32(output, self.mod_output as u32)?;
+
+        serde::encode_u8(output, self.output)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.enabled = serde::decode_u8(input)? != 0;
+
+        input.read_exact(&mut self.wave_table)?;
+        self.wave_write_enabled = serde::decode_u8(input)? != 0;
+        self.wave_position = serde::decode_u8(input)?;
+        self.wave_accumulator = serde::decode_u16(input)?;
+        self.wave_frequency = serde::decode_u16(input)?;
+        self.wave_halted = serde::decode_u8(input)? != 0;
+
+        self.volume.load(input)?;
+        self.envelopes_halted = serde::decode_u8(input)? != 0;
+        self.master_speed = serde::decode_u8(input)?;
+        self.master_volume = serde::decode_u8(input)?;
+
+        self.mod_envelope.load(input)?;
+        input.read_exact(&mut self.mod_table)?;
+        self.mod_position = serde::decode_u8(input)?;
+        self.mod_accumulator = serde::decode_u16(input)?;
+        self.mod_frequency = serde::decode_u16(input)?;
+        self.mod_disabled = serde::decode_u8(input)? != 0;
+        self.mod_counter = serde::decode_u8(input)? as i8;
+        self.mod_output = serde::decode_u32(input)? as i32;
+
+        self.output = serde::decode_u8(input)?;
+        Ok(())
+    }
+}
+
+impl FDSAudio {
+    pub fn new_audio() -> Self {
+        Self {
+            enabled: false,
+
+            wave_table: [0; 64],
+            wave_write_enabled: false,
+            wave_position: 0,
+            wave_accumulator: 0,
+            wave_frequency: 0,
+            wave_halted: true,
+
+            volume: Envelope::new_envelope(),
+            envelopes_halted: false,
+            master_speed: 0xe8,
+            master_volume: 0,
+
+            mod_envelope: Envelope::new_envelope(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_accumulator: 0,
+            mod_frequency: 0,
+            mod_disabled: true,
+            mod_counter: 0,
+            mod_output: 0,
+
+            output: 0,
+        }
+    }
+
+    fn set_mod_counter(&mut self, val: i32) {
+        // The counter is a 7-bit signed value, so it wraps at -64 and 63
+        let val = if val >= 64 {
+            val - 128
+        } else if val < -64 {
+            val + 128
+        } else {
+            val
+        };
+
+        self.mod_counter = val as i8;
+    }
+
+    fn update_mod_output(&mut self) {
+        // This is the pitch calculation from the NesDev wiki, rounding quirks
+        // and all.
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.mod_envelope.gain as i32;
+        let remainder = temp & 0x0f;
+        temp >>= 4;
+
+        if remainder > 0 && (temp & 0x80) == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= self.wave_frequency as i32;
+        let remainder = temp & 0x3f;
+        temp >>= 6;
+
+        if remainder >= 32 {
+            temp += 1;
+        }
+
+        self.mod_output = temp;
+    }
+
+    fn step_modulator(&mut self) {
+        if self.mod_disabled || self.mod_frequency == 0 {
+            return;
+        }
+
+        let (acc, overflowed) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+        self.mod_accumulator = acc;
+
+        if !overflowed {
+            return;
+        }
+
+        let entry = self.mod_table[self.mod_position as usize];
+        if entry == MOD_RESET {
+            self.set_mod_counter(0);
+        } else {
+            let counter = self.mod_counter as i32 + MOD_ADJUSTMENTS[entry as usize] as i32;
+            self.set_mod_counter(counter);
+        }
+
+        self.mod_position = (self.mod_position + 1) & 0x3f;
+        self.update_mod_output();
+    }
+
+    fn update_output(&mut self) {
+        let gain = self.volume.gain.min(32) as u32;
+        let level = gain * MASTER_VOLUME_TABLE[self.master_volume as usize];
+        let sample = self.wave_table[self.wave_position as usize] as u32;
+
+        self.output = ((sample * level) / 1152) as u8;
+    }
+
+    pub fn step(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.step(self.master_speed);
+
+            if self.mod_envelope.step(self.master_speed) {
+                self.update_mod_output();
+            }
+        }
+
+        self.step_modulator();
+
+        if self.wave_halted {
+            self.wave_position = 0;
+            self.update_output();
+            return;
+        }
+
+        // The output is held while the waveform RAM is being written to
+        if !self.wave_write_enabled {
+            self.update_output();
+        }
+
+        let pitch = self.wave_frequency as i32 + self.mod_output;
+        if pitch > 0 && !self.wave_write_enabled {
+            let (acc, overflowed) = self.wave_accumulator.overflowing_add(pitch as u16);
+            self.wave_accumulator = acc;
+
+            if overflowed {
+                self.wave_position = (self.wave_position + 1) & 0x3f;
+            }
+        }
+    }
+
+    pub fn signal(&self) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+
+        (self.output as f32 / 63.0) * 0.4
+    }
+}
@@ -1,18 +1,18 @@
-use std::io::{Read, Write};
 use std::io;
 use std::fs::File;
 
 use crate::mapper::Mapper;
 use crate::mapper::MirrorMode;
-use crate::serde;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
 
 //
 // NROM (mapper 0)
 //
 pub struct Mapper0 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
 
     pub mirror_mode: MirrorMode,
 }
@@ -22,53 +22,45 @@ impl Mapper for Mapper0 {
         &self.mirror_mode
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
-            0x0000 ..= 0x1fff => {
-                let len = self.chr_rom.len();
-                self.chr_rom[address as usize % len]
-            },
-            0x6000 ..= 0x7fff => self.sram[address as usize - 0x6000],
-            0x8000 ..= 0xffff => self.prg_rom[address as usize % self.prg_rom.len()],
-            _ => 0,
+            0x0000 ..= 0x1fff => self.chr.read(address),
+            0x6000 ..= 0x7fff => self.sram.read(address),
+            0x8000 ..= 0xffff => self.prg.read(address),
+            _ => None,
         }
     }
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
-            0x0000 ..= 0x1fff => {
-                let len = self.chr_rom.len();
-                self.chr_rom[address as usize % len] = val;
-            },
-            0x6000 ..= 0x7fff => {
-                self.sram[address as usize - 0x6000] = val;
-            },
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
             _ => { },
         }
     }
 
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
         Ok(())
     }
 }
 
 impl Mapper0 {
-    pub fn new_mapper(rom: Vec<u8>, vrom: Vec<u8>, mirror_mode: u8) -> Self {
+    pub fn new_mapper(cart: Cartridge) -> Self {
         Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, 0x8000),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
         }
     }
 }
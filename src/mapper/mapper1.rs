@@ -1,21 +1,46 @@
-use std::io::{Read, Write};
 use std::io;
+use std::io::{Read, Write};
 use std::fs::File;
 
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, MapperEvent};
 use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
 use crate::serde;
 
 const PRG_BANK_SIZE: usize = 16384;
 const CHR_BANK_SIZE: usize = 4096;
 
+// Boards with 8KB of CHR-RAM reuse the upper lines of the CHR bank registers
+// for more PRG-ROM or PRG-RAM than the MMC1 can address by itself.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MMC1Board {
+    SxROM,
+
+    // SUROM has 512KB of PRG-ROM, with bit 4 of the CHR bank choosing the
+    // 256KB half.
+    SUROM,
+
+    // SOROM has 16KB of PRG-RAM, with bit 3 of the CHR bank choosing the 8KB
+    // bank.
+    SOROM,
+
+    // SXROM has 32KB of PRG-RAM, with bits 2-3 of the CHR bank choosing the
+    // 8KB bank, and up to 512KB of PRG-ROM like SUROM.
+    SXROM,
+}
+
 //
 // MMC1/SxROM (mapper 1)
 //
 pub struct Mapper1 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
+    board: MMC1Board,
+
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    battery: bool,
 
     // Registers
     control: u8,
@@ -26,24 +51,29 @@ pub struct Mapper1 {
     shift_register: u8,
     write_count: u8,
 
-    // The number of PRG-ROM banks in this cartridge
-    n_banks: usize,
+    // The MMC1 ignores a write on the cycle straight after another, which
+    // happens with read-modify-write instructions. CPU cycles are only
+    // reported after each instruction, so two writes before the count moves
+    // on come from the same instruction.
+    cycles: u64,
+    last_write: Option<u64>,
 
     mirror_mode: MirrorMode,
 }
 
 impl Mapper1 {
-    pub fn new_mapper(rom: Vec<u8>,
-                      vrom: Vec<u8>,
-                      mirror_mode: u8)
+    pub fn new_mapper(cart: Cartridge,
+                      board: MMC1Board)
         -> Self
     {
-        let n_banks = rom.len() / PRG_BANK_SIZE;
+        let mut mapper = Self {
+            board: board,
 
-        Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            battery: cart.battery,
 
             control: (3 << 2),
             chr_bank0: 0,
@@ -52,17 +82,28 @@ impl Mapper1 {
 
             shift_register: 0,
             write_count: 0,
-            n_banks: n_banks,
 
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
-        }
+            cycles: 0,
+            last_write: None,
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+        };
+
+        mapper.update_banks();
+        mapper
     }
 
     fn load_register(&mut self, address: u16, val: u8) {
+        if self.last_write == Some(self.cycles) {
+            return;
+        }
+        self.last_write = Some(self.cycles);
+
         if val & 0x80 == 0x80 {
             self.shift_register = 0;
             self.control = 3 << 2;
             self.write_count = 0;
+            self.update_banks();
         } else {
             self.shift_register |= (val & 1) << (self.write_count as usize);
             self.write_count += 1;
@@ -83,6 +124,58 @@ impl Mapper1 {
         (self.control >> 4) & 1
     }
 
+    fn update_banks(&mut self) {
+        match self.chr_mode() {
+            0 => {
+                let bank = self.chr_bank0 as usize & 0xfe;
+                self.chr.map(0x0000, CHR_BANK_SIZE, bank);
+                self.chr.map(0x1000, CHR_BANK_SIZE, bank | 1);
+            },
+            _ => {
+                self.chr.map(0x0000, CHR_BANK_SIZE, self.chr_bank0 as usize);
+                self.chr.map(0x1000, CHR_BANK_SIZE, self.chr_bank1 as usize);
+            },
+        }
+
+        // The 256KB half of the PRG-ROM on SUROM and SXROM, which also
+        // applies to the fixed banks.
+        let outer = match self.board {
+            MMC1Board::SUROM | MMC1Board::SXROM => (self.chr_bank0 & 0x10) as usize,
+            _ => 0,
+        };
+
+        let bank = outer | (self.prg_bank & 0x0f) as usize;
+        match self.prg_mode() {
+            0 | 1 => {
+                self.prg.map(0x8000, PRG_BANK_SIZE, bank & 0xfe);
+                self.prg.map(0xc000, PRG_BANK_SIZE, bank | 1);
+            },
+            2 => {
+                self.prg.map(0x8000, PRG_BANK_SIZE, outer);
+                self.prg.map(0xc000, PRG_BANK_SIZE, bank);
+            },
+            _ => {
+                let last_bank = std::cmp::min(outer | 0x0f, self.prg.last_bank(PRG_BANK_SIZE));
+                self.prg.map(0x8000, PRG_BANK_SIZE, bank);
+                self.prg.map(0xc000, PRG_BANK_SIZE, last_bank);
+            },
+        }
+
+        let sram_bank = match self.board {
+            MMC1Board::SOROM => (self.chr_bank0 >> 3) & 1,
+            MMC1Board::SXROM => (self.chr_bank0 >> 2) & 3,
+            _ => 0,
+        };
+
+        // Bit 4 of the PRG bank disables PRG-RAM on the MMC1B and later,
+        // leaving $6000-$7FFF as open bus.
+        if self.prg_bank & 0x10 != 0 {
+            self.sram.unmap(0x6000, 0x2000);
+        } else {
+            self.sram.map(0x6000, 0x2000, sram_bank as usize);
+        }
+    }
+
     fn write_register(&mut self, address: u16, val: u8) {
         match address {
             0x0000 ..= 0x9fff => {
@@ -103,9 +196,11 @@ impl Mapper1 {
                 self.chr_bank1 = val & 0b1_1111;
             },
             0xe000 ..= 0xffff => {
-                self.prg_bank = val & 0b1111;
+                self.prg_bank = val & 0b1_1111;
             },
         }
+
+        self.update_banks();
     }
 }
 
@@ -114,89 +209,28 @@ impl Mapper for Mapper1 {
         &self.mirror_mode
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x0fff => {
-                let bank = match self.chr_mode() {
-                    0 => self.chr_bank0,
-                    1 => self.chr_bank0,
-                    _ => panic!("bad chr_mode"),
-                } as usize;
-
-                let index = ((CHR_BANK_SIZE * bank) | (address as usize & 0x3fff)) % self.chr_rom.len();
-                self.chr_rom[index]
-            },
-            0x1000 ..= 0x1fff => {
-                let bank = match self.chr_mode() {
-                    0 => self.chr_bank0 + 1,
-                    1 => self.chr_bank1,
-                    _ => panic!("bad chr_mode"),
-                } as usize;
-
-                let index = ((CHR_BANK_SIZE * bank) | ((address as usize - 0x1000) & 0x3fff)) % self.chr_rom.len();
-                self.chr_rom[index]
-            },
+            0x0000 ..= 0x1fff => self.chr.read(address),
 
             // SRAM
-            0x6000 ..= 0x7fff => {
-                self.sram[address as usize - 0x6000]
-            },
+            0x6000 ..= 0x7fff => self.sram.read(address),
 
             // PRG-ROM
-            0x8000 ..= 0xbfff => {
-                let bank = match self.prg_mode() {
-                    0 | 1 => self.prg_bank as usize & 0xfe,
-                    2     => 0,
-                    3     => self.prg_bank as usize,
-                    _     => panic!("bad prg_mode"),
-                };
-                let index = ((PRG_BANK_SIZE * bank) | (address as usize & 0x3fff)) % self.prg_rom.len();
-
-                self.prg_rom[index]
-            },
-            0xc000 ..= 0xffff => {
-                let bank = match self.prg_mode() {
-                    0 | 1 => (self.prg_bank as usize & 0xfe) | 1,
-                    2     => self.prg_bank as usize,
-                    3     => self.n_banks - 1,
-                    _     => panic!("bad prg_mode"),
-                };
-                let index = ((PRG_BANK_SIZE * bank) | (address as usize & 0x3fff)) % self.prg_rom.len();
-
-                self.prg_rom[index]
-            },
+            0x8000 ..= 0xffff => self.prg.read(address),
 
-            _ => 0,
+            _ => None,
         }
     }
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x0fff => {
-                let bank = match self.chr_mode() {
-                    0 => self.chr_bank0,
-                    1 => self.chr_bank0,
-                    _ => panic!("bad chr_mode"),
-                } as usize;
-
-                let index = ((CHR_BANK_SIZE * bank) | address as usize) % self.chr_rom.len();
-                self.chr_rom[index] = val;
-            },
-            0x1000 ..= 0x1fff => {
-                let bank = match self.chr_mode() {
-                    0 => self.chr_bank0 + 1,
-                    1 => self.chr_bank1,
-                    _ => panic!("bad chr_mode"),
-                } as usize;
-
-                let index = ((CHR_BANK_SIZE * bank) | (address as usize - 0x1000)) % self.chr_rom.len();
-                self.chr_rom[index] = val;
-            },
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
 
             // SRAM
-            0x6000 ..= 0x7fff => { self.sram[address as usize - 0x6000] = val },
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
 
             // PRG-ROM
             0x8000 ..= 0xffff => { self.load_register(address, val) },
@@ -205,33 +239,166 @@ impl Mapper for Mapper1 {
         }
     }
 
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::CPUTick(cycles) => { self.cycles += cycles },
+            _ => { },
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn save_battery(&self, output: &mut File) -> io::Result<()> {
+        output.write_all(self.sram.data())?;
+        Ok(())
+    }
+
+    fn load_battery(&mut self, input: &mut File) -> io::Result<()> {
+        input.read_exact(self.sram.data_mut())?;
+        Ok(())
+    }
+
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
         serde::encode_u8(output, self.control)?;
         serde::encode_u8(output, self.chr_bank0)?;
         serde::encode_u8(output, self.chr_bank1)?;
         serde::encode_u8(output, self.prg_bank)?;
         serde::encode_u8(output, self.shift_register)?;
         serde::encode_u8(output, self.write_count)?;
-        serde::encode_usize(output, self.n_banks)?;
+        serde::encode_u64(output, self.cycles)?;
+        serde::encode_u8(output, self.last_write.is_some() as u8)?;
+        serde::encode_u64(output, self.last_write.unwrap_or(0))?;
         serde::encode_u8(output, self.mirror_mode as u8)?;
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
         self.control = serde::decode_u8(input)?;
         self.chr_bank0 = serde::decode_u8(input)?;
         self.chr_bank1 = serde::decode_u8(input)?;
         self.prg_bank = serde::decode_u8(input)?;
         self.shift_register = serde::decode_u8(input)?;
         self.write_count = serde::decode_u8(input)?;
-        self.n_banks = serde::decode_usize(input)?;
+        self.cycles = serde::decode_u64(input)?;
+        let wrote = serde::decode_u8(input)? != 0;
+        let last_write = serde::decode_u64(input)?;
+        self.last_write = if wrote { Some(last_write) } else { None };
         self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    fn write_register(mapper: &mut Mapper1, address: u16, val: u8) {
+        for i in 0 .. 5 {
+            mapper.write(address, (val >> i) & 1);
+            mapper.notify(MapperEvent::CPUTick(4));
+        }
+    }
+
+    #[test]
+    fn test_chr_rom() {
+        let mut mapper = Mapper1::new_mapper(Cartridge::new_cartridge(banked(2, 0x4000), banked(2, 0x2000), 0), MMC1Board::SxROM);
+        mapper.write(0x0000, 0xff);
+        assert_eq!(mapper.read(0x0000), Some(0));
+
+        let mut mapper = Mapper1::new_mapper(Cartridge::new_cartridge(banked(2, 0x4000), vec![], 0), MMC1Board::SxROM);
+        mapper.write(0x0000, 0xff);
+        assert_eq!(mapper.read(0x0000), Some(0xff));
+    }
+
+    #[test]
+    fn test_prg_ram_disable() {
+        let mut mapper = Mapper1::new_mapper(Cartridge::new_cartridge(banked(2, 0x4000), vec![], 0), MMC1Board::SxROM);
+        mapper.write(0x6000, 1);
+        assert_eq!(mapper.read(0x6000), Some(1));
+
+        write_register(&mut mapper, 0xe000, 0x10);
+        mapper.write(0x6000, 2);
+        assert_eq!(mapper.read(0x6000), None);
+
+        write_register(&mut mapper, 0xe000, 0x00);
+        assert_eq!(mapper.read(0x6000), Some(1));
+    }
+
+    #[test]
+    fn test_consecutive_writes() {
+        let mut mapper = Mapper1::new_mapper(Cartridge::new_cartridge(banked(16, 0x4000), vec![], 0), MMC1Board::SxROM);
+
+        // A read-modify-write instruction writes twice in a row, and only
+        // the first one counts.
+        write_register(&mut mapper, 0x8000, 0x0c);
+        for i in 0 .. 5 {
+            mapper.write(0xe000, (0x03 >> i) & 1);
+            mapper.write(0xe000, 1);
+            mapper.notify(MapperEvent::CPUTick(6));
+        }
+        assert_eq!(mapper.read(0x8000), Some(3));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let new_mapper = || Mapper1::new_mapper(Cartridge::new_cartridge(banked(16, 0x4000), vec![], 0), MMC1Board::SxROM);
+        let mut mapper = new_mapper();
+
+        // A save state taken between the two writes of a read-modify-write
+        // instruction still ignores the second one once loaded.
+        mapper.notify(MapperEvent::CPUTick(1000));
+        mapper.write(0xe000, 1);
+
+        let path = std::env::temp_dir().join("mapper1_test_save_load.state");
+        mapper.save(&mut File::create(&path).unwrap()).unwrap();
+
+        let mut loaded = new_mapper();
+        loaded.load(&mut File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.cycles, 1000);
+        assert_eq!(loaded.last_write, Some(1000));
+
+        loaded.write(0xe000, 0);
+        assert_eq!(loaded.write_count, 1);
+    }
+
+    #[test]
+    fn test_surom() {
+        let mut mapper = Mapper1::new_mapper(Cartridge::new_cartridge(banked(32, 0x4000), vec![], 0), MMC1Board::SUROM);
+        write_register(&mut mapper, 0x8000, 0x0c);
+        assert_eq!(mapper.read(0xc000), Some(15));
+
+        // Bit 4 of the CHR bank picks the second 256KB, including the fixed
+        // bank at $C000.
+        write_register(&mut mapper, 0xa000, 0x10);
+        write_register(&mut mapper, 0xe000, 0x02);
+        assert_eq!(mapper.read(0x8000), Some(18));
+        assert_eq!(mapper.read(0xc000), Some(31));
+    }
+
+    #[test]
+    fn test_sxrom_ram() {
+        let mut cart = Cartridge::new_cartridge(banked(2, 0x4000), vec![], 0);
+        cart.prg_ram_size = 0x8000;
+        let mut mapper = Mapper1::new_mapper(cart, MMC1Board::SXROM);
+
+        for bank in 0 .. 4 {
+            write_register(&mut mapper, 0xa000, bank << 2);
+            mapper.write(0x6000, bank);
+        }
+
+        write_register(&mut mapper, 0xa000, 2 << 2);
+        assert_eq!(mapper.read(0x6000), Some(2));
+        assert_eq!(mapper.sram.data()[0x6000], 3);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 32768;
+const CHR_BANK_SIZE: usize = 8192;
+
+//
+// Color Dreams (mapper 11)
+//
+pub struct Mapper11 {
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    mirror_mode: MirrorMode,
+
+    // Whether writes to PRG-ROM collide with the ROM's own output
+    bus_conflicts: bool,
+}
+
+impl Mapper11 {
+    pub fn new_mapper(cart: Cartridge,
+                      bus_conflicts: bool)
+        -> Self
+    {
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            bus_conflicts: bus_conflicts,
+        }
+    }
+}
+
+impl Mapper for Mapper11 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                let val = if self.bus_conflicts { val & self.read(address).unwrap_or(val) } else { val };
+
+                // 7  bit  0
+                // ---- ----
+                // CCCC LLPP
+                // |||| ||||
+                // |||| ||++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+                // |||| ++--- Used for lockout defeat
+                // ++++------ Select 8 KB CHR ROM bank for PPU $0000-$1FFF
+                self.prg.map(0x8000, PRG_BANK_SIZE, (val & 0b0000_0011) as usize);
+                self.chr.map(0x0000, CHR_BANK_SIZE, ((val & 0b1111_0000) >> 4) as usize);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper11::new_mapper(Cartridge::new_cartridge(banked(4, PRG_BANK_SIZE), banked(16, CHR_BANK_SIZE), 0), false);
+
+        mapper.write(0x8000, 0b1010_0011);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(mapper.read(0xffff), Some(3));
+        assert_eq!(mapper.read(0x0000), Some(10));
+        assert_eq!(mapper.read(0x1fff), Some(10));
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = banked(4, PRG_BANK_SIZE);
+        rom[0] = 0b0110_0010;
+
+        let mut mapper = Mapper11::new_mapper(Cartridge::new_cartridge(rom.clone(), banked(16, CHR_BANK_SIZE), 0), true);
+        mapper.write(0x8000, 0b1100_0011);
+        assert_eq!(mapper.read(0x8000), Some(2));
+        assert_eq!(mapper.read(0x0000), Some(4));
+
+        let mut mapper = Mapper11::new_mapper(Cartridge::new_cartridge(rom, banked(16, CHR_BANK_SIZE), 0), false);
+        mapper.write(0x8000, 0b1100_0011);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(mapper.read(0x0000), Some(12));
+    }
+}
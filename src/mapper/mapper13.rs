@@ -0,0 +1,143 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const CHR_BANK_SIZE: usize = 4096;
+
+//
+// CPROM (mapper 13)
+//
+pub struct Mapper13 {
+    // 16KB of CHR-RAM, the first 4KB of which is always at $0000-$0FFF
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    mirror_mode: MirrorMode,
+
+    // Whether writes to PRG-ROM collide with the ROM's own output
+    bus_conflicts: bool,
+}
+
+impl Mapper13 {
+    pub fn new_mapper(cart: Cartridge,
+                      bus_conflicts: bool)
+        -> Self
+    {
+        let mut chr = Banks::new_ram(vec![0; 0x4000], 0x0000, 0x2000, CHR_BANK_SIZE);
+        chr.map(0x1000, CHR_BANK_SIZE, 0);
+
+        Self {
+            chr: chr,
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, 0x8000),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            bus_conflicts: bus_conflicts,
+        }
+    }
+}
+
+impl Mapper for Mapper13 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                let val = if self.bus_conflicts { val & self.read(address).unwrap_or(val) } else { val };
+
+                // 7  bit  0
+                // ---- ----
+                // xxxx xxCC
+                //        ||
+                //        ++- Select 4 KB CHR RAM bank for PPU $1000-$1FFF
+                self.chr.map(0x1000, CHR_BANK_SIZE, (val & 0b0000_0011) as usize);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper13::new_mapper(Cartridge::new_cartridge(vec![0; 0x8000], vec![], 0), false);
+
+        // Bank 0 is visible in both halves
+        mapper.write(0x1000, 0x34);
+        assert_eq!(mapper.read(0x0000), Some(0x34));
+
+        mapper.write(0x8000, 2);
+        mapper.write(0x1000, 0x56);
+        assert_eq!(mapper.read(0x0000), Some(0x34));
+        assert_eq!(mapper.read(0x1000), Some(0x56));
+        assert_eq!(mapper.chr.data()[0x2000], 0x56);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = vec![0xff; 0x8000];
+        rom[0x10] = 0b0000_0001;
+
+        let mut mapper = Mapper13::new_mapper(Cartridge::new_cartridge(rom.clone(), vec![], 0), true);
+        mapper.write(0x8010, 0b0000_0011);
+        mapper.write(0x1000, 0x34);
+        assert_eq!(mapper.chr.data()[0x1000], 0x34);
+        assert_eq!(mapper.chr.data()[0x3000], 0x00);
+
+        let mut mapper = Mapper13::new_mapper(Cartridge::new_cartridge(rom, vec![], 0), false);
+        mapper.write(0x8010, 0b0000_0011);
+        mapper.write(0x1000, 0x34);
+        assert_eq!(mapper.chr.data()[0x3000], 0x34);
+    }
+}
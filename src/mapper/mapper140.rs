@@ -0,0 +1,120 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 32768;
+const CHR_BANK_SIZE: usize = 8192;
+
+//
+// Jaleco JF-11/JF-14 (mapper 140)
+//
+pub struct Mapper140 {
+    chr: Banks,
+    prg: Banks,
+
+    mirror_mode: MirrorMode,
+}
+
+impl Mapper140 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+        }
+    }
+}
+
+impl Mapper for Mapper140 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // Like GxROM, but the register is at $6000-$7FFF
+            0x6000 ..= 0x7fff => {
+                // 7  bit  0
+                // ---- ----
+                // xxPP CCCC
+                //   || ||||
+                //   || ++++- Select 8 KB CHR ROM bank for PPU $0000-$1FFF
+                //   ++------ Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+                self.chr.map(0x0000, CHR_BANK_SIZE, (val & 0b0000_1111) as usize);
+                self.prg.map(0x8000, PRG_BANK_SIZE, ((val & 0b0011_0000) >> 4) as usize);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper140::new_mapper(Cartridge::new_cartridge(banked(4, PRG_BANK_SIZE), banked(16, CHR_BANK_SIZE), 0));
+
+        mapper.write(0x6000, 0b0010_1100);
+        assert_eq!(mapper.read(0x8000), Some(2));
+        assert_eq!(mapper.read(0x0000), Some(12));
+
+        // Writes to PRG-ROM don't do anything
+        mapper.write(0x8000, 0);
+        assert_eq!(mapper.read(0x8000), Some(2));
+    }
+
+    #[test]
+    fn test_register_range() {
+        let mut mapper = Mapper140::new_mapper(Cartridge::new_cartridge(banked(4, PRG_BANK_SIZE), banked(16, CHR_BANK_SIZE), 0));
+
+        // Mirrored throughout $6000-$7FFF, with the top two bits ignored
+        mapper.write(0x7fff, 0b1111_0111);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(mapper.read(0xffff), Some(3));
+        assert_eq!(mapper.read(0x0000), Some(7));
+
+        // Nothing there to read back
+        assert_eq!(mapper.read(0x6000), None);
+    }
+}
@@ -0,0 +1,180 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::{Mapper, MapperEvent};
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 8192;
+
+//
+// 100-in-1 Contra Function 16, K-1029 (mapper 15)
+//
+pub struct Mapper15 {
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+    mirror_mode: MirrorMode,
+}
+
+impl Mapper15 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        let mut mapper = Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+        };
+
+        mapper.reset();
+        mapper
+    }
+
+    // The bank mode comes from the low two bits of the address that was
+    // written to, and the bank from the value.
+    fn update_banks(&mut self, mode: u8, latch: u8) {
+        let bank = (latch & 0b0011_1111) as usize;
+        let sub  = (latch >> 7) as usize;
+
+        for slot in 0 .. 4 {
+            // Each mode is a different discrete board: NROM-256, UNROM,
+            // NROM-64 and NROM-128 respectively.
+            let page = match mode {
+                0 => ((bank << 1) + slot) ^ sub,
+                1 => {
+                    let bank = if slot >= 2 { bank | 0x07 } else { bank };
+                    (slot & 1) + ((bank << 1) ^ sub)
+                },
+                2 => (bank << 1) + sub,
+                _ => (slot & 1) + ((bank << 1) ^ sub),
+            };
+
+            let address = 0x8000 + (slot * PRG_BANK_SIZE) as u16;
+            self.prg.map(address, PRG_BANK_SIZE, page);
+        }
+
+        // CHR-RAM is write-protected in the NROM modes
+        self.chr.set_writable(mode == 1 || mode == 2);
+    }
+
+    fn reset(&mut self) {
+        self.write(0x8000, 0);
+    }
+}
+
+impl Mapper for Mapper15 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                // A~[1... .... .... ..MM]
+                //                     ||
+                //                     ++- Bank mode
+                //
+                // 7  bit  0
+                // ---- ----
+                // SMBB BBBB
+                // |||| ||||
+                // ||++-++++- Select 16 KB PRG ROM bank
+                // |+-------- Mirroring (0: vertical, 1: horizontal)
+                // +--------- Select 8 KB half of the bank, in some modes
+                self.update_banks((address & 0x03) as u8, val);
+
+                self.mirror_mode = MirrorMode::from_vh01((val >> 6) & 1);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::Reset => { self.reset() },
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_modes() {
+        let mut mapper = Mapper15::new_mapper(Cartridge::new_cartridge(banked(128, PRG_BANK_SIZE), vec![0; 0x2000], 0));
+
+        // NROM-256
+        mapper.write(0x8000, 4);
+        assert_eq!(mapper.read(0x8000), Some(8));
+        assert_eq!(mapper.read(0xe000), Some(11));
+
+        // UNROM, with the last bank of the 128KB block fixed at $C000
+        mapper.write(0x8001, 9);
+        assert_eq!(mapper.read(0x8000), Some(18));
+        assert_eq!(mapper.read(0xa000), Some(19));
+        assert_eq!(mapper.read(0xc000), Some(30));
+        assert_eq!(mapper.read(0xe000), Some(31));
+
+        // NROM-64
+        mapper.write(0x8002, 0x83);
+        assert_eq!(mapper.read(0x8000), Some(7));
+        assert_eq!(mapper.read(0xe000), Some(7));
+
+        // NROM-128
+        mapper.write(0x8003, 5);
+        assert_eq!(mapper.read(0x8000), Some(10));
+        assert_eq!(mapper.read(0xc000), Some(10));
+        assert_eq!(mapper.read(0xe000), Some(11));
+
+        // Resetting goes back to the menu
+        mapper.notify(MapperEvent::Reset);
+        assert_eq!(mapper.read(0x8000), Some(0));
+        assert_eq!(mapper.read(0xe000), Some(3));
+    }
+}
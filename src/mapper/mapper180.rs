@@ -0,0 +1,138 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 16384;
+
+//
+// UNROM with a 74HC08 (mapper 180), as used by Crazy Climber
+//
+// Like UxROM, but with the first bank fixed at $8000, and the switchable bank
+// at $C000.
+//
+pub struct Mapper180 {
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    mirror_mode: MirrorMode,
+
+    // Whether writes to PRG-ROM collide with the ROM's own output
+    bus_conflicts: bool,
+}
+
+impl Mapper180 {
+    pub fn new_mapper(cart: Cartridge,
+                      bus_conflicts: bool)
+        -> Self
+    {
+        let mut prg = Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE);
+        prg.map(0x8000, PRG_BANK_SIZE, 0);
+        prg.map(0xc000, PRG_BANK_SIZE, 0);
+
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: prg,
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            bus_conflicts: bus_conflicts,
+        }
+    }
+}
+
+impl Mapper for Mapper180 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                let val = if self.bus_conflicts { val & self.read(address).unwrap_or(val) } else { val };
+
+                // Select 16 KB PRG ROM bank for CPU $C000-$FFFF
+                self.prg.map(0xc000, PRG_BANK_SIZE, (val & 0b0000_0111) as usize);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper180::new_mapper(Cartridge::new_cartridge(banked(8, PRG_BANK_SIZE), vec![0; 0x2000], 0), false);
+
+        assert_eq!(mapper.read(0x8000), Some(0));
+        assert_eq!(mapper.read(0xc000), Some(0));
+
+        mapper.write(0x8000, 5);
+        assert_eq!(mapper.read(0x8000), Some(0));
+        assert_eq!(mapper.read(0xc000), Some(5));
+        assert_eq!(mapper.read(0xffff), Some(5));
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = banked(8, PRG_BANK_SIZE);
+        rom[0] = 0b0000_0110;
+
+        let mut mapper = Mapper180::new_mapper(Cartridge::new_cartridge(rom.clone(), vec![0; 0x2000], 0), true);
+        mapper.write(0x8000, 0b0000_0011);
+        assert_eq!(mapper.read(0xc000), Some(2));
+
+        let mut mapper = Mapper180::new_mapper(Cartridge::new_cartridge(rom, vec![0; 0x2000], 0), false);
+        mapper.write(0x8000, 0b0000_0011);
+        assert_eq!(mapper.read(0xc000), Some(3));
+    }
+}
@@ -0,0 +1,456 @@
+mod audio;
+
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mapper::{Mapper, MapperEvent};
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::mapper::mapper19::audio::N163Audio;
+use crate::mem::Memory;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+
+//
+// Namco 163 (mapper 19)
+//
+pub struct Mapper19 {
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    // CHR-ROM as seen from the nametables, for banks that don't select CIRAM
+    nt: Banks,
+
+    // The console's 2KB of nametable RAM (CIRAM). The N163 can map it into
+    // both the pattern tables and the nametables, so the mapper drives all
+    // nametable accesses itself.
+    vram: [u8; 0x800],
+
+    battery: bool,
+
+    // Registers
+    chr_banks: [u8; 8],
+    nt_banks: [u8; 4],
+
+    // Disables CIRAM in the pattern tables at $0000-$0FFF and $1000-$1FFF
+    // respectively, so that banks $E0-$FF select CHR-ROM instead.
+    chr_ram_disabled: [bool; 2],
+
+    // PRG-RAM write protect, from $F800
+    write_protect: u8,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_flag: bool,
+
+    audio: N163Audio,
+}
+
+impl Mapper19 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        let mut prg = Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE);
+
+        // $E000-$FFFF is fixed to the last bank
+        let last_bank = prg.last_bank(PRG_BANK_SIZE);
+        prg.map(0xe000, PRG_BANK_SIZE, last_bank);
+
+        let mut mapper = Self {
+            chr: Banks::new_rom(cart.chr_rom.clone(), 0x0000, 0x2000, CHR_BANK_SIZE),
+            prg: prg,
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            nt: Banks::new_rom(cart.chr_rom, 0x2000, 0x1000, CHR_BANK_SIZE),
+
+            vram: [0; 0x800],
+
+            battery: cart.battery,
+
+            chr_banks: [0; 8],
+            nt_banks: [0; 4],
+
+            chr_ram_disabled: [false; 2],
+
+            write_protect: 0,
+
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_flag: false,
+
+            audio: N163Audio::new_audio(),
+        };
+
+        mapper.update_chr_banks();
+        mapper
+    }
+
+    // Banks that select CIRAM are unmapped from CHR-ROM, and go to vram
+    // instead.
+    fn update_chr_banks(&mut self) {
+        for slot in 0 .. 8 {
+            let bank = self.chr_banks[slot];
+            let allowed = !self.chr_ram_disabled[slot / 4];
+            let address = (slot * CHR_BANK_SIZE) as u16;
+
+            if Self::selects_ciram(bank, allowed) {
+                self.chr.unmap(address, CHR_BANK_SIZE);
+            } else {
+                self.chr.map(address, CHR_BANK_SIZE, bank as usize);
+            }
+        }
+
+        for table in 0 .. 4 {
+            let bank = self.nt_banks[table];
+            let address = 0x2000 + (table * CHR_BANK_SIZE) as u16;
+
+            if Self::selects_ciram(bank, true) {
+                self.nt.unmap(address, CHR_BANK_SIZE);
+            } else {
+                self.nt.map(address, CHR_BANK_SIZE, bank as usize);
+            }
+        }
+    }
+
+    // Whether a 1KB bank register value (either from the pattern tables or
+    // the nametables) selects CIRAM rather than CHR-ROM.
+    fn selects_ciram(val: u8, ciram_allowed: bool) -> bool {
+        ciram_allowed && val >= 0xe0
+    }
+
+    // The CIRAM page comes from the low bit of the bank
+    fn vram_index(bank: u8, address: u16) -> usize {
+        (bank as usize & 1) * 0x400 | (address as usize & 0x03ff)
+    }
+
+    fn sram_writable(&self, address: u16) -> bool {
+        // 7  bit  0
+        // ---- ----
+        // KKKK DCBA
+        // |||| ||||
+        // |||| |||+- 1: Write-protect $6000-$67FF
+        // |||| ||+-- 1: Write-protect $6800-$6FFF
+        // |||| |+--- 1: Write-protect $7000-$77FF
+        // |||| +---- 1: Write-protect $7800-$7FFF
+        // ++++------ Must be 0100 for any writes to be allowed
+        if self.write_protect & 0xf0 != 0x40 {
+            return false;
+        }
+
+        let page = (address as usize - 0x6000) / 0x800;
+        self.write_protect & (1 << page) == 0
+    }
+
+    fn step_irq_counter(&mut self, cycles: u64) {
+        // The 15-bit IRQ counter counts up on every CPU cycle, and stops once
+        // it reaches $7FFF, raising an IRQ.
+        if !self.irq_enabled || self.irq_counter >= 0x7fff {
+            return;
+        }
+
+        let counter = self.irq_counter as u64 + cycles;
+
+        if counter >= 0x7fff {
+            self.irq_counter = 0x7fff;
+            self.irq_flag = true;
+        } else {
+            self.irq_counter = counter as u16;
+        }
+    }
+}
+
+impl Mapper for Mapper19 {
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM, or CIRAM
+            0x0000 ..= 0x1fff if self.chr.is_mapped(address) => self.chr.read(address),
+            0x0000 ..= 0x1fff => {
+                let bank = self.chr_banks[address as usize / CHR_BANK_SIZE];
+                Some(self.vram[Self::vram_index(bank, address)])
+            },
+
+            // Internal RAM data port
+            0x4800 ..= 0x4fff => Some(self.audio.read(address)),
+
+            // IRQ counter
+            0x5000 ..= 0x57ff => Some(self.irq_counter as u8),
+            0x5800 ..= 0x5fff => {
+                Some(((self.irq_enabled as u8) << 7) | (self.irq_counter >> 8) as u8)
+            },
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM, or CIRAM
+            0x0000 ..= 0x1fff if self.chr.is_mapped(address) => self.chr.write(address, val),
+            0x0000 ..= 0x1fff => {
+                let bank = self.chr_banks[address as usize / CHR_BANK_SIZE];
+                self.vram[Self::vram_index(bank, address)] = val;
+            },
+
+            // Internal RAM data port
+            0x4800 ..= 0x4fff => self.audio.write(address, val),
+
+            // IRQ counter. Writing to either half acknowledges the IRQ.
+            0x5000 ..= 0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | val as u16;
+                self.irq_flag = false;
+            },
+            0x5800 ..= 0x5fff => {
+                // 7  bit  0
+                // ---- ----
+                // EHHH HHHH
+                // |||| ||||
+                // |+++-++++- The high 7 bits of the IRQ counter
+                // +--------- IRQ enable
+                self.irq_counter = (self.irq_counter & 0x00ff)
+                    | (((val & 0x7f) as u16) << 8);
+                self.irq_enabled = (val & 0b1000_0000) != 0;
+                self.irq_flag = false;
+            },
+
+            // SRAM
+            0x6000 ..= 0x7fff => {
+                if self.sram_writable(address) {
+                    self.sram.write(address, val);
+                }
+            },
+
+            // CHR bank select, one register per 1KB of pattern table
+            0x8000 ..= 0xbfff => {
+                self.chr_banks[(address as usize - 0x8000) / 0x800] = val;
+                self.update_chr_banks();
+            },
+
+            // Nametable select, one register per nametable
+            0xc000 ..= 0xdfff => {
+                self.nt_banks[(address as usize - 0xc000) / 0x800] = val;
+                self.update_chr_banks();
+            },
+
+            0xe000 ..= 0xe7ff => {
+                // 7  bit  0
+                // ---- ----
+                // .SPP PPPP
+                //  ||| ||||
+                //  |++-++++- Select 8KB PRG-ROM bank at $8000-$9FFF
+                //  +-------- Disable sound
+                self.prg.map(0x8000, PRG_BANK_SIZE, (val & 0b0011_1111) as usize);
+                self.audio.disabled = (val & 0b0100_0000) != 0;
+            },
+            0xe800 ..= 0xefff => {
+                // 7  bit  0
+                // ---- ----
+                // HLPP PPPP
+                // |||| ||||
+                // ||++-++++- Select 8KB PRG-ROM bank at $A000-$BFFF
+                // |+-------- Disable CIRAM at $0000-$0FFF
+                // +--------- Disable CIRAM at $1000-$1FFF
+                self.prg.map(0xa000, PRG_BANK_SIZE, (val & 0b0011_1111) as usize);
+                self.chr_ram_disabled[0] = (val & 0b0100_0000) != 0;
+                self.chr_ram_disabled[1] = (val & 0b1000_0000) != 0;
+                self.update_chr_banks();
+            },
+            0xf000 ..= 0xf7ff => {
+                // Select 8KB PRG-ROM bank at $C000-$DFFF
+                self.prg.map(0xc000, PRG_BANK_SIZE, (val & 0b0011_1111) as usize);
+            },
+            0xf800 ..= 0xffff => {
+                // This register is shared between the PRG-RAM write protect,
+                // and the internal RAM's address port.
+                self.write_protect = val;
+                self.audio.write(address, val);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn read_nametable(&mut self, address: u16) -> Option<u8> {
+        let address = 0x2000 | (address & 0x0fff);
+
+        if self.nt.is_mapped(address) {
+            self.nt.read(address)
+        } else {
+            let bank = self.nt_banks[(address as usize - 0x2000) / 0x400];
+            Some(self.vram[Self::vram_index(bank, address)])
+        }
+    }
+
+    fn write_nametable(&mut self, address: u16, val: u8) -> bool {
+        let address = 0x2000 | (address & 0x0fff);
+
+        // Writes to nametables that are mapped to CHR-ROM are dropped
+        if !self.nt.is_mapped(address) {
+            let bank = self.nt_banks[(address as usize - 0x2000) / 0x400];
+            self.vram[Self::vram_index(bank, address)] = val;
+        }
+
+        true
+    }
+
+    fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::CPUTick(cycles) => {
+                self.step_irq_counter(cycles);
+
+                for _ in 0 .. cycles {
+                    self.audio.step();
+                }
+            },
+            _ => { },
+        }
+    }
+
+    fn signal(&self) -> f32 {
+        self.audio.signal()
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn save_battery(&self, output: &mut File) -> io::Result<()> {
+        output.write_all(self.sram.data())?;
+        output.write_all(self.audio.ram())?;
+        Ok(())
+    }
+
+    fn load_battery(&mut self, input: &mut File) -> io::Result<()> {
+        input.read_exact(self.sram.data_mut())?;
+        input.read_exact(self.audio.ram_mut())?;
+        Ok(())
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        self.nt.save(output)?;
+        output.write_all(&self.vram)?;
+        output.write_all(&self.chr_banks)?;
+        output.write_all(&self.nt_banks)?;
+        serde::encode_u8(output, self.chr_ram_disabled[0] as u8)?;
+        serde::encode_u8(output, self.chr_ram_disabled[1] as u8)?;
+        serde::encode_u8(output, self.write_protect)?;
+        serde::encode_u16(output, self.irq_counter)?;
+        serde::encode_u8(output, self.irq_enabled as u8)?;
+        serde::encode_u8(output, self.irq_flag as u8)?;
+        self.audio.save(output)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.nt.load(input)?;
+        input.read_exact(&mut self.vram)?;
+        input.read_exact(&mut self.chr_banks)?;
+        input.read_exact(&mut self.nt_banks)?;
+        self.chr_ram_disabled[0] = serde::decode_u8(input)? != 0;
+        self.chr_ram_disabled[1] = serde::decode_u8(input)? != 0;
+        self.write_protect = serde::decode_u8(input)?;
+        self.irq_counter = serde::decode_u16(input)?;
+        self.irq_enabled = serde::decode_u8(input)? != 0;
+        self.irq_flag = serde::decode_u8(input)? != 0;
+        self.audio.load(input)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_mapper() -> Mapper19 {
+        Mapper19::new_mapper(Cartridge::new_cartridge(vec![0; 0x8000], vec![0; 0x2000], 0))
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut mapper = new_mapper();
+        mapper.write(0x5000, 0xfd);
+        mapper.write(0x5800, 0xff);
+        assert_eq!(mapper.read(0x5000), Some(0xfd));
+        assert_eq!(mapper.read(0x5800), Some(0xff));
+
+        mapper.notify(MapperEvent::CPUTick(1));
+        assert!(!mapper.irq_flag());
+
+        // The counter stops at $7FFF, and raises an IRQ
+        mapper.notify(MapperEvent::CPUTick(5));
+        assert!(mapper.irq_flag());
+        assert_eq!(mapper.read(0x5000), Some(0xff));
+        assert_eq!(mapper.read(0x5800), Some(0xff));
+
+        // Writing either half acknowledges it
+        mapper.write(0x5000, 0);
+        assert!(!mapper.irq_flag());
+
+        // And nothing counts while it's disabled
+        mapper.write(0x5800, 0x00);
+        mapper.notify(MapperEvent::CPUTick(10));
+        assert_eq!(mapper.read(0x5000), Some(0));
+    }
+
+    #[test]
+    fn test_sound_ram_port() {
+        let mut mapper = new_mapper();
+
+        // With auto-increment, each access moves on to the next byte
+        mapper.write(0xf800, 0x80 | 0x7e);
+        mapper.write(0x4800, 1);
+        mapper.write(0x4800, 2);
+        mapper.write(0x4800, 3);
+        assert_eq!(mapper.audio.ram()[0x7e ..], [1, 2]);
+        assert_eq!(mapper.audio.ram()[0], 3);
+
+        mapper.write(0xf800, 0x80 | 0x7e);
+        assert_eq!(mapper.read(0x4800), Some(1));
+        assert_eq!(mapper.read(0x4800), Some(2));
+
+        // Without it, the address stays put
+        mapper.write(0xf800, 0x7e);
+        assert_eq!(mapper.read(0x4800), Some(1));
+        assert_eq!(mapper.read(0x4800), Some(1));
+    }
+
+    #[test]
+    fn test_write_protect() {
+        let mut mapper = new_mapper();
+
+        // Nothing's writable unless the high nybble is 0100
+        mapper.write(0xf800, 0x00);
+        mapper.write(0x6000, 1);
+        assert_eq!(mapper.read(0x6000), Some(0));
+
+        mapper.write(0xf800, 0x40);
+        mapper.write(0x6000, 1);
+        assert_eq!(mapper.read(0x6000), Some(1));
+
+        // And then each bit protects 2KB
+        mapper.write(0xf800, 0x41);
+        mapper.write(0x6000, 2);
+        mapper.write(0x6800, 2);
+        assert_eq!(mapper.read(0x6000), Some(1));
+        assert_eq!(mapper.read(0x6800), Some(2));
+    }
+}
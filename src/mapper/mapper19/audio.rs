@@ -0,0 +1,240 @@
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mem::Memory;
+use crate::serde;
+
+// https://wiki.nesdev.com/w/index.php/Namco_163_audio
+//
+// The N163 has 128 bytes of internal RAM, which holds both the waveforms (as
+// packed 4-bit samples) and the registers for up to eight channels. The
+// channel registers live in the top of the RAM, at $40-$7F, 8 bytes each:
+//
+//   $x0  frequency, bits 0-7
+//   $x1  phase, bits 0-7
+//   $x2  frequency, bits 8-15
+//   $x3  phase, bits 8-15
+//   $x4  LLLL LLFF  wave length (256 - L*4 samples), frequency bits 16-17
+//   $x5  phase, bits 16-23
+//   $x6  wave address, in 4-bit samples
+//   $x7  .... VVVV  volume
+//
+// The high nybble of $7F (which is also channel 7's volume register) holds
+// the number of enabled channels, minus one.
+
+// Only one channel is updated at a time, taking 15 CPU cycles each
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+pub struct N163Audio {
+    ram: [u8; 0x80],
+
+    // The address port at $F800
+    address: u8,
+    auto_increment: bool,
+
+    // Sound disable, from bit 6 of $E000
+    pub disabled: bool,
+
+    cycles: u8,
+    channel: usize,
+
+    // The last output of each channel. Hardware time-multiplexes these, but
+    // averaging them sounds the same without the high-pitched whine.
+    outputs: [i16; 8],
+}
+
+impl Memory for N163Audio {
+    fn read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4800 ..= 0x4fff => {
+                let val = self.ram[self.address as usize];
+                self.step_address();
+                val
+            },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            0x4800 ..= 0x4fff => {
+                self.ram[self.address as usize] = val;
+                self.step_address();
+            },
+            0xf800 ..= 0xffff => {
+                // 7  bit  0
+                // ---- ----
+                // IAAA AAAA
+                // |||| ||||
+                // |+++-++++- Address in internal RAM
+                // +--------- Auto-increment
+                self.address        = val & 0b0111_1111;
+                self.auto_increment = (val & 0b1000_0000) != 0;
+            },
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        output.write_all(&self.ram)?;
+        serde::encode_u8(output, self.address)?;
+        serde::encode_u8(output, self.auto_increment as u8)?;
+        serde::encode_u8(output, self.disabled as u8)?;
+        serde::encode_u8(output, self.cycles)?;
+        serde::encode_usize(output, self.channel)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        input.read_exact(&mut self.ram)?;
+        self.address = serde::decode_u8(input)?;
+        self.auto_increment = serde::decode_u8(input)? != 0;
+        self.disabled = serde::decode_u8(input)? != 0;
+        self.cycles = serde::decode_u8(input)?;
+        self.channel = serde::decode_usize(input)?;
+        self.outputs = [0; 8];
+        Ok(())
+    }
+}
+
+impl N163Audio {
+    pub fn new_audio() -> Self {
+        Self {
+            ram: [0; 0x80],
+
+            address: 0,
+            auto_increment: false,
+
+            disabled: false,
+
+            cycles: 0,
+            channel: 7,
+
+            outputs: [0; 8],
+        }
+    }
+
+    // The internal RAM is battery-backed on some boards, along with PRG-RAM
+    pub fn ram(&self) -> &[u8; 0x80] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8; 0x80] {
+        &mut self.ram
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.address = (self.address + 1) & 0x7f;
+        }
+    }
+
+    fn n_channels(&self) -> usize {
+        ((self.ram[0x7f] >> 4) & 0x07) as usize + 1
+    }
+
+    fn sample(&self, index: u8) -> i16 {
+        // Samples are packed two per byte, low nybble first
+        let byte = self.ram[(index >> 1) as usize & 0x7f];
+
+        if index & 1 == 0 {
+            (byte & 0x0f) as i16
+        } else {
+            (byte >> 4) as i16
+        }
+    }
+
+    fn step_channel(&mut self) {
+        let base = 0x40 + self.channel * 8;
+        let reg = |i: usize| self.ram[base + i] as u32;
+
+        let frequency = reg(0) | (reg(2) << 8) | ((reg(4) & 0x03) << 16);
+        let mut phase = reg(1) | (reg(3) << 8) | (reg(5) << 16);
+        let length    = 256 - (reg(4) & 0xfc);
+        let offset    = reg(6);
+        let volume    = (reg(7) & 0x0f) as i16;
+
+        phase = (phase + frequency) % (length << 16);
+
+        self.ram[base + 1] =  phase        as u8;
+        self.ram[base + 3] = (phase >> 8)  as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        let index = ((phase >> 16) + offset) as u8;
+        self.outputs[self.channel] = (self.sample(index) - 8) * volume;
+    }
+
+    pub fn step(&mut self) {
+        self.cycles += 1;
+
+        if self.cycles < CYCLES_PER_CHANNEL {
+            return;
+        }
+
+        self.cycles = 0;
+
+        if self.disabled {
+            return;
+        }
+
+        self.step_channel();
+
+        // Channels are updated from 7 downwards, wrapping back around to 7
+        // after the last enabled channel.
+        let first = 8 - self.n_channels();
+        if self.channel <= first {
+            self.channel = 7;
+        } else {
+            self.channel -= 1;
+        }
+    }
+
+    pub fn signal(&self) -> f32 {
+        if self.disabled {
+            return 0.0;
+        }
+
+        let n = self.n_channels();
+        let sum: i16 = self.outputs[8 - n ..].iter().sum();
+
+        (sum as f32 / n as f32) * 0.002
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_channels(audio: &mut N163Audio, n: usize) {
+        for _ in 0 .. n * CYCLES_PER_CHANNEL as usize {
+            audio.step();
+        }
+    }
+
+    #[test]
+    fn test_channel_count() {
+        let mut audio = N163Audio::new_audio();
+
+        // Channel 6 plays a flat wave of 15s at full volume, from a 32 sample
+        // waveform at address 0. Channel 7 is silent.
+        for byte in audio.ram_mut()[0 .. 16].iter_mut() {
+            *byte = 0xff;
+        }
+        audio.ram_mut()[0x74] = 0xe0;
+        audio.ram_mut()[0x77] = 0x0f;
+
+        // With one channel enabled, only channel 7 is played
+        step_channels(&mut audio, 2);
+        assert_eq!(audio.signal(), 0.0);
+
+        // With two, channel 6 is played too, and the two are averaged
+        audio.ram_mut()[0x7f] = 0x10;
+        step_channels(&mut audio, 2);
+        assert_eq!(audio.signal(), (7.0 * 15.0 / 2.0) * 0.002);
+
+        // Channel 6 is left out again once it's disabled
+        audio.ram_mut()[0x7f] = 0x00;
+        assert_eq!(audio.signal(), 0.0);
+    }
+}
@@ -1,10 +1,10 @@
-use std::io::{Read, Write};
 use std::io;
 use std::fs::File;
 
 use crate::mapper::Mapper;
 use crate::mapper::MirrorMode;
-use crate::serde;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
 
 const PRG_BANK_SIZE: usize = 16384;
 
@@ -12,29 +12,34 @@ const PRG_BANK_SIZE: usize = 16384;
 // UxROM (mapper 2)
 //
 pub struct Mapper2 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
-
-    prg_bank1: u8,
-    prg_bank2: u8,
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
 
     mirror_mode: MirrorMode,
+
+    // Whether writes to PRG-ROM collide with the ROM's own output
+    bus_conflicts: bool,
 }
 
 impl Mapper2 {
-    pub fn new_mapper(rom: Vec<u8>, vrom: Vec<u8>, mirror_mode: u8) -> Self {
-        let n_banks = rom.len() / PRG_BANK_SIZE;
+    pub fn new_mapper(cart: Cartridge,
+                      bus_conflicts: bool)
+        -> Self
+    {
+        let mut prg = Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE);
+        let last_bank = prg.last_bank(PRG_BANK_SIZE);
+        prg.map(0x8000, PRG_BANK_SIZE, 1);
+        prg.map(0xc000, PRG_BANK_SIZE, last_bank);
 
         Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: prg,
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
 
-            prg_bank1: 1,
-            prg_bank2: n_banks as u8 - 1,
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
 
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
+            bus_conflicts: bus_conflicts,
         }
     }
 }
@@ -44,60 +49,70 @@ impl Mapper for Mapper2 {
         &self.mirror_mode
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => self.chr_rom[address as usize],
+            0x0000 ..= 0x1fff => self.chr.read(address),
 
             // SRAM
-            0x6000 ..= 0x7fff => self.sram[address as usize - 0x6000],
+            0x6000 ..= 0x7fff => self.sram.read(address),
 
             // PRG-ROM
-            0x8000 ..= 0xbfff => {
-                let index = (self.prg_bank1 as usize * PRG_BANK_SIZE)
-                          + (address as usize - 0x8000);
-                self.prg_rom[index]
-            },
-            0xc000 ..= 0xffff => {
-                let index = (self.prg_bank2 as usize * PRG_BANK_SIZE)
-                          + (address as usize - 0xc000);
-                self.prg_rom[index]
-            },
+            0x8000 ..= 0xffff => self.prg.read(address),
 
-            _ => 0,
+            _ => None,
         }
     }
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => { self.chr_rom[address as usize] = val },
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
 
             // SRAM
-            0x6000 ..= 0x7fff => { self.sram[address as usize - 0x6000] = val },
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
 
             // PRG-ROM
-            0x8000 ..= 0xffff => { self.prg_bank1 = val & 0x0f },
+            0x8000 ..= 0xffff => {
+                let val = if self.bus_conflicts { val & self.read(address).unwrap_or(val) } else { val };
+                self.prg.map(0x8000, PRG_BANK_SIZE, (val & 0x0f) as usize);
+            },
 
             _ => { },
         }
     }
 
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
-        serde::encode_u8(output, self.prg_bank1)?;
-        serde::encode_u8(output, self.prg_bank2)?;
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
-        self.prg_bank1 = serde::decode_u8(input)?;
-        self.prg_bank2 = serde::decode_u8(input)?;
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = banked(8, PRG_BANK_SIZE);
+        rom[7 * PRG_BANK_SIZE] = 0b0000_0110;
+
+        let mut mapper = Mapper2::new_mapper(Cartridge::new_cartridge(rom.clone(), vec![0; 0x2000], 0), true);
+        mapper.write(0xc000, 0b0000_0011);
+        assert_eq!(mapper.read(0x8000), Some(2));
+
+        let mut mapper = Mapper2::new_mapper(Cartridge::new_cartridge(rom, vec![0; 0x2000], 0), false);
+        mapper.write(0xc000, 0b0000_0011);
+        assert_eq!(mapper.read(0x8000), Some(3));
+    }
+}
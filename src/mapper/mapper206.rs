@@ -0,0 +1,187 @@
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+
+//
+// Namco 108/DxROM (mapper 206)
+//
+// The predecessor to the MMC3, with the same bank registers but without the
+// PRG/CHR mode bits, mirroring control, PRG-RAM or IRQs.
+//
+pub struct Mapper206 {
+    chr: Banks,
+    prg: Banks,
+    mirror_mode: MirrorMode,
+
+    regs: [u8; 8],
+    index: usize,
+}
+
+impl Mapper206 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        let mut mapper = Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            regs: [0; 8],
+            index: 0,
+        };
+
+        mapper.update_banks();
+        mapper
+    }
+
+    fn update_banks(&mut self) {
+        // Two 2KB banks, then four 1KB banks
+        self.chr.map(0x0000, CHR_BANK_SIZE, self.regs[0] as usize & 0xfe);
+        self.chr.map(0x0400, CHR_BANK_SIZE, self.regs[0] as usize | 0x01);
+        self.chr.map(0x0800, CHR_BANK_SIZE, self.regs[1] as usize & 0xfe);
+        self.chr.map(0x0c00, CHR_BANK_SIZE, self.regs[1] as usize | 0x01);
+
+        for i in 0 .. 4 {
+            let address = 0x1000 + (i * CHR_BANK_SIZE) as u16;
+            self.chr.map(address, CHR_BANK_SIZE, self.regs[2 + i] as usize);
+        }
+
+        // The last two PRG banks are fixed
+        let n_banks = self.prg.n_banks(PRG_BANK_SIZE);
+
+        self.prg.map(0x8000, PRG_BANK_SIZE, self.regs[6] as usize);
+        self.prg.map(0xa000, PRG_BANK_SIZE, self.regs[7] as usize);
+        self.prg.map(0xc000, PRG_BANK_SIZE, (n_banks * 2 - 2) % n_banks);
+        self.prg.map(0xe000, PRG_BANK_SIZE, n_banks - 1);
+    }
+}
+
+impl Mapper for Mapper206 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // Only $8000-$9FFF is decoded
+            0x8000 ..= 0x9fff => {
+                if address & 1 == 0 {
+                    // Bank select
+                    self.index = val as usize & 0x07;
+                } else {
+                    // Bank data, with 6 bits of CHR bank and 4 bits of PRG
+                    self.regs[self.index] = match self.index {
+                        0 ..= 5 => val & 0x3f,
+                        _       => val & 0x0f,
+                    };
+
+                    self.update_banks();
+                }
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        output.write_all(&self.regs)?;
+        serde::encode_usize(output, self.index)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        input.read_exact(&mut self.regs)?;
+        self.index = serde::decode_usize(input)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper206::new_mapper(Cartridge::new_cartridge(banked(16, PRG_BANK_SIZE), banked(64, CHR_BANK_SIZE), 0));
+
+        assert_eq!(mapper.read(0xc000), Some(14));
+        assert_eq!(mapper.read(0xe000), Some(15));
+
+        // 2KB bank, ignoring the low bit
+        mapper.write(0x8000, 0);
+        mapper.write(0x8001, 5);
+        assert_eq!(mapper.read(0x0000), Some(4));
+        assert_eq!(mapper.read(0x0400), Some(5));
+
+        // 1KB bank
+        mapper.write(0x8000, 5);
+        mapper.write(0x8001, 0x3f);
+        assert_eq!(mapper.read(0x1c00), Some(0x3f));
+
+        mapper.write(0x8000, 6);
+        mapper.write(0x8001, 3);
+        mapper.write(0x8000, 7);
+        mapper.write(0x8001, 9);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(mapper.read(0xa000), Some(9));
+
+        // The MMC3's mode bits aren't there
+        mapper.write(0x8000, 0xc0);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(mapper.read(0x0000), Some(4));
+    }
+
+    #[test]
+    fn test_register_range() {
+        let mut mapper = Mapper206::new_mapper(Cartridge::new_cartridge(banked(16, PRG_BANK_SIZE), banked(64, CHR_BANK_SIZE), 0));
+
+        // Mirrored throughout $8000-$9FFF by A0
+        mapper.write(0x9ffe, 6);
+        mapper.write(0x8ff1, 5);
+        assert_eq!(mapper.read(0x8000), Some(5));
+
+        // Only 4 bits of PRG bank
+        mapper.write(0x9fff, 0xf3);
+        assert_eq!(mapper.read(0x8000), Some(3));
+
+        // $A000-$FFFF isn't decoded, so the mirroring and IRQ registers of
+        // the MMC3 don't do anything
+        mapper.write(0xa000, 1);
+        mapper.write(0xe000, 6);
+        mapper.write(0xe001, 9);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(*mapper.mirror_mode() as u8, MirrorMode::Horizontal as u8);
+        assert!(!mapper.irq_flag());
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mapper::{Mapper, MapperEvent};
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 16384;
+const CHR_BANK_SIZE: usize = 8192;
+
+//
+// 52-in-1/64-in-1/72-in-1 multicarts (mapper 225)
+//
+pub struct Mapper225 {
+    chr: Banks,
+    prg: Banks,
+    mirror_mode: MirrorMode,
+
+    // Four nybbles of RAM at $5800-$5FFF, which some menus use to keep track
+    // of the selected game.
+    ram: [u8; 4],
+}
+
+impl Mapper225 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            ram: [0; 4],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.write(0x8000, 0);
+    }
+}
+
+impl Mapper for Mapper225 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // RAM
+            0x5800 ..= 0x5fff => Some(self.ram[address as usize & 0x03] & 0x0f),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // RAM
+            0x5800 ..= 0x5fff => { self.ram[address as usize & 0x03] = val & 0x0f },
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                // A~[1HMO PPPP PPCC CCCC]
+                //     |||   ||| |||| ||||
+                //     |||   ||| ||++-++++- Select 8 KB CHR ROM bank
+                //     |||   +++-++-------- Select 16 KB PRG ROM bank
+                //     ||+----------------- PRG mode (0: 32 KB, 1: 16 KB)
+                //     |+------------------ Mirroring (0: vertical, 1: horizontal)
+                //     +------------------- Outer bank, for both PRG and CHR
+                let outer = ((address >> 14) & 1) as usize;
+                let bank  = ((address >> 6) & 0x3f) as usize | (outer << 6);
+
+                self.chr.map(0x0000, CHR_BANK_SIZE, (address & 0x3f) as usize | (outer << 6));
+
+                if (address & 0x1000) != 0 {
+                    self.prg.map(0x8000, PRG_BANK_SIZE, bank);
+                    self.prg.map(0xc000, PRG_BANK_SIZE, bank);
+                } else {
+                    self.prg.map(0x8000, PRG_BANK_SIZE * 2, bank >> 1);
+                }
+
+                self.mirror_mode = MirrorMode::from_vh01(((address >> 13) & 1) as u8);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::Reset => { self.reset() },
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        output.write(&self.ram)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        input.read(&mut self.ram)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper225::new_mapper(Cartridge::new_cartridge(banked(128, PRG_BANK_SIZE), banked(128, CHR_BANK_SIZE), 0));
+
+        // 16KB mode, outer bank 1
+        mapper.write(0x8000 | 0x4000 | 0x1000 | (5 << 6) | 3, 0);
+        assert_eq!(mapper.read(0x8000), Some(69));
+        assert_eq!(mapper.read(0xc000), Some(69));
+        assert_eq!(mapper.read(0x0000), Some(67));
+
+        // 32KB mode
+        mapper.write(0x8000 | (5 << 6), 0);
+        assert_eq!(mapper.read(0x8000), Some(4));
+        assert_eq!(mapper.read(0xc000), Some(5));
+
+        mapper.write(0x5801, 0xff);
+        assert_eq!(mapper.read(0x5805), Some(0x0f));
+
+        mapper.notify(MapperEvent::Reset);
+        assert_eq!(mapper.read(0x8000), Some(0));
+        assert_eq!(mapper.read(0xc000), Some(1));
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mapper::{Mapper, MapperEvent};
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 16384;
+
+//
+// 76-in-1/42-in-1 multicarts (mapper 226)
+//
+pub struct Mapper226 {
+    chr: Banks,
+    prg: Banks,
+    mirror_mode: MirrorMode,
+
+    // Registers at $8000 and $8001
+    regs: [u8; 2],
+}
+
+impl Mapper226 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        let mut mapper = Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            regs: [0; 2],
+        };
+
+        mapper.update_banks();
+        mapper
+    }
+
+    fn update_banks(&mut self) {
+        let bank = (self.regs[0] & 0b0001_1111) as usize
+                 | ((self.regs[0] & 0b1000_0000) as usize >> 2)
+                 | ((self.regs[1] & 0b0000_0001) as usize) << 6;
+
+        if (self.regs[0] & 0b0010_0000) != 0 {
+            // 16 KB mode, mirrored at $C000
+            self.prg.map(0x8000, PRG_BANK_SIZE, bank);
+            self.prg.map(0xc000, PRG_BANK_SIZE, bank);
+        } else {
+            // 32 KB mode
+            self.prg.map(0x8000, PRG_BANK_SIZE * 2, bank >> 1);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.regs = [0; 2];
+        self.mirror_mode = MirrorMode::Vertical;
+        self.update_banks();
+    }
+}
+
+impl Mapper for Mapper226 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                if address & 1 == 0 {
+                    // 7  bit  0
+                    // ---- ----
+                    // HMOP PPPP
+                    // |||| ||||
+                    // |||+-++++- Select PRG ROM bank, bits 0-4
+                    // ||+------- PRG mode (0: 32 KB, 1: 16 KB)
+                    // |+-------- Mirroring (0: vertical, 1: horizontal)
+                    // +--------- Select PRG ROM bank, bit 5
+                    self.regs[0] = val;
+                    self.mirror_mode = MirrorMode::from_vh01((val >> 6) & 1);
+                } else {
+                    // 7  bit  0
+                    // ---- ----
+                    // xxxx xxxH
+                    //         |
+                    //         +- Select PRG ROM bank, bit 6
+                    self.regs[1] = val;
+                }
+
+                self.update_banks();
+            },
+
+            _ => { },
+        }
+    }
+
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::Reset => { self.reset() },
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        output.write(&self.regs)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        input.read(&mut self.regs)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper226::new_mapper(Cartridge::new_cartridge(banked(128, PRG_BANK_SIZE), vec![0; 0x2000], 0));
+
+        // 16KB mode, bank 0b1_1_00101
+        mapper.write(0x8000, 0b1010_0101);
+        mapper.write(0x8001, 1);
+        assert_eq!(mapper.read(0x8000), Some(101));
+        assert_eq!(mapper.read(0xc000), Some(101));
+
+        // 32KB mode
+        mapper.write(0x8000, 0b0000_0101);
+        mapper.write(0x8001, 0);
+        assert_eq!(mapper.read(0x8000), Some(4));
+        assert_eq!(mapper.read(0xc000), Some(5));
+
+        mapper.notify(MapperEvent::Reset);
+        assert_eq!(mapper.read(0x8000), Some(0));
+        assert_eq!(mapper.read(0xc000), Some(1));
+    }
+}
@@ -0,0 +1,241 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::{Mapper, MapperEvent};
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 16384;
+
+//
+// 1200-in-1 and similar multicarts (mapper 227)
+//
+pub struct Mapper227 {
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+    mirror_mode: MirrorMode,
+
+    // The whole register is latched from the address that was written to
+    latch: u16,
+
+    // Some boards have solder pads, which the menu reads to decide which set
+    // of games to list.
+    solder_pads: u8,
+}
+
+impl Mapper227 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        let mut mapper = Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            latch: 0,
+
+            solder_pads: 0,
+        };
+
+        mapper.update_banks();
+        mapper
+    }
+
+    fn update_banks(&mut self) {
+        let low = self.prg_bank(0x8000);
+        let high = self.prg_bank(0xc000);
+
+        self.prg.map(0x8000, PRG_BANK_SIZE, low);
+        self.prg.map(0xc000, PRG_BANK_SIZE, high);
+    }
+
+    fn prg_bank(&self, address: u16) -> usize {
+        let size  = (self.latch & 0x0001) != 0;
+        let bank  = ((self.latch >> 2) & 0x1f) as usize | ((self.latch & 0x0100) >> 3) as usize;
+        let nrom  = (self.latch & 0x0080) != 0;
+        let last  = (self.latch & 0x0200) != 0;
+        let upper = address >= 0xc000;
+
+        match (nrom, size, upper) {
+            // NROM-256
+            (true, true, _) => (bank & !1) | upper as usize,
+
+            // NROM-128
+            (true, false, _) => bank,
+
+            // UNROM, with the first or last bank of the 128 KB block fixed
+            // at $C000
+            (false, _, false) => if size { bank & !1 } else { bank },
+            (false, _, true)  => if last { bank | 0x07 } else { bank & 0x38 },
+        }
+    }
+
+    fn reset(&mut self) {
+        self.latch = 0;
+        self.mirror_mode = MirrorMode::Vertical;
+        self.update_banks();
+    }
+}
+
+impl Mapper for Mapper227 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM. With bit 10 set, the solder pads are connected to the
+            // low address lines.
+            0x8000 ..= 0xffff => {
+                let address = if (self.latch & 0x0400) != 0 {
+                    address | self.solder_pads as u16
+                } else {
+                    address
+                };
+
+                self.prg.read(address)
+            },
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-RAM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                // A~[1... .DLP OPPP PPMS]
+                //          ||| |||| ||||
+                //          ||| |||| |||+- PRG size (0: 16 KB, 1: 32 KB)
+                //          ||| |||| ||+-- Mirroring (0: vertical, 1: horizontal)
+                //          ||| |+++-++--- Select 16 KB PRG ROM bank, bits 0-4
+                //          ||| +--------- PRG mode (0: UNROM, 1: NROM)
+                //          ||+----------- Select 16 KB PRG ROM bank, bit 5
+                //          |+------------ Fix the last (1) or first (0) bank at $C000
+                //          +------------- Read the solder pads
+                self.latch = address & 0x07ff;
+                self.mirror_mode = MirrorMode::from_vh01(((address >> 1) & 1) as u8);
+                self.update_banks();
+            },
+
+            _ => { },
+        }
+    }
+
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::Reset => { self.reset() },
+            _ => { },
+        }
+    }
+
+    fn dip_switches(&self) -> u8 {
+        self.solder_pads
+    }
+
+    fn dip_switch_settings(&self) -> u8 {
+        4
+    }
+
+    fn set_dip_switches(&mut self, val: u8) {
+        self.solder_pads = val & 0x03;
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        serde::encode_u16(output, self.latch)?;
+        serde::encode_u8(output, self.solder_pads)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        self.latch = serde::decode_u16(input)?;
+        self.solder_pads = serde::decode_u8(input)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper227::new_mapper(Cartridge::new_cartridge(banked(64, PRG_BANK_SIZE), vec![0; 0x2000], 0));
+
+        // UNROM, with the first bank of the block at $C000
+        mapper.write(0x8000 | (11 << 2), 0);
+        assert_eq!(mapper.read(0x8000), Some(11));
+        assert_eq!(mapper.read(0xc000), Some(8));
+
+        // ... and with the last
+        mapper.write(0x8200 | (11 << 2), 0);
+        assert_eq!(mapper.read(0xc000), Some(15));
+
+        // NROM-256 in the upper half of the ROM
+        mapper.write(0x8181 | (3 << 2), 0);
+        assert_eq!(mapper.read(0x8000), Some(34));
+        assert_eq!(mapper.read(0xc000), Some(35));
+
+        mapper.notify(MapperEvent::Reset);
+        assert_eq!(mapper.read(0x8000), Some(0));
+        assert_eq!(mapper.read(0xc000), Some(0));
+    }
+
+    #[test]
+    fn test_solder_pads() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000 .. 0x0004].copy_from_slice(&[0, 1, 2, 3]);
+
+        let mut mapper = Mapper227::new_mapper(Cartridge::new_cartridge(rom, vec![0; 0x2000], 0));
+        mapper.set_dip_switches(2);
+
+        assert_eq!(mapper.read(0x8000), Some(0));
+        mapper.write(0x8400, 0);
+        assert_eq!(mapper.read(0x8000), Some(2));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0000 .. 0x0004].copy_from_slice(&[0, 1, 2, 3]);
+
+        let mut mapper = Mapper227::new_mapper(Cartridge::new_cartridge(rom.clone(), vec![0; 0x2000], 0));
+        mapper.set_dip_switches(3);
+        mapper.write(0x8400, 0);
+
+        let path = std::env::temp_dir().join("mapper227_test_save_load.state");
+        mapper.save(&mut File::create(&path).unwrap()).unwrap();
+
+        let mut loaded = Mapper227::new_mapper(Cartridge::new_cartridge(rom, vec![0; 0x2000], 0));
+        loaded.load(&mut File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.read(0x8000), Some(3));
+        assert_eq!(loaded.dip_switches(), 3);
+    }
+}
@@ -0,0 +1,160 @@
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mapper::{Mapper, MapperEvent};
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 16384;
+const CHR_BANK_SIZE: usize = 8192;
+
+//
+// Active Enterprises, Action 52 and Cheetahmen II (mapper 228)
+//
+pub struct Mapper228 {
+    chr: Banks,
+    prg: Banks,
+    mirror_mode: MirrorMode,
+
+    // Four nybbles of RAM, mirrored throughout $4020-$5FFF
+    ram: [u8; 4],
+}
+
+impl Mapper228 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            ram: [0; 4],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.write(0x8000, 0);
+    }
+}
+
+impl Mapper for Mapper228 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // RAM
+            0x4020 ..= 0x5fff => Some(self.ram[address as usize & 0x03] & 0x0f),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // RAM
+            0x4020 ..= 0x5fff => { self.ram[address as usize & 0x03] = val & 0x0f },
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                // A~[1.MH HPPP PPOC CCCC]
+                //      ||| |||| |||| ||||
+                //      ||| |||| ||++-++++- Select 8 KB CHR ROM bank, bits 2-5
+                //      ||| |||| |+-------- PRG mode (0: 32 KB, 1: 16 KB)
+                //      ||| ++++-+--------- Select 16 KB PRG ROM bank
+                //      |++---------------- Select PRG ROM chip
+                //      +------------------ Mirroring (0: vertical, 1: horizontal)
+                //
+                // D~[.... ..CC]
+                //           ||
+                //           ++- Select 8 KB CHR ROM bank, bits 0-1
+                //
+                // There are only three 512 KB PRG-ROM chips, with chip 2
+                // missing, so chip 3 is stored straight after chip 1.
+                let mut chip = ((address >> 11) & 0x03) as u8;
+                if chip == 3 {
+                    chip = 2;
+                }
+
+                let bank = ((chip as usize) << 5) | ((address >> 6) & 0x1f) as usize;
+
+                if (address & 0x0020) != 0 {
+                    self.prg.map(0x8000, PRG_BANK_SIZE, bank);
+                    self.prg.map(0xc000, PRG_BANK_SIZE, bank);
+                } else {
+                    self.prg.map(0x8000, PRG_BANK_SIZE * 2, bank >> 1);
+                }
+
+                let chr_bank = ((address & 0x0f) << 2) as usize | (val & 0x03) as usize;
+                self.chr.map(0x0000, CHR_BANK_SIZE, chr_bank);
+                self.mirror_mode = MirrorMode::from_vh01(((address >> 13) & 1) as u8);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn notify(&mut self, event: MapperEvent) {
+        match event {
+            MapperEvent::Reset => { self.reset() },
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        output.write(&self.ram)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        input.read(&mut self.ram)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper228::new_mapper(Cartridge::new_cartridge(banked(96, PRG_BANK_SIZE), banked(64, CHR_BANK_SIZE), 0));
+
+        // Chip 3 follows on from chip 1
+        mapper.write(0x8000 | (3 << 11) | (4 << 6) | 0x0020 | 0x05, 0x02);
+        assert_eq!(mapper.read(0x8000), Some(68));
+        assert_eq!(mapper.read(0xc000), Some(68));
+        assert_eq!(mapper.read(0x0000), Some(22));
+
+        mapper.write(0x8000 | (1 << 11) | (4 << 6), 0);
+        assert_eq!(mapper.read(0x8000), Some(36));
+        assert_eq!(mapper.read(0xc000), Some(37));
+
+        mapper.write(0x4020, 0x1a);
+        assert_eq!(mapper.read(0x5ffc), Some(0x0a));
+
+        mapper.notify(MapperEvent::Reset);
+        assert_eq!(mapper.read(0x8000), Some(0));
+    }
+}
@@ -1,10 +1,10 @@
-use std::io::{Read, Write};
 use std::io;
 use std::fs::File;
 
 use crate::mapper::Mapper;
 use crate::mapper::MirrorMode;
-use crate::serde;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
 
 const CHR_BANK_SIZE: usize = 8192;
 
@@ -12,25 +12,29 @@ const CHR_BANK_SIZE: usize = 8192;
 // CNROM (mapper 3)
 //
 pub struct Mapper3 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
-
-    chr_bank: u8,
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
 
     mirror_mode: MirrorMode,
+
+    // Whether writes to PRG-ROM collide with the ROM's own output
+    bus_conflicts: bool,
 }
 
 impl Mapper3 {
-    pub fn new_mapper(rom: Vec<u8>, vrom: Vec<u8>, mirror_mode: u8) -> Self {
+    pub fn new_mapper(cart: Cartridge,
+                      bus_conflicts: bool)
+        -> Self
+    {
         Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, 0x8000),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
 
-            chr_bank: 0,
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
 
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
+            bus_conflicts: bus_conflicts,
         }
     }
 }
@@ -40,60 +44,71 @@ impl Mapper for Mapper3 {
         &self.mirror_mode
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => {
-                let bank = self.chr_bank as usize;
-                let index = (CHR_BANK_SIZE * bank) | address as usize & 0x1fff;
-                self.chr_rom[index]
-            },
+            0x0000 ..= 0x1fff => self.chr.read(address),
 
             // SRAM
-            0x6000 ..= 0x7fff => self.sram[address as usize - 0x6000],
+            0x6000 ..= 0x7fff => self.sram.read(address),
 
             // PRG-ROM
-            0x8000 ..= 0xffff => self.prg_rom[address as usize - 0x8000],
+            0x8000 ..= 0xffff => self.prg.read(address),
 
-            _ => 0,
+            _ => None,
         }
     }
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => {
-                let bank = self.chr_bank as usize;
-                let index = (CHR_BANK_SIZE * bank) | address as usize & 0x1fff;
-                self.chr_rom[index] = val;
-            },
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
 
             // SRAM
-            0x6000 ..= 0x7fff => { self.sram[address as usize - 0x6000] = val },
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
 
             // PRG-ROM
             0x8000 ..= 0xffff => {
                 // CNROM only uses the first 2 bits, but other boards may use
                 // the rest, apparently.
-                self.chr_bank = val & 0b0000_0011;
+                let val = if self.bus_conflicts { val & self.read(address).unwrap_or(val) } else { val };
+                self.chr.map(0x0000, CHR_BANK_SIZE, (val & 0b0000_0011) as usize);
             },
             _ =>  { },
         }
     }
 
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
-        serde::encode_u8(output, self.chr_bank)?;
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
-        self.chr_bank = serde::decode_u8(input)?;
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = vec![0xff; 0x8000];
+        rom[0] = 0b0000_0001;
+
+        let mut mapper = Mapper3::new_mapper(Cartridge::new_cartridge(rom.clone(), banked(4, CHR_BANK_SIZE), 0), true);
+        mapper.write(0x8000, 0b0000_0011);
+        assert_eq!(mapper.read(0x0000), Some(1));
+
+        let mut mapper = Mapper3::new_mapper(Cartridge::new_cartridge(rom, banked(4, CHR_BANK_SIZE), 0), false);
+        mapper.write(0x8000, 0b0000_0011);
+        assert_eq!(mapper.read(0x0000), Some(3));
+    }
+}
@@ -0,0 +1,198 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 32768;
+const CHR_BANK_SIZE: usize = 4096;
+
+// Two unrelated boards share mapper 34. NES 2.0 headers tell them apart with
+// the submapper, otherwise NINA-001 is the one with more than 8KB of CHR.
+// BNROM's register is discrete logic, so always has bus conflicts.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mapper34Board {
+    BNROM,
+    NINA001,
+}
+
+//
+// BNROM, NINA-001 (mapper 34)
+//
+pub struct Mapper34 {
+    board: Mapper34Board,
+
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    mirror_mode: MirrorMode,
+}
+
+impl Mapper34 {
+    pub fn new_mapper(cart: Cartridge,
+                      board: Mapper34Board)
+        -> Self
+    {
+        Self {
+            board: board,
+
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+        }
+    }
+}
+
+impl Mapper for Mapper34 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // SRAM, with the NINA-001's registers overlapping the last three
+            // bytes.
+            0x6000 ..= 0x7fff => {
+                self.sram.write(address, val);
+
+                if self.board == Mapper34Board::NINA001 {
+                    match address {
+                        // Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+                        0x7ffd => {
+                            self.prg.map(0x8000, PRG_BANK_SIZE, (val & 0b0000_0001) as usize);
+                        },
+
+                        // Select 4 KB CHR ROM bank for PPU $0000-$0FFF
+                        0x7ffe => {
+                            self.chr.map(0x0000, CHR_BANK_SIZE, (val & 0b0000_1111) as usize);
+                        },
+
+                        // Select 4 KB CHR ROM bank for PPU $1000-$1FFF
+                        0x7fff => {
+                            self.chr.map(0x1000, CHR_BANK_SIZE, (val & 0b0000_1111) as usize);
+                        },
+
+                        _ => { },
+                    }
+                }
+            },
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => {
+                // Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+                if self.board == Mapper34Board::BNROM {
+                    let val = val & self.read(address).unwrap_or(val);
+                    self.prg.map(0x8000, PRG_BANK_SIZE, val as usize);
+                }
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_bnrom() {
+        let mut rom = banked(4, PRG_BANK_SIZE);
+        rom[0x10] = 0xff;
+        let mut mapper = Mapper34::new_mapper(Cartridge::new_cartridge(rom, vec![0; 0x2000], 0), Mapper34Board::BNROM);
+
+        mapper.write(0x8010, 2);
+        assert_eq!(mapper.read(0x8000), Some(2));
+
+        // Bus conflicts
+        mapper.write(0x8000, 3);
+        assert_eq!(mapper.read(0x8000), Some(2));
+
+        // The NINA-001 registers don't exist
+        mapper.write(0x7ffd, 1);
+        assert_eq!(mapper.read(0x8000), Some(2));
+    }
+
+    #[test]
+    fn test_nina001() {
+        let mut mapper = Mapper34::new_mapper(Cartridge::new_cartridge(banked(2, PRG_BANK_SIZE), banked(16, CHR_BANK_SIZE), 0), Mapper34Board::NINA001);
+
+        mapper.write(0x7ffd, 1);
+        mapper.write(0x7ffe, 5);
+        mapper.write(0x7fff, 9);
+        assert_eq!(mapper.read(0x8000), Some(1));
+        assert_eq!(mapper.read(0x0000), Some(5));
+        assert_eq!(mapper.read(0x1000), Some(9));
+        assert_eq!(mapper.read(0x7fff), Some(9));
+
+        mapper.write(0x8000, 0);
+        assert_eq!(mapper.read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn test_nina001_chr() {
+        let mut mapper = Mapper34::new_mapper(Cartridge::new_cartridge(banked(2, PRG_BANK_SIZE), banked(16, CHR_BANK_SIZE), 0), Mapper34Board::NINA001);
+
+        // The two halves are switched independently, with 4 bits of bank
+        mapper.write(0x7ffe, 0xf3);
+        assert_eq!(mapper.read(0x0000), Some(3));
+        assert_eq!(mapper.read(0x0fff), Some(3));
+        assert_eq!(mapper.read(0x1000), Some(1));
+
+        mapper.write(0x7fff, 15);
+        assert_eq!(mapper.read(0x0000), Some(3));
+        assert_eq!(mapper.read(0x1000), Some(15));
+        assert_eq!(mapper.read(0x1fff), Some(15));
+
+        // CHR-ROM isn't writable
+        mapper.write(0x0000, 0x55);
+        assert_eq!(mapper.read(0x0000), Some(3));
+
+        // Only the last three bytes of SRAM are registers
+        mapper.write(0x7ffc, 7);
+        assert_eq!(mapper.read(0x0000), Some(3));
+        assert_eq!(mapper.read(0x1000), Some(15));
+        assert_eq!(mapper.read(0x7ffc), Some(7));
+    }
+}
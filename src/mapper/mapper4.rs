@@ -4,64 +4,213 @@ use std::fs::File;
 
 use crate::mapper::{Mapper, MapperEvent};
 use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
 use crate::serde;
 
 const PRG_BANK_SIZE: usize = 8192;
 const CHR_BANK_SIZE: usize = 1024;
 
+// The MMC3 only clocks its counter once A12 has been low for a few CPU
+// cycles, so that the short drops between sprite fetches are ignored.
+const A12_FILTER_DOTS: u16 = 10;
+
+// Boards built around the MMC3 core, which differ in how PRG-RAM, CHR and
+// the nametables are wired up.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MMC3Board {
+    // MMC3/TxROM (mapper 4)
+    TxROM,
+
+    // MMC6/HKROM (mapper 4, submapper 1), which has 1KB of PRG-RAM inside the
+    // mapper with separate protection for each 512 byte half.
+    HKROM,
+
+    // TxSROM (mapper 118), where bit 7 of the CHR banks drives CIRAM A10
+    // instead of the mirroring register.
+    TxSROM,
+
+    // TQROM (mapper 119), which has both CHR-ROM and 8KB of CHR-RAM, with
+    // bit 6 of the CHR banks choosing between them.
+    TQROM,
+}
+
 //
-// MMC3/TxROM (mapper 4)
+// MMC3/TxROM (mapper 4), MMC6/HKROM, TxSROM (mapper 118), TQROM (mapper 119)
 //
 pub struct Mapper4 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
+    board: MMC3Board,
 
-    mirror_mode: MirrorMode,
+    chr: Banks,
+    chr_ram: Banks,
+    prg: Banks,
+    sram: Banks,
 
-    n_prg_banks: usize,
+    mirror_mode: MirrorMode,
+    battery: bool,
 
     regs: [usize; 8],
     index: usize,
     chr_mode: bool,
     prg_mode: bool,
 
+    // MMC6 PRG-RAM enable ($8000 bit 5) and protection ($A001)
+    sram_enabled: bool,
+    sram_protect: u8,
+
     irq_counter: u8,
     irq_reload: bool,
     irq_period: u8,
     irq_enabled: bool,
     irq_flag: bool,
-    last_vram_addr: u16,
+
+    // The MMC3A and MMC6 only raise an IRQ when the counter reaches 0 by
+    // being decremented, or by being reloaded after a write to $C001, while
+    // the MMC3B and C raise it whenever the counter is 0 after a clock.
+    alt_irq: bool,
+
+    // The last level of A12 on the PPU bus, and how many dots it's been low
+    a12: bool,
+    a12_low_dots: u16,
 }
 
 impl Mapper4 {
-    pub fn new_mapper(rom: Vec<u8>, vrom: Vec<u8>, mirror_mode: u8) -> Self {
-        let n_banks = rom.len() / PRG_BANK_SIZE;
+    pub fn new_mapper(cart: Cartridge,
+                      board: MMC3Board)
+        -> Self
+    {
+        // TQROM has real CHR-ROM alongside its CHR-RAM, which the other
+        // boards don't have at all.
+        let (chr, chr_ram) = if board == MMC3Board::TQROM {
+            let chr_ram_size = std::cmp::max(cart.chr_ram_size, 0x2000);
+            (Banks::new_rom(cart.chr_rom, 0x0000, 0x2000, CHR_BANK_SIZE),
+             Banks::new_ram(vec![0; chr_ram_size], 0x0000, 0x2000, CHR_BANK_SIZE))
+        } else {
+            (Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+             Banks::new_ram(vec![], 0x0000, 0x2000, CHR_BANK_SIZE))
+        };
 
-        Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
+        // The MMC6 only has 1KB, mirrored through $7000-$7FFF
+        let mut sram = if board == MMC3Board::HKROM {
+            Banks::new_ram(vec![0; 0x400], 0x6000, 0x2000, 0x400)
+        } else {
+            Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x400)
+        };
+
+        if board == MMC3Board::HKROM {
+            sram.unmap(0x6000, 0x1000);
+        }
 
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
-            
-            n_prg_banks: n_banks,
+        let mut mapper = Self {
+            board: board,
+
+            chr: chr,
+            chr_ram: chr_ram,
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: sram,
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+            battery: cart.battery,
 
             regs: [0; 8],
             index: 0,
             chr_mode: false,
             prg_mode: false,
 
+            sram_enabled: false,
+            sram_protect: 0,
+
             irq_counter: 0,
             irq_reload: false,
             irq_period: 0,
             irq_enabled: false,
             irq_flag: false,
-            last_vram_addr: 0,
+
+            alt_irq: board == MMC3Board::HKROM || cart.submapper == 4,
+
+            a12: false,
+            a12_low_dots: 0,
+        };
+
+        mapper.update_banks();
+        mapper
+    }
+
+    // The 1KB CHR bank register that applies to an address in the pattern
+    // tables, taking the CHR A12 inversion into account.
+    fn chr_bank(&self, address: u16) -> usize {
+        match (address, self.chr_mode) {
+            (0x0000 ..= 0x03ff, false) => self.regs[0] & 0xfe,
+            (0x0000 ..= 0x03ff, true)  => self.regs[2],
+            (0x0400 ..= 0x07ff, false) => self.regs[0] | 0x01,
+            (0x0400 ..= 0x07ff, true)  => self.regs[3],
+            (0x0800 ..= 0x0bff, false) => self.regs[1] & 0xfe,
+            (0x0800 ..= 0x0bff, true)  => self.regs[4],
+            (0x0c00 ..= 0x0fff, false) => self.regs[1] | 0x01,
+            (0x0c00 ..= 0x0fff, true)  => self.regs[5],
+            (0x1000 ..= 0x13ff, false) => self.regs[2],
+            (0x1000 ..= 0x13ff, true)  => self.regs[0] & 0xfe,
+            (0x1400 ..= 0x17ff, false) => self.regs[3],
+            (0x1400 ..= 0x17ff, true)  => self.regs[0] | 0x01,
+            (0x1800 ..= 0x1bff, false) => self.regs[4],
+            (0x1800 ..= 0x1bff, true)  => self.regs[1] & 0xfe,
+            (0x1c00 ..= 0x1fff, false) => self.regs[5],
+            (0x1c00 ..= 0x1fff, true)  => self.regs[1] | 0x01,
+            _ => panic!("should not happen ever"),
         }
     }
 
+    fn update_banks(&mut self) {
+        // On TQROM, banks with bit 6 set come from CHR-RAM instead
+        for slot in 0 .. 8 {
+            let address = (slot * CHR_BANK_SIZE) as u16;
+            let bank = self.chr_bank(address);
+
+            if self.board == MMC3Board::TQROM && bank & 0x40 != 0 {
+                self.chr.unmap(address, CHR_BANK_SIZE);
+                self.chr_ram.map(address, CHR_BANK_SIZE, bank & 0x07);
+            } else {
+                self.chr.map(address, CHR_BANK_SIZE, bank);
+                self.chr_ram.unmap(address, CHR_BANK_SIZE);
+            }
+        }
+
+        let n_banks = self.prg.n_banks(PRG_BANK_SIZE);
+        let second_last = (n_banks * 2 - 2) % n_banks;
+
+        let (low, high) = if self.prg_mode {
+            (second_last, self.regs[6])
+        } else {
+            (self.regs[6], second_last)
+        };
+
+        self.prg.map(0x8000, PRG_BANK_SIZE, low);
+        self.prg.map(0xa000, PRG_BANK_SIZE, self.regs[7]);
+        self.prg.map(0xc000, PRG_BANK_SIZE, high);
+        self.prg.map(0xe000, PRG_BANK_SIZE, n_banks - 1);
+    }
+
+    // 7  bit  0
+    // ---- ----
+    // HhLl ....
+    // ||||
+    // |||+------ Allow writes to $7000-$71FF
+    // ||+------- Allow reads from $7000-$71FF
+    // |+-------- Allow writes to $7200-$73FF
+    // +--------- Allow reads from $7200-$73FF
+    fn mmc6_readable(&self, address: u16) -> bool {
+        let bit = if address & 0x200 == 0 { 0x20 } else { 0x80 };
+        self.sram_enabled && self.sram_protect & bit != 0
+    }
+
+    fn mmc6_writable(&self, address: u16) -> bool {
+        let bit = if address & 0x200 == 0 { 0x10 } else { 0x40 };
+        self.mmc6_readable(address) && self.sram_protect & bit != 0
+    }
+
     fn step_irq_counter(&mut self) {
+        let previous = self.irq_counter;
+
         if self.irq_counter == 0 || self.irq_reload {
             debug!("step: reloading counter to {}", self.irq_period);
             self.irq_counter = self.irq_period;
@@ -70,12 +219,30 @@ impl Mapper4 {
             debug!("step: decremented counter {}", self.irq_counter);
         }
 
-        if self.irq_counter == 0 && self.irq_enabled {
+        let triggered = !self.alt_irq || previous != 0 || self.irq_reload;
+        if self.irq_counter == 0 && self.irq_enabled && triggered {
             self.irq_flag = true;
         }
 
         self.irq_reload = false;
     }
+
+    // The counter is clocked by A12 rising on the PPU bus, which happens when
+    // the PPU moves from fetching the $0000 pattern table to the $1000 one,
+    // normally once per scanline, or when $2006 changes the address.
+    fn watch_a12(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+
+        if a12 && !self.a12 && self.a12_low_dots >= A12_FILTER_DOTS {
+            self.step_irq_counter();
+        }
+
+        if !a12 && self.a12 {
+            self.a12_low_dots = 0;
+        }
+
+        self.a12 = a12;
+    }
 }
 
 impl Mapper for Mapper4 {
@@ -83,75 +250,31 @@ impl Mapper for Mapper4 {
         &self.mirror_mode
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
-            // CHR-ROM
-            0x0000 ..= 0x1fff => {
-                let bank = match (address, self.chr_mode) {
-                    (0x0000 ..= 0x03ff, false) => self.regs[0] & 0xfe,
-                    (0x0000 ..= 0x03ff, true)  => self.regs[2],
-                    (0x0400 ..= 0x07ff, false) => self.regs[0] | 0x01,
-                    (0x0400 ..= 0x07ff, true)  => self.regs[3],
-                    (0x0800 ..= 0x0bff, false) => self.regs[1] & 0xfe,
-                    (0x0800 ..= 0x0bff, true)  => self.regs[4],
-                    (0x0c00 ..= 0x0fff, false) => self.regs[1] | 0x01,
-                    (0x0c00 ..= 0x0fff, true)  => self.regs[5],
-                    (0x1000 ..= 0x13ff, false) => self.regs[2],
-                    (0x1000 ..= 0x13ff, true)  => self.regs[0] & 0xfe,
-                    (0x1400 ..= 0x17ff, false) => self.regs[3],
-                    (0x1400 ..= 0x17ff, true)  => self.regs[0] | 0x01,
-                    (0x1800 ..= 0x1bff, false) => self.regs[4],
-                    (0x1800 ..= 0x1bff, true)  => self.regs[1] & 0xfe,
-                    (0x1c00 ..= 0x1fff, false) => self.regs[5],
-                    (0x1c00 ..= 0x1fff, true)  => self.regs[1] | 0x01,
-                    _ => panic!("should not happen ever"),
-                };
-
-                let offset = address as usize % 0x0400;
-                let index = ((CHR_BANK_SIZE * bank) | offset) % self.chr_rom.len();
-                self.chr_rom[index]
-            },
+            // CHR-ROM, or CHR-RAM on TQROM
+            0x0000 ..= 0x1fff if self.chr.is_mapped(address) => self.chr.read(address),
+            0x0000 ..= 0x1fff => self.chr_ram.read(address),
 
             // SRAM
-            0x6000 ..= 0x7fff => self.sram[address as usize - 0x6000],
-
-            // PRG-ROM
-            0x8000 ..= 0x9fff => {
-                let bank = if self.prg_mode {
-                    self.n_prg_banks - 2
+            //
+            // With neither half readable the MMC6 leaves the bus open,
+            // otherwise the unreadable half reads as 0.
+            0x7000 ..= 0x7fff if self.board == MMC3Board::HKROM => {
+                if self.mmc6_readable(address) {
+                    self.sram.read(address)
+                } else if self.mmc6_readable(address ^ 0x200) {
+                    Some(0)
                 } else {
-                    self.regs[6]
-                };
-
-                let offset = address as usize & 0x1fff;
-                let index = ((PRG_BANK_SIZE * bank) | offset) % self.prg_rom.len();
-                self.prg_rom[index]
-            },
-            0xa000 ..= 0xbfff => {
-                let bank = self.regs[7];
-                let offset = address as usize & 0x1fff;
-                let index = ((PRG_BANK_SIZE * bank) | offset) % self.prg_rom.len();
-                self.prg_rom[index]
+                    None
+                }
             },
-            0xc000 ..= 0xdfff => {
-                let bank = if self.prg_mode {
-                    self.regs[6]
-                } else {
-                    self.n_prg_banks - 2
-                };
+            0x6000 ..= 0x7fff => self.sram.read(address),
 
-                let offset = address as usize & 0x1fff;
-                let index = ((PRG_BANK_SIZE * bank) | offset) % self.prg_rom.len();
-                self.prg_rom[index]
-            },
-            0xe000 ..= 0xffff => {
-                let bank = self.n_prg_banks - 1;
-                let offset = address as usize & 0x1fff;
-                let index = ((PRG_BANK_SIZE * bank) | offset) % self.prg_rom.len();
-                self.prg_rom[index]
-            },
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
 
-            _ => 0,
+            _ => None,
         }
     }
 
@@ -159,11 +282,17 @@ impl Mapper for Mapper4 {
         let even = address & 1 == 0;
 
         match address {
-            // CHR-ROM
-            0x0000 ..= 0x1fff => { self.chr_rom[address as usize] = val },
+            // CHR-ROM, or CHR-RAM on TQROM
+            0x0000 ..= 0x1fff if self.chr.is_mapped(address) => self.chr.write(address, val),
+            0x0000 ..= 0x1fff => self.chr_ram.write(address, val),
 
             // SRAM
-            0x6000 ..= 0x7fff => { self.sram[address as usize - 0x6000] = val },
+            0x6000 ..= 0x7fff if self.board == MMC3Board::HKROM => {
+                if self.mmc6_writable(address) {
+                    self.sram.write(address, val);
+                }
+            },
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
 
             // PRG-ROM
             0x8000 ..= 0x9fff => {
@@ -172,10 +301,16 @@ impl Mapper for Mapper4 {
                     self.index = val as usize & 0x07;
                     self.prg_mode = val & 0x40 != 0;
                     self.chr_mode = val & 0x80 != 0;
+
+                    if self.board == MMC3Board::HKROM {
+                        self.sram_enabled = val & 0x20 != 0;
+                    }
                 } else {
                     // Bank data
                     self.regs[self.index] = val as usize;
                 }
+
+                self.update_banks();
             },
             0xa000 ..= 0xbfff => {
                 if even {
@@ -184,13 +319,31 @@ impl Mapper for Mapper4 {
                     } else {
                         MirrorMode::Horizontal
                     };
+                } else if self.board == MMC3Board::HKROM {
+                    // PRG-RAM protect, which is ignored while PRG-RAM is
+                    // disabled.
+                    if self.sram_enabled {
+                        self.sram_protect = val & 0xf0;
+                    }
                 } else {
                     // PRG-RAM protect
                     //
-                    // Though these bits are functional on the MMC3, their main
-                    // purpose is to write-protect save RAM during power-off.
-                    // Many emulators choose not to implement them as part of
-                    // iNES Mapper 4 to avoid an incompatibility with the MMC6.
+                    // 7  bit  0
+                    // ---- ----
+                    // RWxx xxxx
+                    // ||
+                    // |+-------- Deny writes to PRG-RAM
+                    // +--------- Enable PRG-RAM
+                    //
+                    // MMC6 games use different bits here, so they need the
+                    // HKROM submapper to keep their saves.
+                    if val & 0x80 != 0 {
+                        self.sram.map(0x6000, 0x2000, 0);
+                    } else {
+                        self.sram.unmap(0x6000, 0x2000);
+                    }
+
+                    self.sram.set_writable(val & 0x40 == 0);
                 }
             },
 
@@ -224,33 +377,54 @@ impl Mapper for Mapper4 {
         }
     }
 
+    // On TxSROM, each nametable follows the CHR bank that's mapped into the
+    // same 1KB slot of $0000-$0FFF, with bit 7 selecting the CIRAM page.
+    fn nametable_page(&self, table: usize) -> usize {
+        if self.board == MMC3Board::TxSROM {
+            self.chr_bank(table as u16 * 0x400) >> 7
+        } else {
+            self.mirror_mode.coefficients()[table]
+        }
+    }
+
     fn irq_flag(&self) -> bool {
         self.irq_flag
     }
 
     fn notify(&mut self, event: MapperEvent) {
         match event {
-            MapperEvent::HBlank => { self.step_irq_counter() },
-            MapperEvent::VRAMAddressChange(addr) => {
-                let prev_a12 = (self.last_vram_addr >> 12) & 1;
-                let cur_a12  = (addr >> 12) & 1;
-
-                if prev_a12 == 0 && cur_a12 == 1 {
-                    self.step_irq_counter();
+            MapperEvent::PPUTick => {
+                if !self.a12 {
+                    self.a12_low_dots = self.a12_low_dots.saturating_add(1);
                 }
-
-                self.last_vram_addr = addr;
             },
+            MapperEvent::PPURead(address)      => { self.watch_a12(address) },
+            MapperEvent::PPUWrite(address, _)  => { self.watch_a12(address) },
+            MapperEvent::VRAMAddressChange(address) => { self.watch_a12(address) },
             _ => { },
         }
     }
 
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn save_battery(&self, output: &mut File) -> io::Result<()> {
+        output.write(self.sram.data())?;
+        Ok(())
+    }
+
+    fn load_battery(&mut self, input: &mut File) -> io::Result<()> {
+        input.read(self.sram.data_mut())?;
+        Ok(())
+    }
+
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
+        self.chr.save(output)?;
+        self.chr_ram.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
         serde::encode_u8(output, self.mirror_mode as u8)?;
-        serde::encode_usize(output, self.n_prg_banks)?;
 
         for i in 0 .. 8 {
             serde::encode_usize(output, self.regs[i])?;
@@ -259,20 +433,26 @@ impl Mapper for Mapper4 {
         serde::encode_u8(output, self.chr_mode as u8)?;
         serde::encode_u8(output, self.prg_mode as u8)?;
 
+        serde::encode_u8(output, self.sram_enabled as u8)?;
+        serde::encode_u8(output, self.sram_protect)?;
+
         serde::encode_u8(output, self.irq_counter)?;
+        serde::encode_u8(output, self.irq_reload as u8)?;
         serde::encode_u8(output, self.irq_period)?;
         serde::encode_u8(output, self.irq_enabled as u8)?;
         serde::encode_u8(output, self.irq_flag as u8)?;
+        serde::encode_u8(output, self.a12 as u8)?;
+        serde::encode_u16(output, self.a12_low_dots)?;
 
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
+        self.chr.load(input)?;
+        self.chr_ram.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
         self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
-        self.n_prg_banks = serde::decode_usize(input)?;
 
         for i in 0 .. 8 {
             self.regs[i] = serde::decode_usize(input)?;
@@ -281,11 +461,166 @@ impl Mapper for Mapper4 {
         self.chr_mode = serde::decode_u8(input)? != 0;
         self.prg_mode = serde::decode_u8(input)? != 0;
 
+        self.sram_enabled = serde::decode_u8(input)? != 0;
+        self.sram_protect = serde::decode_u8(input)?;
+
         self.irq_counter = serde::decode_u8(input)?;
+        self.irq_reload = serde::decode_u8(input)? != 0;
         self.irq_period = serde::decode_u8(input)?;
         self.irq_enabled = serde::decode_u8(input)? != 0;
         self.irq_flag = serde::decode_u8(input)? != 0;
+        self.a12 = serde::decode_u8(input)? != 0;
+        self.a12_low_dots = serde::decode_u16(input)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_board(board: MMC3Board) -> Mapper4 {
+        Mapper4::new_mapper(Cartridge::new_cartridge(vec![0; 0x8000], vec![0; 0x2000], 0), board)
+    }
+
+    #[test]
+    fn test_mmc6_ram_protect() {
+        let mut mapper = new_board(MMC3Board::HKROM);
+
+        // Disabled by default, leaving open bus
+        mapper.write(0x7000, 0x12);
+        assert_eq!(mapper.read(0x7000), None);
+
+        // Enable PRG-RAM, then make the lower half readable and writable,
+        // and the upper half only readable.
+        mapper.write(0x8000, 0x20);
+        mapper.write(0xa001, 0xb0);
+
+        mapper.write(0x7000, 0x12);
+        mapper.write(0x7200, 0x34);
+        assert_eq!(mapper.read(0x7000), Some(0x12));
+        assert_eq!(mapper.read(0x7200), Some(0));
+
+        // 1KB, mirrored through $7000-$7FFF, with open bus below that
+        assert_eq!(mapper.read(0x7400), Some(0x12));
+        assert_eq!(mapper.read(0x6000), None);
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mapper = new_board(MMC3Board::TxROM);
+
+        // Enabled and writable at power on
+        mapper.write(0x6000, 0x12);
+        assert_eq!(mapper.read(0x6000), Some(0x12));
+
+        // Write-protected
+        mapper.write(0xa001, 0xc0);
+        mapper.write(0x6000, 0x34);
+        assert_eq!(mapper.read(0x6000), Some(0x12));
+
+        // Disabled, leaving open bus
+        mapper.write(0xa001, 0x00);
+        assert_eq!(mapper.read(0x6000), None);
+
+        mapper.write(0xa001, 0x80);
+        mapper.write(0x6000, 0x34);
+        assert_eq!(mapper.read(0x6000), Some(0x34));
+    }
+
+    // A scanline's worth of fetches, with the background at $0000 and the
+    // sprites at $1000.
+    fn scanline(mapper: &mut Mapper4) {
+        for dot in 0 .. 341 {
+            mapper.notify(MapperEvent::PPUTick);
+
+            if dot % 2 == 1 {
+                let address = match dot {
+                    257 ..= 320 if dot % 8 == 5 || dot % 8 == 7 => 0x1ff0,
+                    257 ..= 320 => 0x2000,
+                    _           => 0x0000,
+                };
+                mapper.notify(MapperEvent::PPURead(address));
+            }
+        }
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut mapper = new_board(MMC3Board::TxROM);
+        mapper.write(0xc000, 2);
+        mapper.write(0xc001, 0);
+        mapper.write(0xe001, 0);
+
+        // Only one clock per scanline, despite A12 dropping between each of
+        // the sprite fetches.
+        scanline(&mut mapper);
+        scanline(&mut mapper);
+        assert!(!mapper.irq_flag());
+        scanline(&mut mapper);
+        assert!(mapper.irq_flag());
+
+        mapper.write(0xe000, 0);
+        assert!(!mapper.irq_flag());
+    }
+
+    #[test]
+    fn test_irq_revisions() {
+        // With a latch of 0, the MMC3B raises an IRQ on every clock, but the
+        // MMC3A only after the counter's been reloaded through $C001.
+        for &(submapper, flags) in [(0, [true, true]), (4, [true, false])].iter() {
+            let cart = Cartridge {
+                submapper: submapper,
+                ..Cartridge::new_cartridge(vec![0; 0x8000], vec![0; 0x2000], 0)
+            };
+            let mut mapper = Mapper4::new_mapper(cart, MMC3Board::TxROM);
+            mapper.write(0xc000, 0);
+            mapper.write(0xc001, 0);
+            mapper.write(0xe001, 0);
+
+            for &flag in flags.iter() {
+                scanline(&mut mapper);
+                assert_eq!(mapper.irq_flag(), flag);
+
+                mapper.write(0xe000, 0);
+                mapper.write(0xe001, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_txsrom_nametables() {
+        let mut mapper = new_board(MMC3Board::TxSROM);
+
+        // 2KB bank at $0000 points $2000-$27FF at CIRAM page 1, and the one
+        // at $0800 points $2800-$2FFF at page 0.
+        mapper.write(0x8000, 0);
+        mapper.write(0x8001, 0x80);
+        mapper.write(0x8000, 1);
+        mapper.write(0x8001, 0x00);
+
+        let pages: Vec<usize> = (0 .. 4).map(|i| mapper.nametable_page(i)).collect();
+        assert_eq!(pages, vec![1, 1, 0, 0]);
+
+        // Swapping to 1KB banks at $0000
+        mapper.write(0x8000, 0x82);
+        mapper.write(0x8001, 0x80);
+        assert_eq!(mapper.nametable_page(0), 1);
+        assert_eq!(mapper.nametable_page(1), 0);
+    }
+
+    #[test]
+    fn test_tqrom_chr_ram() {
+        let mut mapper = new_board(MMC3Board::TQROM);
+
+        // 1KB bank at $1000 is CHR-RAM page 2
+        mapper.write(0x8000, 2);
+        mapper.write(0x8001, 0x42);
+
+        mapper.write(0x1000, 0x12);
+        assert_eq!(mapper.read(0x1000), Some(0x12));
+        assert_eq!(mapper.chr_ram.data()[0x800], 0x12);
+        assert!(mapper.chr.data().iter().all(|&b| b == 0));
+    }
+}
@@ -1,9 +1,10 @@
-use std::io::{Read, Write};
 use std::io;
 use std::fs::File;
 
 use crate::mapper::Mapper;
 use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
 use crate::serde;
 
 const PRG_BANK_SIZE: usize = 32768;
@@ -13,39 +14,29 @@ const CHR_BANK_SIZE: usize = 8192;
 // GxROM (mapper 66)
 //
 pub struct Mapper66 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
-
-    // Registers
-    chr_bank: u8,
-    prg_bank: u8,
-
-    // The number of PRG-ROM banks in this cartridge
-    n_banks: usize,
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
 
     mirror_mode: MirrorMode,
+
+    // Whether writes to PRG-ROM collide with the ROM's own output
+    bus_conflicts: bool,
 }
 
 impl Mapper66 {
-    pub fn new_mapper(rom: Vec<u8>,
-                      vrom: Vec<u8>,
-                      mirror_mode: u8)
+    pub fn new_mapper(cart: Cartridge,
+                      bus_conflicts: bool)
         -> Self
     {
-        let n_banks = rom.len() / PRG_BANK_SIZE;
-
         Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
 
-            chr_bank: 0,
-            prg_bank: 0,
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
 
-            n_banks: n_banks,
-
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
+            bus_conflicts: bus_conflicts,
         }
     }
 }
@@ -55,37 +46,28 @@ impl Mapper for Mapper66 {
         &self.mirror_mode
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => {
-                let index = (CHR_BANK_SIZE * self.chr_bank as usize) | address as usize;
-                self.chr_rom[index]
-            },
+            0x0000 ..= 0x1fff => self.chr.read(address),
 
             // SRAM
-            0x6000 ..= 0x7fff => {
-                self.sram[address as usize - 0x6000]
-            },
+            0x6000 ..= 0x7fff => self.sram.read(address),
 
             // PRG-ROM
-            0x8000 ..= 0xffff => {
-                let bank = self.prg_bank as usize;
-                let index = ((PRG_BANK_SIZE * bank) | address as usize & 0x7fff) % self.prg_rom.len();
-                self.prg_rom[index]
-            },
+            0x8000 ..= 0xffff => self.prg.read(address),
 
-            _ => 0,
+            _ => None,
         }
     }
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => { self.chr_rom[address as usize & 0x1fff] = val },
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
 
             // SRAM
-            0x6000 ..= 0x7fff => { self.sram[address as usize - 0x6000] = val },
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
 
             // PRG-ROM
             0x8000 ..= 0xffff => {
@@ -95,32 +77,50 @@ impl Mapper for Mapper66 {
                 //   ||   ||
                 //   ||   ++- Select 8 KB CHR ROM bank for PPU $0000-$1FFF
                 //   ++------ Select 32 KB PRG ROM bank for CPU $8000-$FFFF
-                self.chr_bank =  val & 0b0000_0011;
-                self.prg_bank = (val & 0b0011_0000) >> 4;
-            }
+                let val = if self.bus_conflicts { val & self.read(address).unwrap_or(val) } else { val };
+                self.chr.map(0x0000, CHR_BANK_SIZE, (val & 0b0000_0011) as usize);
+                self.prg.map(0x8000, PRG_BANK_SIZE, ((val & 0b0011_0000) >> 4) as usize);
+            },
+
             _ => { },
         }
     }
 
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
-        serde::encode_u8(output, self.chr_bank)?;
-        serde::encode_u8(output, self.prg_bank)?;
-        serde::encode_usize(output, self.n_banks)?;
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
         serde::encode_u8(output, self.mirror_mode as u8)?;
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
-        self.chr_bank = serde::decode_u8(input)?;
-        self.prg_bank = serde::decode_u8(input)?;
-        self.n_banks = serde::decode_usize(input)?;
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
         self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = banked(4, PRG_BANK_SIZE);
+        rom[0] = 0b0001_0001;
+
+        let mut mapper = Mapper66::new_mapper(Cartridge::new_cartridge(rom.clone(), banked(4, CHR_BANK_SIZE), 0), true);
+        mapper.write(0x8000, 0b0011_0011);
+        assert_eq!(mapper.read(0x8000), Some(1));
+        assert_eq!(mapper.read(0x0000), Some(1));
+
+        let mut mapper = Mapper66::new_mapper(Cartridge::new_cartridge(rom, banked(4, CHR_BANK_SIZE), 0), false);
+        mapper.write(0x8000, 0b0011_0011);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(mapper.read(0x0000), Some(3));
+    }
+}
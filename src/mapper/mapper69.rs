@@ -1,10 +1,15 @@
+mod audio;
+
 use std::convert::From;
-use std::io::{Read, Write};
 use std::io;
 use std::fs::File;
 
 use crate::mapper::{Mapper, MapperEvent};
 use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::mapper::mapper69::audio::Sunsoft5BAudio;
+use crate::mem::Memory;
 use crate::serde;
 
 const PRG_BANK_SIZE: usize = 8192;
@@ -35,64 +40,70 @@ impl From<u8> for Command {
 }
 
 //
-// Sunsoft FME-7/5A (mapper 69)
+// Sunsoft FME-7/5A/5B (mapper 69)
 //
 pub struct Mapper69 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
+    chr: Banks,
+    sram: Banks,
+
+    // $6000-$7FFF can also be pointed at PRG-ROM, so the PRG-ROM window
+    // starts there.
+    prg: Banks,
+
     mirror_mode: MirrorMode,
 
     // A command to run
     cmd: Option<Command>,
 
-    // Registers (set by the command above)
-    chr_banks: [usize; 8],
-    sram_bank: usize,
-    prg_banks: [usize; 4],
-    ram_select: bool,
-    ram_enabled: bool,
-
     irq_enabled: bool,
     irq_counter_enabled: bool,
     irq_counter_value: u16,
     irq_flag: bool,
+
+    // Expansion audio, only present on the 5B, but writes to the audio
+    // registers are harmless on the other variants.
+    audio: Sunsoft5BAudio,
 }
 
 
 impl Mapper69 {
-    pub fn new_mapper(rom: Vec<u8>,
-                      vrom: Vec<u8>,
-                      mirror_mode: u8)
+    pub fn new_mapper(cart: Cartridge)
         -> Self
     {
-        let n_banks = rom.len() / PRG_BANK_SIZE;
+        let mut prg = Banks::new_rom(cart.prg_rom, 0x6000, 0xa000, PRG_BANK_SIZE);
+        let mut sram = Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, PRG_BANK_SIZE);
+
+        for address in (0x6000 ..= 0xc000).step_by(PRG_BANK_SIZE) {
+            prg.map(address, PRG_BANK_SIZE, 0);
+        }
+
+        let last_bank = prg.last_bank(PRG_BANK_SIZE);
+        prg.map(0xe000, PRG_BANK_SIZE, last_bank);
+        sram.unmap(0x6000, PRG_BANK_SIZE);
 
         Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            sram: sram,
+            prg: prg,
 
-            cmd: None,
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
 
-            chr_banks: [0; 8],
-            sram_bank: 0,
-            prg_banks: [0, 0, 0, n_banks - 1],
-            ram_select: false,
-            ram_enabled: false,
+            cmd: None,
 
             irq_enabled: false,
             irq_counter_enabled: false,
             irq_counter_value: 0,
             irq_flag: false,
+
+            audio: Sunsoft5BAudio::new_audio(),
         }
     }
 
     fn run_cmd(&mut self, parameter: u8) {
         match self.cmd {
             Some(Command::CHRBank(n)) => {
-                self.chr_banks[n as usize] = parameter as usize;
+                let address = n as u16 * CHR_BANK_SIZE as u16;
+                self.chr.map(address, CHR_BANK_SIZE, parameter as usize);
             },
             Some(Command::PRGBank(n)) => {
                 if n == 0x08 {
@@ -111,11 +122,22 @@ impl Mapper69 {
                     // Despite there being 6 bits of data available for the
                     // bank number in the FME-7 board, only 5 bits were used
                     // by the 5A and 5B variants.
-                    let bank         =  parameter & 0b0001_1111;
-                    self.ram_select  = (parameter & 0b0100_0000) != 0;
-                    self.ram_enabled = (parameter & 0b1000_0000) != 0;
-
-                    self.sram_bank = bank as usize;
+                    let bank        =  parameter & 0b0001_1111;
+                    let ram_select  = (parameter & 0b0100_0000) != 0;
+                    let ram_enabled = (parameter & 0b1000_0000) != 0;
+
+                    // Disabled PRG-RAM is open bus
+                    if ram_select {
+                        self.prg.unmap(0x6000, PRG_BANK_SIZE);
+                    } else {
+                        self.prg.map(0x6000, PRG_BANK_SIZE, bank as usize);
+                    }
+
+                    if ram_select && ram_enabled {
+                        self.sram.map(0x6000, PRG_BANK_SIZE, 0);
+                    } else {
+                        self.sram.unmap(0x6000, PRG_BANK_SIZE);
+                    }
                 } else {
                     // 7  bit  0
                     // ---- ----
@@ -129,8 +151,9 @@ impl Mapper69 {
                     let bank = parameter & 0b0001_1111;
 
                     // n will be one of 0x09, 0x0a, 0x0b, and I want to map
-                    // that to a number 0, 1, or 2, so we subtract 9
-                    self.prg_banks[n as usize - 0x09] = bank as usize;
+                    // that to $8000, $A000 or $C000, so we subtract 8
+                    let address = 0x6000 + (n as u16 - 0x08) * PRG_BANK_SIZE as u16;
+                    self.prg.map(address, PRG_BANK_SIZE, bank as usize);
                 }
             },
             Some(Command::Mirror) => {
@@ -214,61 +237,48 @@ impl Mapper for Mapper69 {
 
     fn notify(&mut self, event: MapperEvent) {
         match event {
-            MapperEvent::CPUTick(cycles) => { self.step_irq_counter(cycles) },
+            MapperEvent::CPUTick(cycles) => {
+                self.step_irq_counter(cycles);
+
+                for _ in 0 .. cycles {
+                    self.audio.step();
+                }
+            },
             _ => { },
         }
     }
 
+    fn signal(&self) -> f32 {
+        self.audio.signal()
+    }
+
     fn irq_flag(&self) -> bool {
         self.irq_flag
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => {
-                let reg = address as usize / CHR_BANK_SIZE;
-                let bank = self.chr_banks[reg];
-                let index = (bank * CHR_BANK_SIZE) | (address as usize & 0x03ff);
-                self.chr_rom[index]
-            },
+            0x0000 ..= 0x1fff => self.chr.read(address),
 
-            // SRAM
-            0x6000 ..= 0x7fff => {
-                match (self.ram_select, self.ram_enabled) {
-                    (true, false) => 0,  // open bus
-                    (true, true)  => self.sram[address as usize - 0x6000],
-                    (false, _)    => {
-                        let index = (self.sram_bank * PRG_BANK_SIZE)
-                            | (address as usize & 0x1fff);
-                        self.prg_rom[index & (self.prg_rom.len() - 1)]
-                    },
-                }
-            },
+            // SRAM, or PRG-ROM
+            0x6000 ..= 0x7fff if self.prg.is_mapped(address) => self.prg.read(address),
+            0x6000 ..= 0x7fff => self.sram.read(address),
 
             // PRG-ROM
-            0x8000 ..= 0xffff => {
-                let reg = (address as usize - 0x8000) / PRG_BANK_SIZE;
-                let index = (self.prg_banks[reg] * PRG_BANK_SIZE)
-                    | (address as usize & 0x1fff);
-                self.prg_rom[index & (self.prg_rom.len() - 1)]
-            },
+            0x8000 ..= 0xffff => self.prg.read(address),
 
-            _ => 0,
+            _ => None,
         }
     }
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => { },
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
 
             // SRAM
-            0x6000 ..= 0x7fff => {
-                if self.ram_select && self.ram_enabled {
-                    self.sram[address as usize - 0x6000] = val;
-                }
-            },
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
 
             // PRG-ROM
             0x8000 ..= 0x9fff => {
@@ -293,14 +303,17 @@ impl Mapper for Mapper69 {
                 self.run_cmd(val);
             },
 
+            // 5B audio register select and write
+            0xc000 ..= 0xffff => self.audio.write(address, val),
+
             _ => { },
         }
     }
 
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
+        self.chr.save(output)?;
+        self.sram.save(output)?;
+        self.prg.save(output)?;
         serde::encode_u8(output, self.mirror_mode as u8)?;
 
         match self.cmd {
@@ -320,30 +333,19 @@ impl Mapper for Mapper69 {
             Some(Command::IRQHi)  => { serde::encode_u8(output, 6)? },
         }
 
-        for i in 0 .. 8 {
-            serde::encode_usize(output, self.chr_banks[i])?;
-        }
-
-        serde::encode_usize(output, self.sram_bank)?;
-
-        for i in 0 .. 4 {
-            serde::encode_usize(output, self.prg_banks[i])?;
-        }
-
-        serde::encode_u8(output, self.ram_select as u8)?;
-        serde::encode_u8(output, self.ram_enabled as u8)?;
-
         serde::encode_u8(output, self.irq_enabled as u8)?;
         serde::encode_u8(output, self.irq_counter_enabled as u8)?;
         serde::encode_u16(output, self.irq_counter_value)?;
 
+        self.audio.save(output)?;
+
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
+        self.chr.load(input)?;
+        self.sram.load(input)?;
+        self.prg.load(input)?;
         self.mirror_mode = MirrorMode::from_vh01(serde::decode_u8(input)?);
 
         let cmd = serde::decode_u8(input)?;
@@ -364,23 +366,12 @@ impl Mapper for Mapper69 {
             _ => unreachable!("bad cmd"),
         };
 
-        for i in 0 .. 8 {
-            self.chr_banks[i] = serde::decode_usize(input)?;
-        }
-
-        self.sram_bank = serde::decode_usize(input)?;
-
-        for i in 0 .. 4 {
-            self.prg_banks[i] = serde::decode_usize(input)?;
-        }
-
-        self.ram_select = serde::decode_u8(input)? != 0;
-        self.ram_enabled = serde::decode_u8(input)? != 0;
-
         self.irq_enabled = serde::decode_u8(input)? != 0;
         self.irq_counter_enabled = serde::decode_u8(input)? != 0;
         self.irq_counter_value = serde::decode_u16(input)?;
 
+        self.audio.load(input)?;
+
         Ok(())
     }
 }
@@ -0,0 +1,424 @@
+use std::io::{Read, Write};
+use std::io;
+use std::fs::File;
+
+use crate::mem::Memory;
+use crate::serde;
+
+// https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+//
+// The 5B is a licensed copy of the YM2149 (itself a variant of the
+// AY-3-8910), a three channel square wave PSG with a shared noise generator
+// and a shared envelope generator. Registers are selected by writing to
+// $C000, and then written to via $E000:
+//
+//   $00-$05  12-bit tone periods for channels A, B and C (low, then high)
+//   $06      5-bit noise period
+//   $07      ..NN NTTT  noise and tone disable for each channel
+//   $08-$0A  ...E VVVV  envelope enable, and volume for each channel
+//   $0B-$0C  16-bit envelope period (low, then high)
+//   $0D      .... CAAH  envelope shape (continue, attack, alternate, hold)
+
+// Everything in the chip is clocked at 1/16th of the CPU clock
+const CLOCK_DIVIDER: u8 = 16;
+
+lazy_static!{
+    // The 5B's DAC is logarithmic, 1.5dB per step of the 5-bit envelope.
+    // Fixed channel volumes only have 4 bits, so they step by 3dB.
+    static ref VOLUME_TABLE: [f32; 32] = {
+        let mut table = [0.0; 32];
+
+        for i in 1 .. 32 {
+            table[i] = 10.0f32.powf(-((31 - i) as f32 * 1.5) / 20.0);
+        }
+
+        table
+    };
+}
+
+pub struct Sunsoft5BAudio {
+    regs: [u8; 0x10],
+    register: u8,
+
+    divider: u8,
+
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+
+    noise_counter: u8,
+    noise_shift: u32,
+    noise_output: bool,
+
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+impl Memory for Sunsoft5BAudio {
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // Audio register select
+            0xc000 ..= 0xdfff => { self.register = val & 0x0f },
+
+            // Audio register write
+            0xe000 ..= 0xffff => {
+                self.regs[self.register as usize] = val;
+
+                if self.register == 0x0d {
+                    self.restart_envelope();
+                }
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        output.write_all(&self.regs)?;
+        serde::encode_u8(output, self.register)?;
+        serde::encode_u8(output, self.divider)?;
+
+        for i in 0 .. 3 {
+            serde::encode_u16(output, self.tone_counters[i])?;
+            serde::encode_u8(output, self.tone_outputs[i] as u8)?;
+        }
+
+        serde::encode_u8(output, self.noise_counter)?;
+        serde::encode_u32(output, self.noise_shift)?;
+        serde::encode_u8(output, self.noise_output as u8)?;
+
+        serde::encode_u16(output, self.envelope_counter)?;
+        serde::encode_u8(output, self.envelope_step)?;
+        serde::encode_u8(output, self.envelope_attack as u8)?;
+        serde::encode_u8(output, self.envelope_holding as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        input.read_exact(&mut self.regs)?;
+        self.register = serde::decode_u8(input)?;
+        self.divider = serde::decode_u8(input)?;
+
+        for i in 0 .. 3 {
+            self.tone_counters[i] = serde::decode_u16(input)?;
+            self.tone_outputs[i] = serde::decode_u8(input)? != 0;
+        }
+
+        self.noise_counter = serde::decode_u8(input)?;
+        self.noise_shift = serde::decode_u32(input)?;
+        self.noise_output = serde::decode_u8(input)? != 0;
+
+        self.envelope_counter = serde::decode_u16(input)?;
+        self.envelope_step = serde::decode_u8(input)?;
+        self.envelope_attack = serde::decode_u8(input)? != 0;
+        self.envelope_holding = serde::decode_u8(input)? != 0;
+        Ok(())
+    }
+}
+
+impl Sunsoft5BAudio {
+    pub fn new_audio() -> Self {
+        Self {
+            regs: [0; 0x10],
+            register: 0,
+
+            divider: 0,
+
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+
+            noise_counter: 0,
+            noise_shift: 1,
+            noise_output: false,
+
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let lo = self.regs[channel * 2] as u16;
+        let hi = (self.regs[channel * 2 + 1] & 0x0f) as u16;
+        (hi << 8) | lo
+    }
+
+    fn noise_period(&self) -> u8 {
+        self.regs[0x06] & 0x1f
+    }
+
+    fn envelope_period(&self) -> u16 {
+        ((self.regs[0x0c] as u16) << 8) | self.regs[0x0b] as u16
+    }
+
+    fn restart_envelope(&mut self) {
+        self.envelope_counter = 0;
+        self.envelope_step = 0;
+        self.envelope_attack = (self.regs[0x0d] & 0b0100) != 0;
+        self.envelope_holding = false;
+    }
+
+    fn envelope_volume(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn step_tones(&mut self) {
+        for i in 0 .. 3 {
+            self.tone_counters[i] += 1;
+
+            // A period of 0 behaves the same as a period of 1
+            if self.tone_counters[i] >= self.tone_period(i).max(1) {
+                self.tone_counters[i] = 0;
+                self.tone_outputs[i] = !self.tone_outputs[i];
+            }
+        }
+    }
+
+    fn step_noise(&mut self) {
+        self.noise_counter += 1;
+
+        // The noise generator runs at half the rate of the tone generators
+        if self.noise_counter >= self.noise_period().max(1) * 2 {
+            self.noise_counter = 0;
+
+            // 17-bit LFSR, with taps at bits 0 and 3
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+            self.noise_output = self.noise_shift & 1 != 0;
+        }
+    }
+
+    fn step_envelope(&mut self) {
+        self.envelope_counter += 1;
+
+        if self.envelope_counter < self.envelope_period().max(1) {
+            return;
+        }
+
+        self.envelope_counter = 0;
+
+        if self.envelope_holding {
+            return;
+        }
+
+        self.envelope_step += 1;
+
+        if self.envelope_step < 32 {
+            return;
+        }
+
+        let shape = self.regs[0x0d];
+        let cont  = (shape & 0b1000) != 0;
+        let alt   = (shape & 0b0010) != 0;
+        let hold  = (shape & 0b0001) != 0;
+
+        if !cont {
+            // Shapes $00-$07 always drop to silence after the first cycle
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+        } else if hold {
+            self.envelope_holding = true;
+            self.envelope_attack ^= alt;
+            self.envelope_step = 31;
+        } else {
+            self.envelope_attack ^= alt;
+            self.envelope_step = 0;
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.divider += 1;
+
+        if self.divider < CLOCK_DIVIDER {
+            return;
+        }
+
+        self.divider = 0;
+
+        self.step_tones();
+        self.step_noise();
+        self.step_envelope();
+    }
+
+    pub fn signal(&self) -> f32 {
+        let mixer = self.regs[0x07];
+
+        (0 .. 3).fold(0.0, |acc, i| {
+            let tone_disabled  = (mixer >> i) & 1 != 0;
+            let noise_disabled = (mixer >> (i + 3)) & 1 != 0;
+
+            let on = (self.tone_outputs[i] || tone_disabled)
+                  && (self.noise_output || noise_disabled);
+
+            if !on {
+                return acc;
+            }
+
+            let volume = self.regs[0x08 + i];
+            let level = if volume & 0b1_0000 != 0 {
+                self.envelope_volume()
+            } else if volume & 0x0f == 0 {
+                0
+            } else {
+                (volume & 0x0f) * 2 + 1
+            };
+
+            acc + VOLUME_TABLE[level as usize] * 0.15
+        } )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_reg(audio: &mut Sunsoft5BAudio, register: u8, val: u8) {
+        audio.write(0xc000, register);
+        audio.write(0xe000, val);
+    }
+
+    // Runs the chip for n of its own clocks
+    fn clock(audio: &mut Sunsoft5BAudio, n: usize) {
+        for _ in 0 .. n * CLOCK_DIVIDER as usize {
+            audio.step();
+        }
+    }
+
+    // The output of a channel at the given level, on its own
+    fn level(level: usize) -> f32 {
+        VOLUME_TABLE[level] * 0.15
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut audio = Sunsoft5BAudio::new_audio();
+
+        // Only channel A's tone, at full volume, with a period of 4
+        write_reg(&mut audio, 0x07, 0b0011_1110);
+        write_reg(&mut audio, 0x08, 0x0f);
+        write_reg(&mut audio, 0x00, 4);
+        write_reg(&mut audio, 0x01, 0);
+        assert_eq!(audio.signal(), 0.0);
+
+        clock(&mut audio, 3);
+        assert_eq!(audio.signal(), 0.0);
+        clock(&mut audio, 1);
+        assert_eq!(audio.signal(), level(31));
+        clock(&mut audio, 4);
+        assert_eq!(audio.signal(), 0.0);
+
+        // Only 12 bits of the period are used, and 0 acts like 1
+        write_reg(&mut audio, 0x00, 0);
+        write_reg(&mut audio, 0x01, 0xf0);
+        assert_eq!(audio.tone_period(0), 0);
+        clock(&mut audio, 1);
+        assert_eq!(audio.signal(), level(31));
+        clock(&mut audio, 1);
+        assert_eq!(audio.signal(), 0.0);
+    }
+
+    #[test]
+    fn test_volume() {
+        let mut audio = Sunsoft5BAudio::new_audio();
+
+        // With the tone and noise disabled, a channel's always on
+        write_reg(&mut audio, 0x07, 0b0011_1111);
+        write_reg(&mut audio, 0x08, 0x0f);
+        assert_eq!(audio.signal(), 1.0 * 0.15);
+
+        // Each step of the volume is 3dB
+        write_reg(&mut audio, 0x08, 0x0e);
+        let ratio = audio.signal() / (1.0 * 0.15);
+        assert!((20.0 * ratio.log10() + 3.0).abs() < 0.001);
+
+        write_reg(&mut audio, 0x08, 0x00);
+        assert_eq!(audio.signal(), 0.0);
+
+        // The channels are mixed together
+        write_reg(&mut audio, 0x08, 0x0f);
+        write_reg(&mut audio, 0x09, 0x0f);
+        assert_eq!(audio.signal(), 2.0 * 0.15);
+    }
+
+    #[test]
+    fn test_envelope_shapes() {
+        // The level at the start and end of the first two cycles, for each
+        // shape. Shapes $00-$07 only run once.
+        let shapes: [[usize; 4]; 16] = [
+            [31, 0, 0, 0], [31, 0, 0, 0], [31, 0, 0, 0], [31, 0, 0, 0],
+            [0, 31, 0, 0], [0, 31, 0, 0], [0, 31, 0, 0], [0, 31, 0, 0],
+            [31, 0, 31, 0],   // $08 \\\\
+            [31, 0, 0, 0],    // $09 \___
+            [31, 0, 0, 31],   // $0A \/\/
+            [31, 0, 31, 31],  // $0B \
+            [0, 31, 0, 31],   // $0C ////
+            [0, 31, 31, 31],  // $0D /
+            [0, 31, 31, 0],   // $0E /\/\
+            [0, 31, 0, 0],    // $0F /___
+        ];
+
+        for (shape, levels) in shapes.iter().enumerate() {
+            let mut audio = Sunsoft5BAudio::new_audio();
+            write_reg(&mut audio, 0x07, 0b0011_1111);
+            write_reg(&mut audio, 0x08, 0x10);
+            write_reg(&mut audio, 0x0b, 1);
+            write_reg(&mut audio, 0x0c, 0);
+            write_reg(&mut audio, 0x0d, shape as u8);
+
+            assert_eq!(audio.signal(), level(levels[0]), "shape {:X} start", shape);
+            clock(&mut audio, 31);
+            assert_eq!(audio.signal(), level(levels[1]), "shape {:X} end", shape);
+            clock(&mut audio, 1);
+            assert_eq!(audio.signal(), level(levels[2]), "shape {:X} second start", shape);
+            clock(&mut audio, 31);
+            assert_eq!(audio.signal(), level(levels[3]), "shape {:X} second end", shape);
+
+            // Held shapes stay where they ended up
+            clock(&mut audio, 40);
+            if shape & 0b1001 != 0b1000 {
+                assert_eq!(audio.signal(), level(levels[3]), "shape {:X} held", shape);
+            }
+        }
+
+        // Writing the shape restarts the envelope
+        let mut audio = Sunsoft5BAudio::new_audio();
+        write_reg(&mut audio, 0x0b, 1);
+        write_reg(&mut audio, 0x0d, 0x0d);
+        clock(&mut audio, 40);
+        assert_eq!(audio.envelope_volume(), 31);
+        write_reg(&mut audio, 0x0d, 0x0d);
+        assert_eq!(audio.envelope_volume(), 0);
+    }
+
+    #[test]
+    fn test_noise() {
+        let mut audio = Sunsoft5BAudio::new_audio();
+
+        // The 17-bit LFSR goes through every state but 0 before repeating
+        write_reg(&mut audio, 0x06, 1);
+        for i in 1 .. (1 << 17) - 1 {
+            audio.step_noise();
+            audio.step_noise();
+            assert!(audio.noise_shift != 1, "repeated after {} shifts", i);
+        }
+        audio.step_noise();
+        audio.step_noise();
+        assert_eq!(audio.noise_shift, 1);
+
+        // A channel with noise enabled follows its output
+        write_reg(&mut audio, 0x07, 0b0011_0111);
+        write_reg(&mut audio, 0x08, 0x0f);
+        for _ in 0 .. 20 {
+            clock(&mut audio, 2);
+            let expected = if audio.noise_output { level(31) } else { 0.0 };
+            assert_eq!(audio.signal(), expected);
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use std::io::{Read, Write};
 use std::io;
 use std::fs::File;
 
 use crate::mapper::Mapper;
 use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
 use crate::serde;
 
 const PRG_BANK_SIZE: usize = 32768;
@@ -12,27 +13,27 @@ const PRG_BANK_SIZE: usize = 32768;
 // AxROM (mapper 7)
 //
 pub struct Mapper7 {
-    chr_rom: Vec<u8>,
-    prg_rom: Vec<u8>,
-    sram: [u8; 0x2000],
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
     mirror_mode: MirrorMode,
 
-    prg_bank: u8,
+    // Whether writes to PRG-ROM collide with the ROM's own output
+    bus_conflicts: bool,
 }
 
 impl Mapper7 {
-    pub fn new_mapper(rom: Vec<u8>,
-                      vrom: Vec<u8>,
-                      mirror_mode: u8)
+    pub fn new_mapper(cart: Cartridge,
+                      bus_conflicts: bool)
         -> Self
     {
         Self {
-            chr_rom: vrom,
-            prg_rom: rom,
-            sram: [0; 0x2000],
-            mirror_mode: MirrorMode::from_hv01(mirror_mode),
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
 
-            prg_bank: 0,
+            bus_conflicts: bus_conflicts,
         }
     }
 }
@@ -42,32 +43,28 @@ impl Mapper for Mapper7 {
         &self.mirror_mode
     }
 
-    fn read(&mut self, address: u16) -> u8 {
+    fn read(&mut self, address: u16) -> Option<u8> {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => self.chr_rom[address as usize & 0x1fff],
+            0x0000 ..= 0x1fff => self.chr.read(address),
 
             // SRAM
-            0x6000 ..= 0x7fff => self.sram[address as usize - 0x6000],
+            0x6000 ..= 0x7fff => self.sram.read(address),
 
             // PRG-ROM
-            0x8000 ..= 0xffff => {
-                let bank = self.prg_bank as usize;
-                let index = ((PRG_BANK_SIZE * bank) | address as usize & 0x7fff) % self.prg_rom.len();
-                self.prg_rom[index]
-            },
+            0x8000 ..= 0xffff => self.prg.read(address),
 
-            _ => 0,
+            _ => None,
         }
     }
 
     fn write(&mut self, address: u16, val: u8) {
         match address {
             // CHR-ROM
-            0x0000 ..= 0x1fff => { self.chr_rom[address as usize & 0x1fff] = val },
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
 
             // SRAM
-            0x6000 ..= 0x7fff => { self.sram[address as usize - 0x6000] = val },
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
 
             // PRG-ROM
             0x8000 ..= 0xffff => {
@@ -77,9 +74,11 @@ impl Mapper for Mapper7 {
                 //    |  |||
                 //    |  +++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
                 //    +------ Select 1 KB VRAM page for all 4 nametables
-                self.prg_bank  =  val & 0b0000_0111;
+                let val = if self.bus_conflicts { val & self.read(address).unwrap_or(val) } else { val };
                 let chr_mirror = (val & 0b0001_0000) != 0;
 
+                self.prg.map(0x8000, PRG_BANK_SIZE, (val & 0b0000_0111) as usize);
+
                 self.mirror_mode = if chr_mirror {
                     MirrorMode::Single0
                 } else {
@@ -91,21 +90,41 @@ impl Mapper for Mapper7 {
     }
 
     fn save(&self, output: &mut File) -> io::Result<()> {
-        serde::encode_vec(output, &self.chr_rom)?;
-        serde::encode_vec(output, &self.prg_rom)?;
-        output.write(&self.sram)?;
-        serde::encode_u8(output, self.prg_bank)?;
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
         serde::encode_u8(output, self.mirror_mode as u8)?;
         Ok(())
     }
 
     fn load(&mut self, input: &mut File) -> io::Result<()> {
-        self.chr_rom = serde::decode_vec(input)?;
-        self.prg_rom = serde::decode_vec(input)?;
-        input.read(&mut self.sram)?;
-        self.prg_bank = serde::decode_u8(input)?;
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
         self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
         Ok(())
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut rom = banked(8, PRG_BANK_SIZE);
+        rom[0] = 0b0000_0101;
+
+        let mut mapper = Mapper7::new_mapper(Cartridge::new_cartridge(rom.clone(), vec![0; 0x2000], 0), true);
+        mapper.write(0x8000, 0b0001_0111);
+        assert_eq!(mapper.read(0x8000), Some(5));
+        assert_eq!(*mapper.mirror_mode() as u8, MirrorMode::Single1 as u8);
+
+        let mut mapper = Mapper7::new_mapper(Cartridge::new_cartridge(rom, vec![0; 0x2000], 0), false);
+        mapper.write(0x8000, 0b0001_0111);
+        assert_eq!(mapper.read(0x8000), Some(7));
+        assert_eq!(*mapper.mirror_mode() as u8, MirrorMode::Single0 as u8);
+    }
+}
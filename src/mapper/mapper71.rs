@@ -0,0 +1,153 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 16384;
+
+//
+// Camerica/Codemasters (mapper 71)
+//
+pub struct Mapper71 {
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    mirror_mode: MirrorMode,
+
+    // Only Fire Hawk's board (submapper 1) has the mirroring register
+    mirroring_control: bool,
+}
+
+impl Mapper71 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        let mut prg = Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE);
+        let last_bank = prg.last_bank(PRG_BANK_SIZE);
+        prg.map(0x8000, PRG_BANK_SIZE, 0);
+        prg.map(0xc000, PRG_BANK_SIZE, last_bank);
+
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, 0x2000),
+            prg: prg,
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+
+            mirroring_control: cart.submapper == 1,
+        }
+    }
+}
+
+impl Mapper for Mapper71 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
+
+            // Mirroring control, only wired up on the BF9097 board used by
+            // Fire Hawk. Other games can write here without it doing anything.
+            0x9000 ..= 0x9fff if self.mirroring_control => {
+                // 7  bit  0
+                // ---- ----
+                // xxxM xxxx
+                //    |
+                //    +------ Select 1 KB VRAM page for all 4 nametables
+                self.mirror_mode = if (val & 0b0001_0000) == 0 {
+                    MirrorMode::Single0
+                } else {
+                    MirrorMode::Single1
+                };
+            },
+
+            // Select 16 KB PRG ROM bank for CPU $8000-$BFFF
+            0xc000 ..= 0xffff => {
+                self.prg.map(0x8000, PRG_BANK_SIZE, (val & 0b0000_1111) as usize);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper71::new_mapper(Cartridge::new_cartridge(banked(8, PRG_BANK_SIZE), vec![0; 0x2000], 0));
+
+        mapper.write(0xc000, 3);
+        assert_eq!(mapper.read(0x8000), Some(3));
+        assert_eq!(mapper.read(0xc000), Some(7));
+
+        // No bus conflicts
+        mapper.write(0xc000, 5);
+        assert_eq!(mapper.read(0x8000), Some(5));
+
+        // Not Fire Hawk, so the mirroring is fixed
+        mapper.write(0x9000, 0x10);
+        assert_eq!(*mapper.mirror_mode() as u8, MirrorMode::Horizontal as u8);
+    }
+
+    #[test]
+    fn test_fire_hawk() {
+        let mut cart = Cartridge::new_cartridge(banked(8, PRG_BANK_SIZE), vec![0; 0x2000], 0);
+        cart.submapper = 1;
+        let mut mapper = Mapper71::new_mapper(cart);
+
+        mapper.write(0x9000, 0x10);
+        assert_eq!(*mapper.mirror_mode() as u8, MirrorMode::Single1 as u8);
+        mapper.write(0x9fff, 0xef);
+        assert_eq!(*mapper.mirror_mode() as u8, MirrorMode::Single0 as u8);
+
+        // $8000-$8FFF isn't the mirroring register
+        mapper.write(0x8000, 0x10);
+        assert_eq!(*mapper.mirror_mode() as u8, MirrorMode::Single0 as u8);
+    }
+}
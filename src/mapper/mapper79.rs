@@ -0,0 +1,139 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const PRG_BANK_SIZE: usize = 32768;
+const CHR_BANK_SIZE: usize = 8192;
+
+//
+// NINA-03/NINA-06 (mapper 79)
+//
+pub struct Mapper79 {
+    chr: Banks,
+    prg: Banks,
+    sram: Banks,
+
+    mirror_mode: MirrorMode,
+}
+
+impl Mapper79 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, PRG_BANK_SIZE),
+            sram: Banks::new_ram(vec![0; cart.prg_ram_size], 0x6000, 0x2000, 0x2000),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+        }
+    }
+}
+
+impl Mapper for Mapper79 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.read(address),
+
+            // PRG-ROM
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // The register is mirrored throughout $4100-$5FFF, wherever A8 is
+            // set.
+            0x4100 ..= 0x5fff if (address & 0xe100) == 0x4100 => {
+                // 7  bit  0
+                // ---- ----
+                // xxxx PCCC
+                //      ||||
+                //      |+++- Select 8 KB CHR ROM bank for PPU $0000-$1FFF
+                //      +---- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+                self.chr.map(0x0000, CHR_BANK_SIZE, (val & 0b0000_0111) as usize);
+                self.prg.map(0x8000, PRG_BANK_SIZE, ((val & 0b0000_1000) >> 3) as usize);
+            },
+
+            // SRAM
+            0x6000 ..= 0x7fff => self.sram.write(address, val),
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        self.sram.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.sram.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper79::new_mapper(Cartridge::new_cartridge(banked(2, PRG_BANK_SIZE), banked(8, CHR_BANK_SIZE), 0));
+
+        mapper.write(0x4100, 0b0000_1110);
+        assert_eq!(mapper.read(0x8000), Some(1));
+        assert_eq!(mapper.read(0x0000), Some(6));
+
+        // Ignored, as A8 is clear
+        mapper.write(0x4200, 0);
+        assert_eq!(mapper.read(0x8000), Some(1));
+
+        // Mirrored
+        mapper.write(0x5f00, 3);
+        assert_eq!(mapper.read(0x8000), Some(0));
+        assert_eq!(mapper.read(0x0000), Some(3));
+    }
+
+    #[test]
+    fn test_rom_writes() {
+        let mut mapper = Mapper79::new_mapper(Cartridge::new_cartridge(banked(2, PRG_BANK_SIZE), banked(8, CHR_BANK_SIZE), 0));
+
+        // The register isn't at $8000-$FFFF, so there are no bus conflicts
+        // to worry about
+        mapper.write(0x4100, 0b0000_1101);
+        mapper.write(0x8100, 0);
+        mapper.write(0xc100, 0);
+        assert_eq!(mapper.read(0x8000), Some(1));
+        assert_eq!(mapper.read(0x0000), Some(5));
+
+        // Nor is it at $4000-$40FF
+        mapper.write(0x4000, 0);
+        assert_eq!(mapper.read(0x8000), Some(1));
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+use std::fs::File;
+
+use crate::mapper::Mapper;
+use crate::mapper::MirrorMode;
+use crate::ines::Cartridge;
+use crate::mapper::banks::Banks;
+use crate::serde;
+
+const CHR_BANK_SIZE: usize = 8192;
+
+//
+// Jaleco JF-xx/Konami (mapper 87)
+//
+pub struct Mapper87 {
+    chr: Banks,
+    prg: Banks,
+
+    mirror_mode: MirrorMode,
+}
+
+impl Mapper87 {
+    pub fn new_mapper(cart: Cartridge)
+        -> Self
+    {
+        Self {
+            chr: Banks::new_chr(cart.chr_rom, cart.chr_ram_size, CHR_BANK_SIZE),
+            prg: Banks::new_rom(cart.prg_rom, 0x8000, 0x8000, 0x8000),
+
+            mirror_mode: MirrorMode::from_hv01(cart.mirror_mode),
+        }
+    }
+}
+
+impl Mapper for Mapper87 {
+    fn mirror_mode(&self) -> &MirrorMode {
+        &self.mirror_mode
+    }
+
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.read(address),
+
+            // PRG-ROM, 16KB carts are mirrored
+            0x8000 ..= 0xffff => self.prg.read(address),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, address: u16, val: u8) {
+        match address {
+            // CHR-ROM
+            0x0000 ..= 0x1fff => self.chr.write(address, val),
+
+            // There's no SRAM, just the register
+            0x6000 ..= 0x7fff => {
+                // 7  bit  0
+                // ---- ----
+                // xxxx xxLH
+                //        ||
+                //        ++- Select 8 KB CHR ROM bank for PPU $0000-$1FFF
+                //
+                // The two bits are wired up in reverse, so L is the low bit
+                // of the bank and H is the high bit.
+                let bank = ((val & 0b0000_0001) << 1) | ((val & 0b0000_0010) >> 1);
+                self.chr.map(0x0000, CHR_BANK_SIZE, bank as usize);
+            },
+
+            _ => { },
+        }
+    }
+
+    fn save(&self, output: &mut File) -> io::Result<()> {
+        self.chr.save(output)?;
+        self.prg.save(output)?;
+        serde::encode_u8(output, self.mirror_mode as u8)?;
+        Ok(())
+    }
+
+    fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.chr.load(input)?;
+        self.prg.load(input)?;
+        self.mirror_mode = MirrorMode::from_hv01(serde::decode_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::banks::banked;
+
+    #[test]
+    fn test_banking() {
+        let mut mapper = Mapper87::new_mapper(Cartridge::new_cartridge(banked(1, 0x4000), banked(4, CHR_BANK_SIZE), 0));
+
+        mapper.write(0x6000, 0b01);
+        assert_eq!(mapper.read(0x0000), Some(2));
+
+        mapper.write(0x6000, 0b10);
+        assert_eq!(mapper.read(0x0000), Some(1));
+
+        // 16KB of PRG-ROM is mirrored at $C000
+        assert_eq!(mapper.read(0xc000), Some(0));
+    }
+
+    #[test]
+    fn test_register_range() {
+        let mut mapper = Mapper87::new_mapper(Cartridge::new_cartridge(banked(2, 0x4000), banked(4, CHR_BANK_SIZE), 0));
+
+        // Mirrored throughout $6000-$7FFF, with the upper bits ignored
+        mapper.write(0x7fff, 0b1111_1111);
+        assert_eq!(mapper.read(0x0000), Some(3));
+        assert_eq!(mapper.read(0x1fff), Some(3));
+
+        // Writes to PRG-ROM don't do anything
+        mapper.write(0x8000, 0);
+        assert_eq!(mapper.read(0x0000), Some(3));
+        assert_eq!(mapper.read(0xc000), Some(1));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::ines::Cartridge;
+use crate::mapper::Mapper;
+use crate::mapper::{Mapper0, Mapper1, Mapper2, Mapper3, Mapper4, Mapper7};
+use crate::mapper::{Mapper11, Mapper13, Mapper15, Mapper19, Mapper34, Mapper66};
+use crate::mapper::{Mapper69, Mapper71, Mapper79, Mapper87, Mapper140, Mapper180};
+use crate::mapper::{Mapper206, Mapper225, Mapper226, Mapper227, Mapper228};
+use crate::mapper::{MMC1Board, MMC3Board, Mapper34Board};
+
+pub type MapperConstructor = fn(Cartridge) -> Box<dyn Mapper>;
+
+pub struct MapperEntry {
+    pub mapper: u16,
+
+    // Entries without a submapper are used for any submapper that doesn't
+    // have an entry of its own.
+    pub submapper: Option<u8>,
+
+    pub name: &'static str,
+    pub new: MapperConstructor,
+}
+
+// Maps iNES/NES 2.0 mapper and submapper numbers to the boards that implement
+// them. New boards are added with register().
+pub struct MapperRegistry {
+    entries: Vec<MapperEntry>,
+}
+
+// The discrete logic boards don't stop the ROM from driving the data bus when
+// the CPU writes to it, so the written value gets ANDed with the ROM's. NES
+// 2.0 submapper 1 marks boards that avoid this, and 2 marks ones that
+// definitely don't, so assume the worst otherwise.
+fn bus_conflicts(cart: &Cartridge) -> bool {
+    cart.submapper != 1
+}
+
+impl MapperRegistry {
+    // An empty registry
+    pub fn new_registry() -> Self {
+        Self {
+            entries: vec![],
+        }
+    }
+
+    // A registry with all of the boards that this crate supports
+    pub fn new_default_registry() -> Self {
+        let mut registry = Self::new_registry();
+
+        registry.register(0, None, "NROM", |c| {
+            Box::new(Mapper0::new_mapper(c))
+        });
+        registry.register(1, None, "MMC1/SxROM", |c| {
+            let board = match (c.prg_ram_size, c.prg_rom.len()) {
+                (0x8000, _)  => MMC1Board::SXROM,
+                (0x4000, _)  => MMC1Board::SOROM,
+                (_, 0x80000) => MMC1Board::SUROM,
+                _            => MMC1Board::SxROM,
+            };
+
+            Box::new(Mapper1::new_mapper(c, board))
+        });
+        registry.register(2, None, "UxROM", |c| {
+            let bus_conflicts = bus_conflicts(&c);
+            Box::new(Mapper2::new_mapper(c, bus_conflicts))
+        });
+        registry.register(3, None, "CNROM", |c| {
+            let bus_conflicts = bus_conflicts(&c);
+            Box::new(Mapper3::new_mapper(c, bus_conflicts))
+        });
+        registry.register(4, None, "MMC3/TxROM", |c| {
+            Box::new(Mapper4::new_mapper(c, MMC3Board::TxROM))
+        });
+        registry.register(4, Some(1), "MMC6/HKROM", |c| {
+            Box::new(Mapper4::new_mapper(c, MMC3Board::HKROM))
+        });
+        registry.register(4, Some(4), "MMC3A/TxROM", |c| {
+            Box::new(Mapper4::new_mapper(c, MMC3Board::TxROM))
+        });
+        registry.register(7, None, "AxROM", |c| {
+            let bus_conflicts = bus_conflicts(&c);
+            Box::new(Mapper7::new_mapper(c, bus_conflicts))
+        });
+        registry.register(11, None, "Color Dreams", |c| {
+            let bus_conflicts = bus_conflicts(&c);
+            Box::new(Mapper11::new_mapper(c, bus_conflicts))
+        });
+        registry.register(13, None, "CPROM", |c| {
+            let bus_conflicts = bus_conflicts(&c);
+            Box::new(Mapper13::new_mapper(c, bus_conflicts))
+        });
+        registry.register(15, None, "100-in-1 Contra Function 16", |c| {
+            Box::new(Mapper15::new_mapper(c))
+        });
+        registry.register(19, None, "Namco 163", |c| {
+            Box::new(Mapper19::new_mapper(c))
+        });
+
+        // Without a submapper, NINA-001 is the one with more than 8KB of CHR
+        registry.register(34, None, "BNROM/NINA-001", |c| {
+            let board = if c.chr_rom.len() > 0x2000 {
+                Mapper34Board::NINA001
+            } else {
+                Mapper34Board::BNROM
+            };
+
+            Box::new(Mapper34::new_mapper(c, board))
+        });
+        registry.register(34, Some(1), "NINA-001", |c| {
+            Box::new(Mapper34::new_mapper(c, Mapper34Board::NINA001))
+        });
+        registry.register(34, Some(2), "BNROM", |c| {
+            Box::new(Mapper34::new_mapper(c, Mapper34Board::BNROM))
+        });
+
+        registry.register(66, None, "GxROM", |c| {
+            let bus_conflicts = bus_conflicts(&c);
+            Box::new(Mapper66::new_mapper(c, bus_conflicts))
+        });
+        registry.register(69, None, "Sunsoft FME-7/5A/5B", |c| {
+            Box::new(Mapper69::new_mapper(c))
+        });
+        registry.register(71, None, "Camerica/Codemasters", |c| {
+            Box::new(Mapper71::new_mapper(c))
+        });
+        registry.register(79, None, "NINA-03/NINA-06", |c| {
+            Box::new(Mapper79::new_mapper(c))
+        });
+        registry.register(87, None, "Jaleco JF-xx", |c| {
+            Box::new(Mapper87::new_mapper(c))
+        });
+        registry.register(118, None, "TxSROM", |c| {
+            Box::new(Mapper4::new_mapper(c, MMC3Board::TxSROM))
+        });
+        registry.register(119, None, "TQROM", |c| {
+            Box::new(Mapper4::new_mapper(c, MMC3Board::TQROM))
+        });
+        registry.register(140, None, "Jaleco JF-11/JF-14", |c| {
+            Box::new(Mapper140::new_mapper(c))
+        });
+        registry.register(180, None, "UNROM with 74HC08", |c| {
+            let bus_conflicts = bus_conflicts(&c);
+            Box::new(Mapper180::new_mapper(c, bus_conflicts))
+        });
+        registry.register(206, None, "Namco 108/DxROM", |c| {
+            Box::new(Mapper206::new_mapper(c))
+        });
+        registry.register(225, None, "52-in-1/64-in-1 multicart", |c| {
+            Box::new(Mapper225::new_mapper(c))
+        });
+        registry.register(226, None, "76-in-1/42-in-1 multicart", |c| {
+            Box::new(Mapper226::new_mapper(c))
+        });
+        registry.register(227, None, "1200-in-1 multicart", |c| {
+            Box::new(Mapper227::new_mapper(c))
+        });
+        registry.register(228, None, "Action 52", |c| {
+            Box::new(Mapper228::new_mapper(c))
+        });
+
+        registry
+    }
+
+    // Adds a board to the registry, replacing any existing entry for the same
+    // mapper and submapper.
+    pub fn register(&mut self,
+                    mapper: u16,
+                    submapper: Option<u8>,
+                    name: &'static str,
+                    new: MapperConstructor)
+    {
+        self.entries.retain(|e| e.mapper != mapper || e.submapper != submapper);
+        self.entries.push(MapperEntry {
+            mapper: mapper,
+            submapper: submapper,
+            name: name,
+            new: new,
+        });
+        self.entries.sort_by_key(|e| (e.mapper, e.submapper));
+    }
+
+    // Finds the board for a mapper and submapper, falling back to the
+    // mapper's generic entry if the submapper doesn't have its own.
+    pub fn find(&self, mapper: u16, submapper: u8) -> Option<&MapperEntry> {
+        self.entries.iter()
+            .find(|e| e.mapper == mapper && e.submapper == Some(submapper))
+            .or_else(|| {
+                self.entries.iter().find(|e| e.mapper == mapper && e.submapper.is_none())
+            })
+    }
+
+    pub fn entries(&self) -> &Vec<MapperEntry> {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cartridge(submapper: u8) -> Cartridge {
+        Cartridge {
+            submapper: submapper,
+            ..Cartridge::new_cartridge(vec![0; 0x8000], vec![0; 0x2000], 0)
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let registry = MapperRegistry::new_default_registry();
+
+        assert_eq!(registry.find(4, 0).unwrap().name, "MMC3/TxROM");
+        assert_eq!(registry.find(4, 1).unwrap().name, "MMC6/HKROM");
+        assert_eq!(registry.find(4, 3).unwrap().name, "MMC3/TxROM");
+        assert!(registry.find(5, 0).is_none());
+        assert!(registry.find(0x104, 0).is_none());
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = MapperRegistry::new_registry();
+
+        registry.register(0x104, Some(2), "Test", |c| {
+            Box::new(Mapper0::new_mapper(c))
+        });
+
+        let entry = registry.find(0x104, 2).unwrap();
+        assert_eq!(entry.name, "Test");
+        assert!(registry.find(0x104, 0).is_none());
+
+        let mut mapper = (entry.new)(cartridge(2));
+        assert_eq!(mapper.read(0x8000), Some(0));
+    }
+}
@@ -3,6 +3,7 @@ use std::fs::File;
 use std::io::{Read, Write};
 use std::io;
 use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::apu::APU;
 use crate::controller::Controller;
@@ -13,6 +14,65 @@ pub trait Memory {
     fn write(&mut self, _address: u16, _val: u8) { }
     fn save(&self, _output: &mut File) -> io::Result<()> { Ok(()) }
     fn load(&mut self, _input: &mut File) -> io::Result<()> { Ok(()) }
+
+    // Tells the memory how many cycles into the current instruction the next
+    // write happens, for anything that needs to know exactly when it lands.
+    fn set_cycle_offset(&mut self, _offset: u64) { }
+}
+
+// What the 2KB of RAM holds at power on. On the hardware it's whatever the
+// chips settle on, which differs between consoles and runs, and some games
+// read it before they've written it, like for seeding their random numbers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RAMInit {
+    Zeros,
+    Ones,
+
+    // Pseudo-random bytes from the seed, so a run can be reproduced
+    Random(u64),
+}
+
+impl RAMInit {
+    // Parses "zeros", "ones", "random" or "random:SEED". Without a seed, one
+    // is taken from the clock.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.splitn(2, ':');
+
+        match (parts.next()?, parts.next()) {
+            ("zeros", None)        => Some(RAMInit::Zeros),
+            ("ones", None)         => Some(RAMInit::Ones),
+            ("random", None)       => Some(RAMInit::Random(clock_seed())),
+            ("random", Some(seed)) => seed.parse().ok().map(RAMInit::Random),
+            _                      => None,
+        }
+    }
+
+    pub fn fill(&self, ram: &mut [u8]) {
+        match *self {
+            RAMInit::Zeros => ram.iter_mut().for_each(|b| *b = 0x00),
+            RAMInit::Ones  => ram.iter_mut().for_each(|b| *b = 0xff),
+            RAMInit::Random(seed) => {
+                // xorshift64*, which needs a state that isn't zero
+                let mut state = seed ^ 0x9e37_79b9_7f4a_7c15;
+                if state == 0 {
+                    state = 1;
+                }
+
+                for b in ram.iter_mut() {
+                    state ^= state >> 12;
+                    state ^= state << 25;
+                    state ^= state >> 27;
+                    *b = (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8;
+                }
+            },
+        }
+    }
+}
+
+fn clock_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() ^ ((d.subsec_nanos() as u64) << 32))
+        .unwrap_or(0)
 }
 
 pub struct NESMemory {
@@ -20,11 +80,19 @@ pub struct NESMemory {
     apu:        Rc<RefCell<APU>>,
     controller: Rc<RefCell<Controller>>,
     ram:        [u8; 0x800],
+
+    // The last value on the CPU's data bus. Reads from anything that doesn't
+    // drive the bus, or only drives some of its bits, see what's left of it.
+    // http://wiki.nesdev.com/w/index.php/Open_bus_behavior
+    bus:        u8,
+
+    // How many cycles into the current instruction the next write happens
+    cycle_offset: u64,
 }
 
 impl Memory for NESMemory {
     fn read(&mut self, address: u16) -> u8 {
-        match address {
+        let val = match address {
             // The first 0x2000 bytes are RAM, but there's only 2KB (0x800) of
             // actual RAM, and the rest is just a mirror of the first 2KB.
             0x0000 ..= 0x1fff => self.ram[address as usize % 0x800],
@@ -33,41 +101,62 @@ impl Memory for NESMemory {
             // address space is just a mirror of these first eight bytes.
             0x2000 ..= 0x3fff => self.ppu.borrow_mut().read(address),
 
-            // APU registers
-            0x4000 ..= 0x4013 => self.apu.borrow_mut().read(address),
+            // APU registers, which are write-only
+            0x4000 ..= 0x4013 => self.bus,
 
-            // OAM DMA
-            0x4014            => 0,
+            // OAM DMA, also write-only
+            0x4014            => self.bus,
 
-            // APU registers
-            0x4015            => self.apu.borrow_mut().read(address),
+            // APU status, where bit 5 isn't connected
+            0x4015            => {
+                let status = self.apu.borrow_mut().read(address);
+                (status & !0x20) | (self.bus & 0x20)
+            },
 
-            // Controller 1
-            0x4016            => self.controller.borrow_mut().read(address),
+            // Controller 1, which only drives the low bits
+            0x4016            => {
+                let val = self.controller.borrow_mut().read(address);
+                (val & 0x1f) | (self.bus & 0xe0)
+            },
 
             // Controller 2
-            0x4017            => 0,
-
-            // Expansion ROM
-            0x4020 ..= 0x5fff => 0,
+            0x4017            => self.bus & 0xe0,
 
-            // SRAM
-            0x6000 ..= 0x7fff => self.ppu.borrow_mut().data.mapper.borrow_mut().read(address),
+            // APU test registers, which are normally disabled
+            0x4018 ..= 0x401f => self.bus,
 
-            // PRG-ROM
-            0x8000 ..= 0xffff => self.ppu.borrow_mut().data.mapper.borrow_mut().read(address),
+            // Expansion ROM, SRAM and PRG-ROM
+            0x4020 ..= 0xffff => {
+                self.ppu.borrow_mut().data.mapper.borrow_mut().read(address)
+                    .unwrap_or(self.bus)
+            },
+        };
 
-            _ => unreachable!("read out of bounds 0x{:04X}", address),
+        // The APU status is read inside the CPU, so it never reaches the bus
+        if address != 0x4015 {
+            self.bus = val;
         }
+
+        val
+    }
+
+    fn set_cycle_offset(&mut self, offset: u64) {
+        self.cycle_offset = offset;
     }
 
     fn write(&mut self, address: u16, val: u8) {
+        self.bus = val;
+
+        let cycle_offset = self.cycle_offset;
+        self.cycle_offset = 0;
+
         match address {
             // RAM
             0x0000 ..= 0x1fff => { self.ram[(address as usize) % 0x800] = val; },
 
-            // PPU registers
-            0x2000 ..= 0x3fff => self.ppu.borrow_mut().write(address, val),
+            // PPU registers, which are written on the dot that the write's
+            // cycle lines up with, 3 dots to a cycle.
+            0x2000 ..= 0x3fff => self.ppu.borrow_mut().write_at(address, val, cycle_offset * 3),
 
             // APU registers
             0x4000 ..= 0x4013 => self.apu.borrow_mut().write(address, val),
@@ -85,7 +174,7 @@ impl Memory for NESMemory {
             0x4017            => { },
 
             // Expansion ROM
-            0x4020 ..= 0x5fff => { },
+            0x4020 ..= 0x5fff => self.ppu.borrow_mut().data.mapper.borrow_mut().write(address, val),
 
             // SRAM
             0x6000 ..= 0x7fff => self.ppu.borrow_mut().data.mapper.borrow_mut().write(address, val),
@@ -111,50 +200,136 @@ impl Memory for NESMemory {
 impl NESMemory {
     pub fn new_nes_mem(ppu: Rc<RefCell<PPU>>,
                        apu: Rc<RefCell<APU>>,
-                       controller: Rc<RefCell<Controller>>)
+                       controller: Rc<RefCell<Controller>>,
+                       ram_init: RAMInit)
         -> Self
     {
+        let mut ram = [0; 0x800];
+        ram_init.fill(&mut ram);
+
         Self {
             ppu: ppu,
             apu: apu,
             controller: controller,
-            ram: [0; 0x800],
+            ram: ram,
+
+            bus: 0,
+            cycle_offset: 0,
         }
     }
 }
 
+// Memory with nothing but a mapper 0 cartridge plugged in, holding the given
+// PRG-ROM and no PRG-RAM
+#[cfg(test)]
+impl NESMemory {
+    pub fn new_test_mem(prg_rom: Vec<u8>) -> Self {
+        use crate::ines::Cartridge;
+        use crate::mapper::{Mapper, Mapper0};
+
+        let cart = Cartridge {
+            prg_ram_size: 0,
+            ..Cartridge::new_cartridge(prg_rom, vec![], 0)
+        };
+        let mapper: Box<dyn Mapper> = Box::new(Mapper0::new_mapper(cart));
+
+        Self::new_nes_mem(
+            Rc::new(RefCell::new(PPU::new_nes_ppu(Rc::new(RefCell::new(mapper))))),
+            Rc::new(RefCell::new(APU::new_nes_apu())),
+            Rc::new(RefCell::new(Controller::new_controller())),
+            RAMInit::Zeros
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_read_write() {
-        let ppu = PPU::new_nes_ppu();
-        let ctrl = Controller::new_controller();
-        let mut mem = NESMemory::new_nes_mem(ppu, ctrl);
+        let mut mem = NESMemory::new_test_mem(vec![0; 0x8000]);
 
-        // RAM
-        assert_eq!(mem.read(0x1000), Ok(0));
-        assert_eq!(mem.write(0x1000, 5), Ok(5));
-        assert_eq!(mem.read(0x1000), Ok(5));
+        // RAM, which is mirrored every 2KB
+        assert_eq!(mem.read(0x1000), 0);
+        mem.write(0x1000, 5);
+        assert_eq!(mem.read(0x1000), 5);
+        assert_eq!(mem.read(0x0000), 5);
 
         // ROM
-        mem.load_rom(&vec![0; 0x8000]);
-        assert_eq!(mem.read(0x8000), Ok(0));
-        assert_eq!(mem.read(0x8001), Ok(0));
-        assert_eq!(mem.read(0xffff), Ok(0));
+        assert_eq!(mem.read(0x8000), 0);
+        assert_eq!(mem.read(0x8001), 0);
+        assert_eq!(mem.read(0xffff), 0);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut mem = NESMemory::new_test_mem(vec![0; 0x8000]);
+        mem.write(0x0000, 0xa5);
+
+        // The write-only APU registers, and the disabled test registers
+        for address in 0x4000 ..= 0x401f {
+            if address == 0x4015 || address == 0x4016 || address == 0x4017 {
+                continue;
+            }
+
+            assert_eq!(mem.read(0x0000), 0xa5);
+            assert_eq!(mem.read(address), 0xa5, "${:04X}", address);
+        }
+
+        // Nothing on the cartridge at $4020-$7FFF
+        for &address in [0x4020, 0x5000, 0x5fff, 0x6000, 0x7fff].iter() {
+            assert_eq!(mem.read(0x0000), 0xa5);
+            assert_eq!(mem.read(address), 0xa5, "${:04X}", address);
+        }
+
+        // The controller ports only drive the low bits
+        mem.read(0x0000);
+        assert_eq!(mem.read(0x4016) & 0xe0, 0xa0);
+        mem.read(0x0000);
+        assert_eq!(mem.read(0x4017), 0xa0);
+
+        // The APU status doesn't reach the bus, and bit 5 isn't driven
+        mem.read(0x0000);
+        assert_eq!(mem.read(0x4015) & 0x20, 0x20);
+        assert_eq!(mem.read(0x4000), 0xa5);
+
+        mem.write(0x0000, 0x00);
+        mem.read(0x0000);
+        assert_eq!(mem.read(0x4015) & 0x20, 0x00);
+    }
+
+    #[test]
+    fn test_rom() {
+        let mut mem = NESMemory::new_test_mem(vec![1; 0x4000]);
+        assert_eq!(mem.read(0x8000), 1);
+        assert_eq!(mem.read(0xffff), 1);
+
+        // ROM doesn't change when written
+        mem.write(0x8000, 2);
+        assert_eq!(mem.read(0x8000), 1);
     }
 
     #[test]
-    fn test_load_rom() {
-        let ppu = PPU::new_nes_ppu();
-        let ctrl = Controller::new_controller();
-        let mut mem = NESMemory::new_nes_mem(ppu, ctrl);
-        mem.load_rom(&vec![0; 0x8000]);
-        assert_eq!(mem.read(0x8000), Ok(0));
-        assert_eq!(mem.read(0xffff), Ok(0));
-        mem.load_rom(&vec![1; 0x8000]);
-        assert_eq!(mem.read(0x8000), Ok(1));
-        assert_eq!(mem.read(0xffff), Ok(1));
+    fn test_ram_init() {
+        assert_eq!(RAMInit::parse("zeros"), Some(RAMInit::Zeros));
+        assert_eq!(RAMInit::parse("ones"), Some(RAMInit::Ones));
+        assert_eq!(RAMInit::parse("random:42"), Some(RAMInit::Random(42)));
+        assert!(RAMInit::parse("random:x").is_none());
+        assert!(RAMInit::parse("twos").is_none());
+
+        let mut ram = [0; 0x800];
+        RAMInit::Ones.fill(&mut ram);
+        assert!(ram.iter().all(|&b| b == 0xff));
+
+        // The same seed always gives the same contents
+        let mut other = [0; 0x800];
+        RAMInit::Random(42).fill(&mut ram);
+        RAMInit::Random(42).fill(&mut other);
+        assert_eq!(ram[..], other[..]);
+        assert!(ram.iter().any(|&b| b != ram[0]));
+
+        RAMInit::Random(43).fill(&mut other);
+        assert_ne!(ram[..], other[..]);
     }
 }
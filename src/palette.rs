@@ -1,7 +1,214 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+
 use sdl2::pixels::Color;
 
+// Each colour emphasis bit in PPUMASK darkens the other two channels, so
+// setting all three darkens everything. This is the fraction of a channel
+// that's left for each bit that darkens it.
+//
+// https://wiki.nesdev.com/w/index.php/Colour_emphasis
+const EMPHASIS_ATTENUATION: f64 = 0.816328;
+
+// Builds the full 512 colour palette from the 64 base colours, with a copy of
+// the base colours for each of the 8 combinations of the emphasis bits. The
+// emphasis bits sit above the 6-bit colour index, the same as they're laid
+// out in PPUMASK.
+pub fn with_emphasis(colors: &[Color]) -> Vec<Color> {
+    let mut palette = Vec::with_capacity(512);
+
+    for emphasis in 0 .. 8 {
+        // Count the bits that are set, other than the channel's own
+        let attenuate = |channel: u8, bit: u8| {
+            let n = (emphasis & !bit).count_ones() as i32;
+            ((channel as f64) * EMPHASIS_ATTENUATION.powi(n)) as u8
+        };
+
+        for color in colors.iter() {
+            palette.push(Color::RGB(
+                attenuate(color.r, 0x01),
+                attenuate(color.g, 0x02),
+                attenuate(color.b, 0x04),
+            ));
+        }
+    }
+
+    palette
+}
+
+// The voltages of the composite signal that the PPU outputs, for the low and
+// then the high half of the square wave at each of the 4 brightness levels,
+// along with the voltages for black and white, and the fraction of the
+// signal that's left when it's darkened by an emphasis bit.
+//
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+const SIGNAL_LEVELS: [f64; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f64 = 0.518;
+const SIGNAL_WHITE: f64 = 1.962;
+const SIGNAL_ATTENUATION: f64 = 0.746;
+
+// The knobs on the TV, for generating a palette from the NTSC signal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NTSCSettings {
+    // Rotates every colour, in degrees
+    pub hue: f64,
+
+    pub saturation: f64,
+    pub contrast: f64,
+    pub brightness: f64,
+
+    // The gamma of the display, where the NTSC standard assumes 2.2
+    pub gamma: f64,
+}
+
+impl NTSCSettings {
+    // These come close to the built-in palette
+    pub fn new_ntsc_settings() -> Self {
+        Self {
+            hue:        0.0,
+            saturation: 1.5,
+            contrast:   1.0,
+            brightness: 0.0,
+            gamma:      2.2,
+        }
+    }
+
+    // Parses a list of settings like "hue=-10,saturation=1.2", and anything
+    // that isn't given keeps its default.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut settings = Self::new_ntsc_settings();
+
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let name = parts.next()?.trim();
+            let val: f64 = parts.next()?.trim().parse().ok()?;
+
+            match name {
+                "hue"        => settings.hue = val,
+                "saturation" => settings.saturation = val,
+                "contrast"   => settings.contrast = val,
+                "brightness" => settings.brightness = val,
+                "gamma"      => settings.gamma = val,
+                _            => return None,
+            }
+        }
+
+        Some(settings)
+    }
+}
+
+// The level of the signal for a colour, at one of the 12 phases of the colour
+// subcarrier. The hue picks which 6 phases are high, and each emphasis bit
+// darkens the signal for a third of the phases.
+fn ntsc_signal(pixel: usize, phase: usize) -> f64 {
+    let color    = pixel & 0x0f;
+    let emphasis = pixel >> 6;
+
+    // Colours $xE and $xF are always black
+    let level = if color > 13 { 1 } else { (pixel >> 4) & 0x03 };
+
+    let in_phase = |color: usize| (color + phase) % 12 < 6;
+
+    // Colour 0 is always high, and colours $xD-$xF are always low, so they're
+    // greys with no colour.
+    let low  = SIGNAL_LEVELS[level];
+    let high = SIGNAL_LEVELS[level + 4];
+
+    let mut signal = match color {
+        0x00        => high,
+        0x0d ..= 0x0f => low,
+        _           => if in_phase(color) { high } else { low },
+    };
+
+    if ((emphasis & 0x01) != 0 && in_phase(0))
+        || ((emphasis & 0x02) != 0 && in_phase(4))
+        || ((emphasis & 0x04) != 0 && in_phase(8))
+    {
+        signal *= SIGNAL_ATTENUATION;
+    }
+
+    signal
+}
+
+// Builds the full 512 colour palette by decoding the NTSC signal for each
+// colour the way a TV would, into YIQ, and then RGB.
+pub fn generate_ntsc_palette(settings: &NTSCSettings) -> Vec<Color> {
+    (0 .. 512).map(|pixel| {
+        let mut y = 0.0;
+        let mut i = 0.0;
+        let mut q = 0.0;
+
+        for phase in 0 .. 12 {
+            let level = ntsc_level(pixel, phase) / 12.0;
+            let angle = subcarrier_angle(phase, settings);
+
+            y += level;
+            i += level * angle.cos();
+            q += level * angle.sin();
+        }
+
+        yiq_to_rgb(y, i, q, settings)
+    } ).collect()
+}
+
+// The level of the signal, scaled so that black is 0 and white is 1
+pub fn ntsc_level(pixel: usize, phase: usize) -> f64 {
+    (ntsc_signal(pixel, phase) - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+// The angle of the colour subcarrier at a phase, that the signal's multiplied
+// by to get the I and Q parts of the colour back out.
+pub fn subcarrier_angle(phase: usize, settings: &NTSCSettings) -> f64 {
+    PI * ((phase + 4) as f64) / 6.0 + settings.hue.to_radians()
+}
+
+// Applies the rest of the TV's settings to a decoded colour, and converts it
+// to RGB.
+pub fn yiq_to_rgb(y: f64, i: f64, q: f64, settings: &NTSCSettings) -> Color {
+    let y = y * settings.contrast + settings.brightness;
+    let i = i * settings.saturation * settings.contrast;
+    let q = q * settings.saturation * settings.contrast;
+
+    let channel = |val: f64| {
+        let val = val.max(0.0).min(1.0).powf(2.2 / settings.gamma);
+        (val * 255.0).round() as u8
+    };
+
+    Color::RGB(
+        channel(y + 0.946882 * i + 0.623557 * q),
+        channel(y - 0.274788 * i - 0.635691 * q),
+        channel(y - 1.108545 * i + 1.709007 * q),
+    )
+}
+
+// .pal files are the RGB bytes of each colour, either the 64 base colours,
+// or all 512 with emphasis, in the same order as the full palette.
+pub fn palette_from_bytes(data: &[u8]) -> io::Result<Vec<Color>> {
+    if data.len() != 192 && data.len() != 1536 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected 192 or 1536 bytes of palette data, but found {}", data.len()),
+        ));
+    }
+
+    let colors: Vec<Color> = data.chunks(3)
+        .map(|c| Color::RGB(c[0], c[1], c[2]))
+        .collect();
+
+    if colors.len() == 64 {
+        Ok(with_emphasis(&colors))
+    } else {
+        Ok(colors)
+    }
+}
+
+pub fn load_palette_file(path: &str) -> io::Result<Vec<Color>> {
+    palette_from_bytes(&fs::read(path)?)
+}
+
 lazy_static!{
-    pub static ref PALETTE: Vec<Color> = [
+    pub static ref PALETTE: Vec<Color> = with_emphasis(&[
             // 0x00
             0x666666, 0x002a88, 0x1412a7, 0x3b00a4, 0x5c007e, 0x6e0040, 0x6c0600, 0x561d00,
 
@@ -33,5 +240,62 @@ lazy_static!{
             let b = c as u8;
             Color::RGB(r, g, b)
         } )
-        .collect::<Vec<_>>();
+        .collect::<Vec<_>>());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emphasis() {
+        assert_eq!(PALETTE.len(), 512);
+        assert_eq!(PALETTE[0x30], Color::RGB(0xff, 0xfe, 0xff));
+
+        // Red emphasis darkens green and blue
+        assert_eq!(PALETTE[0x070], Color::RGB(0xff, 0xcf, 0xd0));
+
+        // Emphasizing everything darkens every channel twice
+        assert_eq!(PALETTE[0x1f0], Color::RGB(0xa9, 0xa9, 0xa9));
+    }
+
+    #[test]
+    fn test_ntsc_palette() {
+        let palette = generate_ntsc_palette(&NTSCSettings::new_ntsc_settings());
+        assert_eq!(palette.len(), 512);
+        assert_eq!(palette[0x0d], Color::RGB(0, 0, 0));
+        assert_eq!(palette[0x30], Color::RGB(255, 255, 255));
+
+        // $16 is a red
+        let red = palette[0x16];
+        assert!(red.r > red.g && red.r > red.b);
+
+        // Red emphasis leaves it alone, but blue emphasis darkens it
+        assert!(palette[0x056].r > palette[0x116].r);
+    }
+
+    #[test]
+    fn test_ntsc_settings() {
+        let settings = NTSCSettings::parse("hue=-10, gamma=1.8").unwrap();
+        assert_eq!(settings.hue, -10.0);
+        assert_eq!(settings.gamma, 1.8);
+        assert_eq!(settings.saturation, 1.5);
+
+        assert_eq!(NTSCSettings::parse(""), Some(NTSCSettings::new_ntsc_settings()));
+        assert_eq!(NTSCSettings::parse("tint=1"), None);
+        assert_eq!(NTSCSettings::parse("hue"), None);
+    }
+
+    #[test]
+    fn test_palette_file() {
+        let palette = palette_from_bytes(&[0x80; 192]).unwrap();
+        assert_eq!(palette.len(), 512);
+        assert_eq!(palette[0x3f], Color::RGB(0x80, 0x80, 0x80));
+        assert_eq!(palette[0x7f], Color::RGB(0x80, 0x68, 0x68));
+
+        let palette = palette_from_bytes(&[0x80; 1536]).unwrap();
+        assert_eq!(palette[0x7f], Color::RGB(0x80, 0x80, 0x80));
+
+        assert!(palette_from_bytes(&[0; 100]).is_err());
+    }
 }
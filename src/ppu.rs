@@ -1,12 +1,17 @@
 mod debug;
+mod filter;
 mod regs;
+mod scaler;
+
+pub use crate::ppu::filter::{Filter, NTSCFilter, NTSCPreset, PaletteFilter};
+pub use crate::ppu::scaler::{PostProcessor, SCALERS};
 
 use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io;
 use std::rc::Rc;
 
-use crate::palette::PALETTE;
 use crate::mapper::{Mapper, MapperEvent};
 use crate::mem::Memory;
 use crate::ppu::regs::PPUCtrl;
@@ -14,9 +19,37 @@ use crate::ppu::regs::PPUMask;
 use crate::ppu::regs::PPUStatus;
 use crate::ppu::regs::OAM;
 use crate::ppu::regs::PPUData;
+use crate::ppu::regs::IOLatch;
 use crate::serde;
 
-use sdl2::pixels::Color;
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// The stages of sprite evaluation
+// http://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+#[derive(Clone, Copy, PartialEq)]
+enum SpriteEvaluation {
+    // Copying the sprites on the next scanline into secondary OAM
+    Copying  = 0,
+
+    // Secondary OAM is full, and the remaining sprites are only checked for
+    // overflow. A hardware bug moves on to the next byte of each sprite as
+    // well as the next sprite, so the wrong bytes get compared.
+    Overflow = 1,
+
+    // Every sprite has been checked, or an overflow has been found
+    Done     = 2,
+}
+
+impl SpriteEvaluation {
+    fn from_u8(val: u8) -> Self {
+        match val {
+            0 => SpriteEvaluation::Copying,
+            1 => SpriteEvaluation::Overflow,
+            _ => SpriteEvaluation::Done,
+        }
+    }
+}
 
 pub struct PPU {
     // PPU registers
@@ -39,12 +72,39 @@ pub struct PPU {
     high_tile_byte: u8,
     tile_data: u64,
 
-    // Sprite data
+    // Sprite data. Only the first 8 slots are filled by the hardware, and the
+    // rest hold the sprites past the limit, when it's turned off.
     sprite_count: usize,
-    sprite_patterns: [u32; 8],
-    sprite_positions: [u8; 8],
-    sprite_priorities: [u8; 8],
-    sprite_indexes: [usize; 8],
+    sprite_patterns: [u32; 64],
+    sprite_positions: [u8; 64],
+    sprite_priorities: [u8; 64],
+
+    // Whether only 8 sprites are drawn on a line, as on the hardware. Games
+    // flicker their sprites to get around it, which turning it off hides.
+    sprite_limit: bool,
+
+    // Whether the first slot holds sprite 0, for sprite zero hits
+    sprite_zero: bool,
+
+    // Sprite evaluation state. The sprites for the next scanline are copied
+    // into secondary OAM during dots 65-256, where n is the sprite and m the
+    // byte of it being read.
+    secondary_oam: [u8; 32],
+    eval: SpriteEvaluation,
+    eval_n: u8,
+    eval_m: u8,
+    eval_byte: usize,
+    eval_count: usize,
+    eval_sprite_zero: bool,
+
+    // The sprite that evaluation started from, and the one it had reached
+    // when secondary OAM filled up, where the sprites past the limit start,
+    // or 64 if it didn't fill up.
+    eval_start: u8,
+    eval_extra: u8,
+
+    // The last value read from OAM while rendering, which is what $2004 sees
+    oam_bus: u8,
 
     // Odd/even frame state
     odd_frame: bool,
@@ -60,14 +120,47 @@ pub struct PPU {
     x: u8,
     w: bool,
 
+    // The second write to PPUADDR copies t into v a few dots later, rather
+    // than straight away, and this counts them down.
+    ppu_addr_delay: u8,
+
     // PPUDATA read buffer
     buffered_data: u8,
 
-    // The last written value to any PPU register
-    // For use when reading the PPUSTATUS
-    last_value: u8,
-
-    pixels: Vec<Vec<Color>>,
+    // After power on or reset, writes to PPUCTRL, PPUMASK, PPUSCROLL and
+    // PPUADDR are ignored until the end of the first vblank, around 29658
+    // CPU cycles later.
+    //
+    // https://wiki.nesdev.com/w/index.php/PPU_power_up_state
+    warming_up: bool,
+
+    // The last value written to or read from any PPU register, which is left
+    // on the PPU's data bus, and read back from the write-only registers and
+    // the bits of the others that aren't driven.
+    latch: IOLatch,
+
+    // Frames since power on, for timing the decay of the I/O latch
+    frame: u64,
+
+    // The phase of the NTSC colour subcarrier, in twelfths of a cycle. Each
+    // dot takes 8 twelfths, so it shifts by a third of a cycle every line,
+    // and every frame, except when the odd frame's dot is skipped.
+    //
+    // https://wiki.nesdev.com/w/index.php/NTSC_video
+    phase: u8,
+
+    // The phase at the first pixel of each line of the frame being drawn
+    line_phases: [u8; SCREEN_HEIGHT],
+
+    // Dots since power on, and writes from the CPU that are waiting for the
+    // dot they land on, along with the register and value.
+    dots: u64,
+    pending_writes: VecDeque<(u64, u16, u8)>,
+
+    // The frame being drawn, with the palette index of each pixel in the low
+    // 6 bits, and the emphasis bits from PPUMASK above them. The conversion to
+    // RGB is left to a Filter.
+    pixels: Box<[u16; SCREEN_WIDTH * SCREEN_HEIGHT]>,
 }
 
 impl Memory for PPU {
@@ -76,14 +169,10 @@ impl Memory for PPU {
         // address space is just a mirror of these first eight bytes.
         let address = address % 8 + 0x2000;
         match address {
-            0x2000 => {
-                let PPUCtrl(n) = self.ctrl;
-                n
-            },
-            0x2001 => {
-                let PPUMask(n) = self.mask;
-                n
-            },
+            // Write-only registers read back whatever's left on the PPU's
+            // data bus, from the last read or write of any register.
+            0x2000 => self.latch.value(self.frame),
+            0x2001 => self.latch.value(self.frame),
             0x2002 => {
                 let PPUStatus(mut n) = self.status;
 
@@ -91,7 +180,7 @@ impl Memory for PPU {
                 // register), set the first 5 bits of the PPUSTATUS value to
                 // the first 5 bits of _that_ last value.
                 n &= ! 0x1f;
-                n |= self.last_value & 0x1f;
+                n |= self.latch.value(self.frame) & 0x1f;
 
                 if self.nmi_occurred {
                     n |= 1 << 7;
@@ -102,12 +191,22 @@ impl Memory for PPU {
                 // w:                  = 0
                 self.w = false;
 
+                self.latch.refresh(n, 0xe0, self.frame);
                 n
             },
-            0x2003 => 0, // OAMADDR is write-only
-            0x2004 => self.oam.read(self.oam_addr as u16),
-            0x2005 => 0, // PPUSCROLL is write-only
-            0x2006 => 0, // PPUADDR is write-only
+            0x2003 => self.latch.value(self.frame), // OAMADDR is write-only
+            0x2004 => {
+                let n = if self.rendering_enabled() && self.scanline <= 239 {
+                    self.oam_bus
+                } else {
+                    self.oam.read(self.oam_addr as u16)
+                };
+
+                self.latch.refresh(n, 0xff, self.frame);
+                n
+            },
+            0x2005 => self.latch.value(self.frame), // PPUSCROLL is write-only
+            0x2006 => self.latch.value(self.frame), // PPUADDR is write-only
             0x2007 => {
                 let rv;
 
@@ -116,17 +215,19 @@ impl Memory for PPU {
                 if self.ppu_addr % 0x4000 <= 0x3eff {
                     rv = self.buffered_data;
                     self.buffered_data = self.data.read(self.ppu_addr);
+                    self.latch.refresh(rv, 0xff, self.frame);
                 } else {
                     // TODO why do we subtract 0x1000 ?
                     self.buffered_data = self.data.read(self.ppu_addr - 0x1000);
-                    rv = self.data.read(self.ppu_addr);
-                }
 
-                self.ppu_addr = self.ppu_addr.wrapping_add(
-                    self.ctrl.vram_addr_increment());
+                    // Palette entries are only 6 bits, the rest come from
+                    // the data bus.
+                    let n = self.data.read(self.ppu_addr) & 0x3f;
+                    rv = n | (self.latch.value(self.frame) & 0xc0);
+                    self.latch.refresh(n, 0x3f, self.frame);
+                }
 
-                self.data.mapper.borrow_mut()
-                    .notify(MapperEvent::VRAMAddressChange(self.ppu_addr));
+                self.increment_ppu_addr();
 
                 rv
             },
@@ -136,9 +237,16 @@ impl Memory for PPU {
     }
 
     fn write(&mut self, address: u16, val: u8) {
-        self.last_value = val;
+        self.latch.refresh(val, 0xff, self.frame);
 
         let address = address % 8 + 0x2000;
+        if self.warming_up {
+            match address {
+                0x2000 | 0x2001 | 0x2005 | 0x2006 => return,
+                _ => { },
+            }
+        }
+
         match address {
             0x2000 => {
                 self.ctrl = PPUCtrl(val);
@@ -154,8 +262,14 @@ impl Memory for PPU {
             0x2002 => { },
             0x2003 => { self.oam_addr = val },
             0x2004 => {
-                self.oam.write(self.oam_addr as u16, val);
-                self.oam_addr = self.oam_addr.wrapping_add(1);
+                // Writes while rendering don't reach OAM, but bump the sprite
+                // part of OAMADDR instead.
+                if self.rendering_active() {
+                    self.oam_addr = self.oam_addr.wrapping_add(4);
+                } else {
+                    self.oam.write(self.oam_addr as u16, val);
+                    self.oam_addr = self.oam_addr.wrapping_add(1);
+                }
             },
             0x2005 => {
                 if self.w {
@@ -179,15 +293,12 @@ impl Memory for PPU {
             0x2006 => {
                 if self.w {
                     // t: ....... HGFEDCBA = d: HGFEDCBA
-                    // v                   = t
+                    // v                   = t, 3 dots later
                     // w:                  = 0
                     self.t = (self.t & 0xff00)
                            | (val as u16);
-                    self.ppu_addr = self.t;
+                    self.ppu_addr_delay = 3;
                     self.w = false;
-
-                    self.data.mapper.borrow_mut()
-                        .notify(MapperEvent::VRAMAddressChange(self.ppu_addr));
                 } else {
                     // t: .FEDCBA ........ = d: ..FEDCBA
                     // t: X...... ........ = 0
@@ -199,11 +310,7 @@ impl Memory for PPU {
             },
             0x2007 => {
                 self.data.write(self.ppu_addr, val);
-                self.ppu_addr = self.ppu_addr.wrapping_add(
-                    self.ctrl.vram_addr_increment());
-
-                self.data.mapper.borrow_mut()
-                    .notify(MapperEvent::VRAMAddressChange(self.ppu_addr));
+                self.increment_ppu_addr();
             },
 
             _ => panic!("bad PPU address 0x{:04X}", address)
@@ -236,24 +343,49 @@ impl Memory for PPU {
         serde::encode_u64(output, self.tile_data)?;
 
         serde::encode_usize(output, self.sprite_count)?;
-        for i in 0 .. 7 {
+        for i in 0 .. self.sprite_count {
             serde::encode_u32(output, self.sprite_patterns[i])?;
             serde::encode_u8(output, self.sprite_positions[i])?;
             serde::encode_u8(output, self.sprite_priorities[i])?;
-            serde::encode_usize(output, self.sprite_indexes[i])?;
         }
+        serde::encode_u8(output, self.sprite_zero as u8)?;
+
+        serde::encode_vec(output, &self.secondary_oam.to_vec())?;
+        serde::encode_u8(output, self.eval as u8)?;
+        serde::encode_u8(output, self.eval_n)?;
+        serde::encode_u8(output, self.eval_m)?;
+        serde::encode_usize(output, self.eval_byte)?;
+        serde::encode_usize(output, self.eval_count)?;
+        serde::encode_u8(output, self.eval_sprite_zero as u8)?;
+        serde::encode_u8(output, self.eval_start)?;
+        serde::encode_u8(output, self.eval_extra)?;
+        serde::encode_u8(output, self.oam_bus)?;
 
         serde::encode_u8(output, self.odd_frame as u8)?;
         serde::encode_u8(output, self.nmi_occurred as u8)?;
         serde::encode_u8(output, self.nmi_output as u8)?;
+        serde::encode_u8(output, self.nmi_previous as u8)?;
         serde::encode_usize(output, self.nmi_delay)?;
 
         serde::encode_u16(output, self.t)?;
         serde::encode_u8(output, self.x)?;
         serde::encode_u8(output, self.w as u8)?;
+        serde::encode_u8(output, self.ppu_addr_delay)?;
 
         serde::encode_u8(output, self.buffered_data)?;
-        serde::encode_u8(output, self.last_value)?;
+        self.latch.save(output)?;
+        serde::encode_u64(output, self.frame)?;
+        serde::encode_u8(output, self.phase)?;
+        serde::encode_u8(output, self.warming_up as u8)?;
+        serde::encode_vec(output, &self.line_phases.to_vec())?;
+
+        serde::encode_u64(output, self.dots)?;
+        serde::encode_usize(output, self.pending_writes.len())?;
+        for &(dot, address, val) in self.pending_writes.iter() {
+            serde::encode_u64(output, dot)?;
+            serde::encode_u16(output, address)?;
+            serde::encode_u8(output, val)?;
+        }
 
         Ok(())
     }
@@ -278,12 +410,24 @@ impl Memory for PPU {
         self.tile_data = serde::decode_u64(input)?;
 
         self.sprite_count = serde::decode_usize(input)?;
-        for i in 0 .. 7 {
+        for i in 0 .. self.sprite_count {
             self.sprite_patterns[i] = serde::decode_u32(input)?;
             self.sprite_positions[i] = serde::decode_u8(input)?;
             self.sprite_priorities[i] = serde::decode_u8(input)?;
-            self.sprite_indexes[i] = serde::decode_usize(input)?;
         }
+        self.sprite_zero = serde::decode_u8(input)? != 0;
+
+        let secondary_oam = serde::decode_vec(input)?;
+        self.secondary_oam.copy_from_slice(&secondary_oam);
+        self.eval = SpriteEvaluation::from_u8(serde::decode_u8(input)?);
+        self.eval_n = serde::decode_u8(input)?;
+        self.eval_m = serde::decode_u8(input)?;
+        self.eval_byte = serde::decode_usize(input)?;
+        self.eval_count = serde::decode_usize(input)?;
+        self.eval_sprite_zero = serde::decode_u8(input)? != 0;
+        self.eval_start = serde::decode_u8(input)?;
+        self.eval_extra = serde::decode_u8(input)?;
+        self.oam_bus = serde::decode_u8(input)?;
 
         self.odd_frame = serde::decode_u8(input)? != 0;
         self.nmi_occurred = serde::decode_u8(input)? != 0;
@@ -294,9 +438,25 @@ impl Memory for PPU {
         self.t = serde::decode_u16(input)?;
         self.x = serde::decode_u8(input)?;
         self.w = serde::decode_u8(input)? != 0;
+        self.ppu_addr_delay = serde::decode_u8(input)?;
 
         self.buffered_data = serde::decode_u8(input)?;
-        self.last_value = serde::decode_u8(input)?;
+        self.latch.load(input)?;
+        self.frame = serde::decode_u64(input)?;
+        self.phase = serde::decode_u8(input)?;
+        self.warming_up = serde::decode_u8(input)? != 0;
+        let line_phases = serde::decode_vec(input)?;
+        self.line_phases.copy_from_slice(&line_phases);
+
+        self.dots = serde::decode_u64(input)?;
+        let n_writes = serde::decode_usize(input)?;
+        self.pending_writes.clear();
+        for _ in 0 .. n_writes {
+            let dot = serde::decode_u64(input)?;
+            let address = serde::decode_u16(input)?;
+            let val = serde::decode_u8(input)?;
+            self.pending_writes.push_back((dot, address, val));
+        }
 
         Ok(())
     }
@@ -328,10 +488,25 @@ impl PPU {
             tile_data: 0,
 
             sprite_count: 0,
-            sprite_patterns: [0; 8],
-            sprite_positions: [0; 8],
-            sprite_priorities: [0; 8],
-            sprite_indexes: [0; 8],
+            sprite_patterns: [0; 64],
+            sprite_positions: [0; 64],
+            sprite_priorities: [0; 64],
+
+            sprite_limit: true,
+
+            sprite_zero: false,
+
+            secondary_oam: [0xff; 32],
+            eval: SpriteEvaluation::Done,
+            eval_n: 0,
+            eval_m: 0,
+            eval_byte: 0,
+            eval_count: 0,
+            eval_sprite_zero: false,
+            eval_start: 0,
+            eval_extra: 64,
+
+            oam_bus: 0,
 
             odd_frame: false,
 
@@ -344,22 +519,108 @@ impl PPU {
             x: 0,
             w: false,
 
+            ppu_addr_delay: 0,
+
             buffered_data: 0,
 
-            last_value: 0,
+            warming_up: false,
+
+            latch: IOLatch::new_io_latch(),
+            frame: 0,
+
+            phase: 0,
+            line_phases: [0; SCREEN_HEIGHT],
+
+            dots: 0,
+            pending_writes: VecDeque::new(),
 
-            pixels: vec![vec![Color::RGB(0, 0, 0); 256]; 240],
+            pixels: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
         }
     }
 
-    pub fn get_pixels(&self) -> &Vec<Vec<Color>> {
+    // Puts the PPU in the state it powers up in. The registers are cleared,
+    // and it starts from the top of the frame. OAM, palette and nametable
+    // contents are left as they are.
+    pub fn power_on(&mut self) {
+        self.reset();
+
+        self.status = PPUStatus(0);
+        self.oam_addr = 0;
+        self.ppu_addr = 0;
+        self.ppu_addr_delay = 0;
+
+        self.dot = 0;
+        self.scanline = 0;
+    }
+
+    // Pressing reset only clears some of the registers. OAMADDR, PPUSTATUS and
+    // v are kept, and the PPU carries on from wherever it was in the frame.
+    pub fn reset(&mut self) {
+        self.ctrl = PPUCtrl(0);
+        self.mask = PPUMask(0);
+        self.nmi_output = false;
+        self.nmi_change();
+
+        self.t = 0;
+        self.x = 0;
+        self.w = false;
+        self.buffered_data = 0;
+        self.odd_frame = false;
+
+        self.pending_writes.clear();
+        self.warming_up = true;
+    }
+
+    pub fn set_sprite_limit(&mut self, limit: bool) {
+        self.sprite_limit = limit;
+    }
+
+    pub fn get_pixels(&self) -> &[u16; SCREEN_WIDTH * SCREEN_HEIGHT] {
         &self.pixels
     }
 
+    pub fn get_line_phases(&self) -> &[u8; SCREEN_HEIGHT] {
+        &self.line_phases
+    }
+
     fn rendering_enabled(&self) -> bool {
         self.mask.show_background() || self.mask.show_sprites()
     }
 
+    // Whether the PPU is busy fetching tiles and sprites right now
+    fn rendering_active(&self) -> bool {
+        self.rendering_enabled() && (self.scanline <= 239 || self.scanline == 261)
+    }
+
+    // Writes a register, `dots' dots from now. The CPU runs a whole
+    // instruction before the PPU catches up with it, so this lets a write land
+    // on the dot that it would on the hardware, partway through the
+    // instruction, which matters for raster effects.
+    pub fn write_at(&mut self, address: u16, val: u8, dots: u64) {
+        if dots == 0 {
+            self.write(address, val);
+        } else {
+            self.pending_writes.push_back((self.dots + dots, address, val));
+        }
+    }
+
+    // Moves on after a PPUDATA access. While rendering, v is being used to
+    // fetch tiles, and the access bumps coarse X and Y together instead.
+    //
+    // https://wiki.nesdev.com/w/index.php/PPU_scrolling#.242007_reads_and_writes
+    fn increment_ppu_addr(&mut self) {
+        if self.rendering_active() {
+            self.increment_x();
+            self.increment_y();
+        } else {
+            self.ppu_addr = self.ppu_addr.wrapping_add(
+                self.ctrl.vram_addr_increment());
+        }
+
+        self.data.mapper.borrow_mut()
+            .notify(MapperEvent::VRAMAddressChange(self.ppu_addr));
+    }
+
     fn nmi_change(&mut self) {
         let nmi = self.nmi_output && self.nmi_occurred;
         if nmi && !self.nmi_previous {
@@ -453,14 +714,9 @@ impl PPU {
         None
     }
 
-    // Fetches the sprite pattern for a single row of a tile. If you wanted the
-    // pattern tables for every row of a sprite, you would call this with the
-    // `row' parameter being the values from 0 to 7 (inclusive).
-    fn fetch_sprite_pattern(&mut self, i: u16, row: i16) -> u32 {
-        let mut tile = self.oam.read(i * 4 + 1) as u16;
-        let attributes = self.oam.read(i * 4 + 2);
-
-        let address: u16;
+    // The address of the low byte of a row of a sprite's pattern
+    fn sprite_pattern_address(&self, tile: u8, attributes: u8, row: i16) -> u16 {
+        let mut tile = tile as u16;
         let mut row = row;
 
         if self.ctrl.sprite_size() == 8 {
@@ -468,9 +724,9 @@ impl PPU {
                 row = 7 - row;
             }
 
-            address = self.ctrl.sprite_pattern_table_addr()
+            self.ctrl.sprite_pattern_table_addr()
                 + (tile * 16)
-                + row as u16;
+                + row as u16
         } else {
             if attributes & 0x80 == 0x80 {
                 row = 15 - row;
@@ -484,14 +740,28 @@ impl PPU {
                 row -= 8;
             }
 
-            address = 0x1000 * table
+            0x1000 * table
                 + (tile * 16)
-                + row as u16;
+                + row as u16
         }
+    }
+
+    // Fetches the sprite pattern for a single row of a tile. If you wanted the
+    // pattern tables for every row of a sprite, you would call this with the
+    // `row' parameter being the values from 0 to 7 (inclusive).
+    fn fetch_sprite_pattern(&mut self, tile: u8, attributes: u8, row: i16) -> u32 {
+        let address = self.sprite_pattern_address(tile, attributes, row);
+
+        let low_tile_byte = self.data.read(address);
+        let high_tile_byte = self.data.read(address + 8);
+
+        Self::sprite_pattern(low_tile_byte, high_tile_byte, attributes)
+    }
 
+    fn sprite_pattern(low_tile_byte: u8, high_tile_byte: u8, attributes: u8) -> u32 {
         let a = ((attributes & 3) << 2) as u32;
-        let mut low_tile_byte = self.data.read(address) as u32;
-        let mut high_tile_byte = self.data.read(address + 8) as u32;
+        let mut low_tile_byte = low_tile_byte as u32;
+        let mut high_tile_byte = high_tile_byte as u32;
 
         // Now we need to return a 32-bit unsigned value, representing the 8
         // pixels of this row of the sprite. This means we have 4 bits per
@@ -528,44 +798,212 @@ impl PPU {
         } )
     }
 
+    fn sprite_in_range(&self, y: u8) -> bool {
+        let row = (self.scanline as i16) - (y as i16);
+        row >= 0 && row < self.ctrl.sprite_size() as i16
+    }
+
+    // Moves on to the next sprite in OAM, finishing once all 64 are done
+    fn next_sprite(&mut self) {
+        self.eval_n = (self.eval_n + 1) & 0x3f;
+
+        if self.eval_n == 0 {
+            self.eval = SpriteEvaluation::Done;
+        }
+    }
+
+    // Moves on to the next byte in OAM, carrying into the next sprite
+    fn next_sprite_byte(&mut self) {
+        self.eval_m = (self.eval_m + 1) & 0x03;
+
+        if self.eval_m == 0 {
+            self.next_sprite();
+        }
+    }
+
+    // Sprite evaluation for the next scanline, run a dot at a time.
+    //
+    // Dots 1-64 clear secondary OAM to $FF. Dots 65-256 then alternate between
+    // reading a byte of OAM on odd dots, and dealing with it on even dots. The
+    // evaluation starts from wherever OAMADDR points, rather than sprite 0.
+    //
+    // http://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
     fn evaluate_sprites(&mut self) {
-        let sz = self.ctrl.sprite_size() as i16;
+        match self.dot {
+            1 ..= 64 => {
+                self.oam_bus = 0xff;
+
+                if self.dot % 2 == 0 {
+                    self.secondary_oam[(self.dot / 2 - 1) as usize] = 0xff;
+                }
+            },
+            65 ..= 256 => {
+                if self.dot == 65 {
+                    self.eval = SpriteEvaluation::Copying;
+                    self.eval_n = self.oam_addr >> 2;
+                    self.eval_m = self.oam_addr & 0x03;
+                    self.eval_start = self.eval_n;
+                    self.eval_byte = 0;
+                    self.eval_count = 0;
+                    self.eval_sprite_zero = false;
+                    self.eval_extra = 64;
+                }
 
-        let mut count = 0;
+                if self.dot % 2 == 1 {
+                    let address = (self.eval_n as u16) * 4 + self.eval_m as u16;
+                    self.oam_bus = self.oam.read(address);
+                } else {
+                    self.evaluate_sprite_byte();
+                }
+            },
+            _ => { },
+        }
+    }
 
-        for i in 0 .. 64 {
-            let sprite = i as u16;
-            let y = self.oam.read(sprite * 4 + 0);
-            let a = self.oam.read(sprite * 4 + 2);
-            let x = self.oam.read(sprite * 4 + 3);
+    fn evaluate_sprite_byte(&mut self) {
+        let val = self.oam_bus;
 
-            let row: i16 = (self.scanline as i16) - (y as i16);
+        match self.eval {
+            SpriteEvaluation::Copying => {
+                // The Y coordinate is always copied, but only kept if the
+                // sprite's in range.
+                self.secondary_oam[self.eval_count * 4 + self.eval_byte] = val;
 
-            if row < 0 || row >= sz {
-                continue
-            }
+                if self.eval_byte == 0 && !self.sprite_in_range(val) {
+                    self.next_sprite();
+                    return;
+                }
 
-            if count < 8 {
-                self.sprite_patterns[count] = self.fetch_sprite_pattern(sprite, row);
-                self.sprite_positions[count] = x;
-                self.sprite_priorities[count] = (a >> 5) & 1;
-                self.sprite_indexes[count] = i;
-            }
+                // The first sprite checked counts as sprite 0
+                if self.eval_byte == 0 && self.dot == 66 {
+                    self.eval_sprite_zero = true;
+                }
+
+                self.eval_byte = (self.eval_byte + 1) % 4;
+                self.next_sprite_byte();
+
+                if self.eval_byte == 0 {
+                    self.eval_count += 1;
 
-            count += 1;
+                    // The eighth sprite may have been the last one before n
+                    // wrapped around, leaving nothing for the overflow check.
+                    if self.eval_count == 8 {
+                        if self.eval == SpriteEvaluation::Copying {
+                            self.eval = SpriteEvaluation::Overflow;
+                        }
+                        self.eval_extra = self.eval_n;
+                    }
+                }
+            },
+            SpriteEvaluation::Overflow => {
+                if self.sprite_in_range(val) {
+                    self.status.set_sprite_overflow();
+                    self.eval = SpriteEvaluation::Done;
+                } else {
+                    // The hardware bug, m is incremented without a carry
+                    self.eval_m = (self.eval_m + 1) & 0x03;
+                    self.next_sprite();
+                }
+            },
+            SpriteEvaluation::Done => {
+                // Keeps reading the Y coordinate of each sprite, but nothing
+                // more is copied.
+                self.eval_n = (self.eval_n + 1) & 0x3f;
+            },
+        }
+    }
+
+    // Sprite patterns are fetched from secondary OAM during dots 257-320,
+    // eight dots per sprite, with OAMADDR held at 0. Slots without a sprite
+    // still fetch tile $FF, which matters to mappers like the MMC3 that watch
+    // the pattern fetches.
+    fn fetch_sprites(&mut self, pre_line: bool) {
+        match self.dot {
+            257 ..= 320 => {
+                if self.dot == 257 {
+                    self.sprite_count = if pre_line { 0 } else { self.eval_count };
+                    self.sprite_zero = !pre_line && self.eval_sprite_zero;
+
+                    if !pre_line && !self.sprite_limit {
+                        self.fetch_extra_sprites();
+                    }
+                }
+
+                self.oam_addr = 0;
+
+                let slot = ((self.dot - 257) / 8) as usize;
+                let byte = std::cmp::min((self.dot - 257) % 8, 3) as usize;
+                self.oam_bus = self.secondary_oam[slot * 4 + byte];
+
+                if (self.dot - 257) % 8 == 4 {
+                    self.fetch_sprite(slot);
+                }
+            },
+            321 ..= 340 | 0 => {
+                self.oam_bus = self.secondary_oam[0];
+            },
+            _ => { },
         }
+    }
 
-        if count > 8 {
-            count = 8;
-            self.status.set_sprite_overflow();
+    fn fetch_sprite(&mut self, slot: usize) {
+        let y          = self.secondary_oam[slot * 4 + 0];
+        let tile       = self.secondary_oam[slot * 4 + 1];
+        let attributes = self.secondary_oam[slot * 4 + 2];
+        let x          = self.secondary_oam[slot * 4 + 3];
+
+        if slot < self.sprite_count {
+            let row = (self.scanline as i16) - (y as i16);
+            self.sprite_patterns[slot] = self.fetch_sprite_pattern(tile, attributes, row);
+            self.sprite_positions[slot] = x;
+            self.sprite_priorities[slot] = (attributes >> 5) & 1;
+        } else {
+            let address = self.sprite_pattern_address(tile, attributes, 0);
+            self.data.read(address);
+            self.data.read(address + 8);
         }
+    }
+
+    // Fills the slots past the first 8 with the rest of the sprites on the
+    // line, in the order the evaluation would've reached them, carrying on
+    // from the sprite after the eighth and wrapping round to where it started.
+    // They're drawn behind the others, and never count towards a sprite zero
+    // hit, and their patterns are read without the cartridge seeing, so games
+    // can't tell.
+    fn fetch_extra_sprites(&mut self) {
+        if self.eval_extra >= 64 {
+            return;
+        }
+
+        let mut n = self.eval_extra as u16;
+        while n != self.eval_start as u16 {
+            let sprite = n;
+            n = (n + 1) & 0x3f;
+
+            let y = self.oam.read(sprite * 4);
+            if !self.sprite_in_range(y) {
+                continue;
+            }
+
+            let tile       = self.oam.read(sprite * 4 + 1);
+            let attributes = self.oam.read(sprite * 4 + 2);
+            let x          = self.oam.read(sprite * 4 + 3);
 
-        self.sprite_count = count;
+            let row     = (self.scanline as i16) - (y as i16);
+            let address = self.sprite_pattern_address(tile, attributes, row);
+            let low     = self.data.peek_pattern(address);
+            let high    = self.data.peek_pattern(address + 8);
+
+            let slot = self.sprite_count;
+            self.sprite_patterns[slot] = Self::sprite_pattern(low, high, attributes);
+            self.sprite_positions[slot] = x;
+            self.sprite_priorities[slot] = (attributes >> 5) & 1;
+            self.sprite_count += 1;
+        }
     }
 
     fn render_pixel(&mut self) {
         let x = self.dot - 1;
-        let y = self.scanline;
 
         let mut background  = self.background_pixel().unwrap_or(0);
         let     (i, sprite) = self.sprite_pixel().unwrap_or((0, 0));
@@ -594,7 +1032,7 @@ impl PPU {
                 background as u16
             },
             (true, true) => {
-                if self.sprite_indexes[i] == 0 && x < 255 {
+                if i == 0 && self.sprite_zero && x < 255 {
                     self.status.set_sprite_zero_hit();
                 }
 
@@ -607,15 +1045,36 @@ impl PPU {
         };
 
         // Set the base palette address
-        let address = 0x3f00 | address_low_nyb;
+        self.output_pixel(0x3f00 | address_low_nyb);
+    }
 
-        let palette_index = self.data.read(address) % 64;
-        let color = PALETTE[palette_index as usize];
-        //let rect = Rect::new((x as i32) * 3, (y as i32) * 3, 3, 3);
+    // With rendering off, the backdrop colour's drawn. If v points into the
+    // palette though, that colour's drawn instead, which some games use to
+    // draw with the palette partway through a line.
+    //
+    // https://wiki.nesdev.com/w/index.php/PPU_palettes#The_background_palette_hack
+    fn render_backdrop(&mut self) {
+        if (self.ppu_addr & 0x3f00) == 0x3f00 {
+            self.output_pixel(self.ppu_addr);
+        } else {
+            self.output_pixel(0x3f00);
+        }
+    }
+
+    fn output_pixel(&mut self, address: u16) {
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+
+        // Greyscale takes the colour out of the index, leaving only the
+        // brightness, and the emphasis bits choose which copy of the palette
+        // to use.
+        let mut palette_index = self.data.read(address) & 0x3f;
+        if self.mask.greyscale() {
+            palette_index &= 0x30;
+        }
 
-        let x = x as usize;
-        let y = y as usize;
-        self.pixels[y][x] = color;
+        let emphasis = self.mask.emphasis() as u16;
+        self.pixels[y * SCREEN_WIDTH + x] = (emphasis << 6) | palette_index as u16;
     }
 
     fn fetch_nametable_byte(&mut self) -> u8 {
@@ -694,11 +1153,14 @@ impl PPU {
             }
         }
 
+        self.phase = (self.phase + 8) % 12;
+
         if self.rendering_enabled() {
             if self.odd_frame && self.scanline == 261 && self.dot == 339 {
                 self.dot = 0;
                 self.scanline = 0;
                 self.odd_frame = false;
+                self.frame += 1;
                 return;
             }
         }
@@ -711,6 +1173,7 @@ impl PPU {
             if self.scanline > 261 {
                 self.scanline = 0;
                 self.odd_frame = ! self.odd_frame;
+                self.frame += 1;
             }
         }
     }
@@ -732,7 +1195,29 @@ impl PPU {
             frame_finished: false,
         };
 
+        // Writes from the CPU that are due by this dot
+        while let Some(&(dot, address, val)) = self.pending_writes.front() {
+            if dot > self.dots {
+                break;
+            }
+
+            self.pending_writes.pop_front();
+            self.write(address, val);
+        }
+        self.dots += 1;
+
         self.tick(&mut res);
+        self.data.mapper.borrow_mut().notify(MapperEvent::PPUTick);
+
+        if self.ppu_addr_delay > 0 {
+            self.ppu_addr_delay -= 1;
+
+            if self.ppu_addr_delay == 0 {
+                self.ppu_addr = self.t;
+                self.data.mapper.borrow_mut()
+                    .notify(MapperEvent::VRAMAddressChange(self.ppu_addr));
+            }
+        }
 
         // All of this logic has been borrowed from github.com/fogleman/nes
 
@@ -745,6 +1230,14 @@ impl PPU {
         let visible_cycle   = self.dot >= 1   && self.dot <= 256;
         let fetch_cycle     = pre_fetch_cycle || visible_cycle;
 
+        if visible_line && self.dot == 1 {
+            self.line_phases[self.scanline as usize] = self.phase;
+        }
+
+        if visible_line && visible_cycle && !self.rendering_enabled() {
+            self.render_backdrop();
+        }
+
         // background logic
         if self.rendering_enabled() {
             if visible_line && visible_cycle {
@@ -800,13 +1293,14 @@ impl PPU {
         }
 
         // sprite logic
-        if self.rendering_enabled() && self.dot == 257 {
+        if self.rendering_enabled() {
             if visible_line {
                 self.evaluate_sprites();
-            } else {
-                self.sprite_count = 0;
             }
 
+            if render_line {
+                self.fetch_sprites(pre_line);
+            }
         }
 
         // vblank logic
@@ -820,17 +1314,9 @@ impl PPU {
             return res;
         }
 
-        // TODO
-        // This isn't entirely correct. It should be at dot 260 as far as NesDev
-        // tells me, but changing this to 260 messes up the HUD in Kirby.
-        // I have a feeling that the IRQ counting for MMC3 games still has
-        // problems and needs to be looked at in its entirety again.
-        if (pre_line || visible_line) && self.rendering_enabled() && self.dot == 280 {
-            self.data.mapper.borrow_mut().notify(MapperEvent::HBlank);
-        }
-
         if pre_line && self.dot == 1 {
             debug!("vblank ended");
+            self.warming_up = false;
             self.status.clear_sprite_zero_hit();
             self.status.clear_sprite_overflow();
 
@@ -841,3 +1327,272 @@ impl PPU {
         return res;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ines::Cartridge;
+    use crate::mapper::Mapper0;
+
+    fn new_ppu() -> PPU {
+        let cart = Cartridge::new_cartridge(vec![0; 0x8000], vec![], 0);
+        let mapper: Box<dyn Mapper> = Box::new(Mapper0::new_mapper(cart));
+        PPU::new_nes_ppu(Rc::new(RefCell::new(mapper)))
+    }
+
+    #[test]
+    fn test_write_timing() {
+        let mut ppu = new_ppu();
+
+        // Writes land after the given number of dots
+        ppu.write_at(0x2003, 0x10, 3);
+        ppu.step();
+        ppu.step();
+        ppu.step();
+        assert_eq!(ppu.oam_addr, 0);
+        ppu.step();
+        assert_eq!(ppu.oam_addr, 0x10);
+
+        // And v is only set a few dots after the second PPUADDR write
+        ppu.write(0x2006, 0x21);
+        ppu.write(0x2006, 0x08);
+        assert_eq!(ppu.ppu_addr, 0);
+        ppu.step();
+        ppu.step();
+        ppu.step();
+        assert_eq!(ppu.ppu_addr, 0x2108);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut ppu = new_ppu();
+        ppu.line_phases[10] = 2;
+        ppu.nmi_previous = true;
+
+        // A write that hasn't landed yet when the state is saved
+        ppu.write_at(0x2003, 0x10, 3);
+        ppu.step();
+
+        let path = std::env::temp_dir().join("ppu_test_save_load.state");
+        ppu.save(&mut File::create(&path).unwrap()).unwrap();
+
+        let mut loaded = new_ppu();
+        loaded.load(&mut File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.dots, ppu.dots);
+        assert_eq!(loaded.line_phases[10], 2);
+        assert!(loaded.nmi_previous);
+
+        loaded.step();
+        loaded.step();
+        assert_eq!(loaded.oam_addr, 0);
+        loaded.step();
+        assert_eq!(loaded.oam_addr, 0x10);
+    }
+
+    #[test]
+    fn test_ppudata_while_rendering() {
+        let mut ppu = new_ppu();
+        ppu.ppu_addr = 0x2000;
+        ppu.write(0x2007, 0);
+        assert_eq!(ppu.ppu_addr, 0x2001);
+
+        // While rendering, coarse X and fine Y are both incremented
+        ppu.write(0x2001, 0x08);
+        ppu.scanline = 100;
+        ppu.write(0x2007, 0);
+        assert_eq!(ppu.ppu_addr, 0x3002);
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let mut ppu = new_ppu();
+        ppu.power_on();
+
+        // Writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR are ignored until
+        // the pre-render line, but the others still land.
+        ppu.write(0x2000, 0x80);
+        ppu.write(0x2001, 0x1e);
+        ppu.write(0x2005, 0x10);
+        ppu.write(0x2006, 0x21);
+        ppu.write(0x2003, 0x10);
+        assert_eq!(ppu.ctrl.0, 0);
+        assert_eq!(ppu.mask.0, 0);
+        assert_eq!(ppu.t, 0);
+        assert!(!ppu.w);
+        assert_eq!(ppu.oam_addr, 0x10);
+
+        while ppu.scanline != 261 || ppu.dot != 1 {
+            ppu.step();
+        }
+        ppu.write(0x2000, 0x80);
+        assert_eq!(ppu.ctrl.0, 0x80);
+
+        // A reset clears the registers but keeps OAMADDR and v, and warms up
+        // again.
+        ppu.ppu_addr = 0x2108;
+        ppu.write(0x2005, 0x10);
+        ppu.reset();
+        assert_eq!(ppu.ctrl.0, 0);
+        assert_eq!(ppu.t, 0);
+        assert!(!ppu.w);
+        assert_eq!(ppu.oam_addr, 0x10);
+        assert_eq!(ppu.ppu_addr, 0x2108);
+
+        ppu.write(0x2001, 0x1e);
+        assert_eq!(ppu.mask.0, 0);
+    }
+
+    // Steps until the given dot has been run
+    fn step_to(ppu: &mut PPU, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.step();
+        }
+    }
+
+    // A PPU with sprites shown, and OAM filled with sprites that aren't on
+    // any line, other than the ones with the given Y coordinates.
+    fn new_sprite_ppu(ys: &[u8]) -> PPU {
+        let mut ppu = new_ppu();
+        ppu.mask = PPUMask(0x18);
+
+        for address in 0 .. 0x100 {
+            ppu.oam.write(address, 0xff);
+        }
+        for (n, &y) in ys.iter().enumerate() {
+            ppu.oam.write(n as u16 * 4, y);
+        }
+        ppu
+    }
+
+    #[test]
+    fn test_secondary_oam_clear() {
+        let mut ppu = new_sprite_ppu(&[]);
+        step_to(&mut ppu, 10, 0);
+        ppu.secondary_oam = [0; 32];
+
+        // Two bytes are cleared every four dots, and $2004 reads $FF meanwhile
+        step_to(&mut ppu, 10, 32);
+        assert_eq!(ppu.secondary_oam[0 .. 16], [0xff; 16]);
+        assert_eq!(ppu.secondary_oam[16 .. 32], [0; 16]);
+        assert_eq!(ppu.read(0x2004), 0xff);
+
+        step_to(&mut ppu, 10, 64);
+        assert_eq!(ppu.secondary_oam, [0xff; 32]);
+    }
+
+    #[test]
+    fn test_sprite_evaluation() {
+        let mut ppu = new_sprite_ppu(&[10, 20, 3]);
+        ppu.oam.write(1, 0x42);
+        step_to(&mut ppu, 10, 258);
+
+        // The sprites on the line are copied, in order
+        assert_eq!(ppu.eval_count, 2);
+        assert_eq!(ppu.secondary_oam[0 .. 2], [10, 0x42]);
+        assert_eq!(ppu.secondary_oam[4], 3);
+        assert_eq!(ppu.secondary_oam[8], 0xff);
+        assert!(ppu.sprite_zero);
+        assert!(!ppu.status.sprite_overflow());
+
+        // $2004 sees the bytes of OAM as they're evaluated
+        step_to(&mut ppu, 11, 65);
+        assert_eq!(ppu.read(0x2004), 10);
+        step_to(&mut ppu, 11, 67);
+        assert_eq!(ppu.read(0x2004), 0x42);
+    }
+
+    #[test]
+    fn test_sprite_evaluation_oam_addr() {
+        let mut ppu = new_sprite_ppu(&[10, 10, 10]);
+        ppu.oam.write(11, 0x42);
+
+        // Starting from sprite 2 skips the first two, and sprite 2 is taken
+        // to be sprite 0.
+        step_to(&mut ppu, 10, 64);
+        ppu.oam_addr = 8;
+        step_to(&mut ppu, 10, 258);
+        assert_eq!(ppu.eval_count, 1);
+        assert_eq!(ppu.secondary_oam[3], 0x42);
+        assert!(ppu.sprite_zero);
+        assert_eq!(ppu.oam_addr, 0);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        // After 8 sprites, the ninth is found as long as it's next
+        let mut ppu = new_sprite_ppu(&[10; 9]);
+        step_to(&mut ppu, 10, 258);
+        assert!(ppu.status.sprite_overflow());
+
+        // The flag's cleared on the pre-render line
+        step_to(&mut ppu, 261, 1);
+        assert!(!ppu.status.sprite_overflow());
+
+        // But after a sprite that isn't on the line, the next sprite's tile
+        // is compared instead of its Y coordinate. A tile number that looks
+        // like it's on the line sets the flag...
+        let mut ppu = new_sprite_ppu(&[10, 10, 10, 10, 10, 10, 10, 10, 0xff, 0xff]);
+        ppu.oam.write(9 * 4 + 1, 10);
+        step_to(&mut ppu, 10, 258);
+        assert!(ppu.status.sprite_overflow());
+
+        // ...and a sprite that is on the line can be missed
+        let mut ppu = new_sprite_ppu(&[10, 10, 10, 10, 10, 10, 10, 10, 0xff, 10]);
+        step_to(&mut ppu, 10, 258);
+        assert!(!ppu.status.sprite_overflow());
+    }
+
+    // Steps to the dot after the sprites for the next line are set up, with
+    // ten sprites on line 10.
+    // A PPU with the first n sprites on line 10, with evaluation starting
+    // from the given OAMADDR
+    fn sprites_on_line(limit: bool, n_sprites: u16, oam_addr: u8) -> PPU {
+        let mut ppu = new_ppu();
+        ppu.set_sprite_limit(limit);
+        ppu.mask = PPUMask(0x18);
+
+        for n in 0 .. 64 {
+            let y = if n < n_sprites { 10 } else { 0xff };
+            ppu.oam.write(n * 4, y);
+            ppu.oam.write(n * 4 + 3, (n * 8) as u8);
+        }
+
+        step_to(&mut ppu, 10, 60);
+        ppu.oam_addr = oam_addr;
+        step_to(&mut ppu, 10, 258);
+        ppu
+    }
+
+    #[test]
+    fn test_sprite_limit() {
+        let ppu = sprites_on_line(true, 10, 0);
+        assert_eq!(ppu.sprite_count, 8);
+        assert!(ppu.status.sprite_overflow());
+
+        // Without the limit the rest are drawn too, after the first 8, but the
+        // overflow flag's still set.
+        let ppu = sprites_on_line(false, 10, 0);
+        assert_eq!(ppu.sprite_count, 10);
+        assert_eq!(ppu.sprite_positions[8 .. 10], [64, 72]);
+        assert!(ppu.status.sprite_overflow());
+        assert!(ppu.sprite_zero);
+    }
+
+    #[test]
+    fn test_sprite_limit_oam_addr() {
+        // Evaluation starts from sprite 2, so sprites 2-9 are the first 8
+        let ppu = sprites_on_line(true, 13, 8);
+        assert_eq!(ppu.sprite_count, 8);
+        let xs: Vec<u8> = (0 .. 8).map(|slot| ppu.secondary_oam[slot * 4 + 3]).collect();
+        assert_eq!(xs, [16, 24, 32, 40, 48, 56, 64, 72]);
+
+        // The rest follow on from sprite 10, wrapping round to sprites 0 and
+        // 1, and none of them are drawn twice
+        let ppu = sprites_on_line(false, 13, 8);
+        assert_eq!(ppu.sprite_count, 13);
+        assert_eq!(ppu.sprite_positions[8 .. 13], [80, 88, 96, 0, 8]);
+    }
+}
@@ -1,5 +1,4 @@
 use crate::mem::Memory;
-use crate::palette::PALETTE;
 use crate::ppu::PPU;
 use crate::ppu::regs::{
     BACKGROUND_PALETTE_ADDRESSES,
@@ -63,7 +62,7 @@ impl PPU {
 
     // For debugging purposes. Displays the palettes and CHR data on the right
     // side of the screen.
-    pub fn render_tile_data(&mut self, canvas: &mut Canvas<Window>) {
+    pub fn render_tile_data(&mut self, canvas: &mut Canvas<Window>, palette: &[Color]) {
         let mut x = 256 * 3 + 20;
         let mut y = 10;
 
@@ -77,7 +76,7 @@ impl PPU {
         for base in BACKGROUND_PALETTE_ADDRESSES.iter() {
             for offset in 0 ..= 3 {
                 let i = self.data.read(*base + offset as u16) as usize;
-                canvas.set_draw_color(PALETTE[i % 64]);
+                canvas.set_draw_color(palette[i % 64]);
 
                 let rect = Rect::new(x + (width as i32) * offset, y, width, height);
                 canvas.fill_rect(rect).unwrap();
@@ -91,7 +90,7 @@ impl PPU {
         for base in SPRITE_PALETTE_ADDRESSES.iter() {
             for offset in 0 ..= 3 {
                 let i = self.data.read(*base + offset as u16) as usize;
-                canvas.set_draw_color(PALETTE[i % 64]);
+                canvas.set_draw_color(palette[i % 64]);
 
                 let rect = Rect::new(x + (width as i32) * offset, y, width, height);
                 canvas.fill_rect(rect).unwrap();
@@ -0,0 +1,268 @@
+use crate::palette;
+use crate::palette::NTSCSettings;
+use crate::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+use sdl2::pixels::Color;
+
+// Turns a frame of the PPU's output, where each pixel is a palette index with
+// the emphasis bits above it, into rows of RGB24 for the screen. The phase of
+// the colour subcarrier at the start of each line is given too, for filters
+// that simulate the video signal.
+pub trait Filter {
+    // The size of the frames that the filter outputs, in pixels
+    fn output_size(&self) -> (usize, usize);
+
+    fn apply(&mut self, pixels: &[u16], phases: &[u8], palette: &[Color], buffer: &mut [u8], pitch: usize);
+}
+
+// Looks up each pixel in the palette, and nothing more
+pub struct PaletteFilter;
+
+impl Filter for PaletteFilter {
+    fn output_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+
+    fn apply(&mut self, pixels: &[u16], _phases: &[u8], palette: &[Color], buffer: &mut [u8], pitch: usize) {
+        for y in 0 .. SCREEN_HEIGHT {
+            for x in 0 .. SCREEN_WIDTH {
+                let color  = palette[pixels[y * SCREEN_WIDTH + x] as usize];
+                let offset = y * pitch + x * 3;
+
+                buffer[offset]   = color.r;
+                buffer[offset+1] = color.g;
+                buffer[offset+2] = color.b;
+            }
+        }
+    }
+}
+
+impl PaletteFilter {
+    pub fn new_filter() -> Self {
+        Self
+    }
+}
+
+// The samples of the signal taken for each pixel, with 12 making up a cycle of
+// the colour subcarrier, and how many samples make up a pixel of the output.
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_OUTPUT: usize = 4;
+
+// How many samples the TV averages over to separate out the brightness, which
+// is a whole cycle of the subcarrier, and to decode the colour, which is two.
+const LUMA_WINDOW: usize = 12;
+const CHROMA_WINDOW: usize = 24;
+
+// How the picture gets from the console to the TV
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NTSCPreset {
+    // Brightness and colour share one signal, so sharp changes in brightness
+    // are mistaken for colour. This blends dithering, causes the rainbow
+    // fringes on edges, and the dot crawl as the phase moves every frame.
+    Composite,
+
+    // Brightness has its own signal and stays sharp, but the colour still
+    // bleeds between pixels.
+    SVideo,
+
+    // Every pixel gets exactly its own colour, from the current palette
+    RGB,
+}
+
+// Simulates the NTSC signal that the PPU outputs, a line at a time, and then
+// decodes it again the way a TV would. The output's twice as wide as the
+// PPU's, so the colour bleeding between pixels shows up.
+//
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+pub struct NTSCFilter {
+    preset: NTSCPreset,
+    settings: NTSCSettings,
+
+    // The level of the signal for every colour, at each of the 12 phases,
+    // and the brightness it averages out to.
+    levels: Vec<[f64; 12]>,
+    lumas: Vec<f64>,
+
+    // The subcarrier that the signal's multiplied by to decode the colour
+    cos: [f64; 12],
+    sin: [f64; 12],
+
+    // Running totals of the signal along the current line, for the brightness
+    // and both parts of the colour, so that averaging over any window of
+    // samples is just a subtraction.
+    y_sums: Vec<f64>,
+    i_sums: Vec<f64>,
+    q_sums: Vec<f64>,
+}
+
+impl Filter for NTSCFilter {
+    fn output_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * SAMPLES_PER_PIXEL / SAMPLES_PER_OUTPUT, SCREEN_HEIGHT)
+    }
+
+    fn apply(&mut self, pixels: &[u16], phases: &[u8], palette: &[Color], buffer: &mut [u8], pitch: usize) {
+        let (width, _) = self.output_size();
+
+        for y in 0 .. SCREEN_HEIGHT {
+            let line = &pixels[y * SCREEN_WIDTH .. (y + 1) * SCREEN_WIDTH];
+
+            if self.preset != NTSCPreset::RGB {
+                self.encode_line(line, phases[y] as usize);
+            }
+
+            for x in 0 .. width {
+                let center = x * SAMPLES_PER_OUTPUT + SAMPLES_PER_OUTPUT / 2;
+                let pixel  = line[center / SAMPLES_PER_PIXEL] as usize;
+
+                let color = match self.preset {
+                    NTSCPreset::Composite => self.decode(window(&self.y_sums, center, LUMA_WINDOW), center),
+                    NTSCPreset::SVideo    => self.decode(self.lumas[pixel], center),
+                    NTSCPreset::RGB       => palette[pixel],
+                };
+
+                let offset = y * pitch + x * 3;
+                buffer[offset]   = color.r;
+                buffer[offset+1] = color.g;
+                buffer[offset+2] = color.b;
+            }
+        }
+    }
+}
+
+impl NTSCFilter {
+    pub fn new_filter(preset: NTSCPreset, settings: NTSCSettings) -> Self {
+        let levels: Vec<[f64; 12]> = (0 .. 512).map(|pixel| {
+            let mut levels = [0.0; 12];
+            for phase in 0 .. 12 {
+                levels[phase] = palette::ntsc_level(pixel, phase);
+            }
+            levels
+        } ).collect();
+
+        let lumas = levels.iter()
+            .map(|levels| levels.iter().sum::<f64>() / 12.0)
+            .collect();
+
+        let mut cos = [0.0; 12];
+        let mut sin = [0.0; 12];
+        for phase in 0 .. 12 {
+            let angle = palette::subcarrier_angle(phase, &settings);
+            cos[phase] = angle.cos();
+            sin[phase] = angle.sin();
+        }
+
+        let samples = SCREEN_WIDTH * SAMPLES_PER_PIXEL + 1;
+
+        Self {
+            preset: preset,
+            settings: settings,
+            levels: levels,
+            lumas: lumas,
+            cos: cos,
+            sin: sin,
+            y_sums: vec![0.0; samples],
+            i_sums: vec![0.0; samples],
+            q_sums: vec![0.0; samples],
+        }
+    }
+
+    // Builds the signal for a line, starting at the given phase. S-Video only
+    // sends the colour through the subcarrier, so the brightness is taken
+    // back out of it.
+    fn encode_line(&mut self, line: &[u16], phase: usize) {
+        for j in 0 .. SCREEN_WIDTH * SAMPLES_PER_PIXEL {
+            let pixel = line[j / SAMPLES_PER_PIXEL] as usize;
+            let phase = (phase + j) % 12;
+
+            let mut level = self.levels[pixel][phase];
+            if self.preset == NTSCPreset::SVideo {
+                level -= self.lumas[pixel];
+            }
+
+            self.y_sums[j + 1] = self.y_sums[j] + level;
+            self.i_sums[j + 1] = self.i_sums[j] + level * self.cos[phase];
+            self.q_sums[j + 1] = self.q_sums[j] + level * self.sin[phase];
+        }
+    }
+
+    fn decode(&self, y: f64, center: usize) -> Color {
+        let i = window(&self.i_sums, center, CHROMA_WINDOW);
+        let q = window(&self.q_sums, center, CHROMA_WINDOW);
+        palette::yiq_to_rgb(y, i, q, &self.settings)
+    }
+}
+
+// The average of the samples in a window around the center, cut short at the
+// ends of the line.
+fn window(sums: &[f64], center: usize, width: usize) -> f64 {
+    let start = center.saturating_sub(width / 2);
+    let end   = std::cmp::min(center + width / 2, sums.len() - 1);
+    (sums[end] - sums[start]) / ((end - start) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::palette::PALETTE;
+
+    #[test]
+    fn test_palette_filter() {
+        let mut pixels = vec![0x0f; SCREEN_WIDTH * SCREEN_HEIGHT];
+        pixels[1] = 0x30;
+        pixels[SCREEN_WIDTH] = 0x70;
+
+        // Rows can be padded
+        let pitch = SCREEN_WIDTH * 3 + 4;
+        let mut buffer = vec![0xff; pitch * SCREEN_HEIGHT];
+
+        let mut filter = PaletteFilter::new_filter();
+        filter.apply(&pixels, &[0; SCREEN_HEIGHT], &PALETTE, &mut buffer, pitch);
+
+        assert_eq!(buffer[0 .. 6], [0x00, 0x00, 0x00, 0xff, 0xfe, 0xff]);
+        assert_eq!(buffer[pitch .. pitch + 3], [0xff, 0xcf, 0xd0]);
+        assert_eq!(buffer[SCREEN_WIDTH * 3], 0xff);
+    }
+
+    fn assert_close(a: Color, b: Color) {
+        let close = |a: u8, b: u8| (a as i16 - b as i16).abs() <= 1;
+        assert!(close(a.r, b.r) && close(a.g, b.g) && close(a.b, b.b), "{:?} != {:?}", a, b);
+    }
+
+    fn ntsc_pixel(preset: NTSCPreset, pixels: &[u16], palette: &[Color], x: usize, y: usize) -> Color {
+        let mut filter = NTSCFilter::new_filter(preset, NTSCSettings::new_ntsc_settings());
+        let (width, height) = filter.output_size();
+        let mut buffer = vec![0; width * height * 3];
+        filter.apply(pixels, &[4; SCREEN_HEIGHT], palette, &mut buffer, width * 3);
+
+        let offset = y * width * 3 + x * 3;
+        Color::RGB(buffer[offset], buffer[offset+1], buffer[offset+2])
+    }
+
+    #[test]
+    fn test_ntsc_filter() {
+        let palette = palette::generate_ntsc_palette(&NTSCSettings::new_ntsc_settings());
+
+        // A screen of one colour decodes to that colour, whatever the preset
+        let pixels = vec![0x16; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for &preset in [NTSCPreset::Composite, NTSCPreset::SVideo, NTSCPreset::RGB].iter() {
+            assert_close(ntsc_pixel(preset, &pixels, &palette, 200, 100), palette[0x16]);
+        }
+
+        // RGB uses whichever palette it's given
+        assert_eq!(ntsc_pixel(NTSCPreset::RGB, &pixels, &PALETTE, 200, 100), PALETTE[0x16]);
+
+        // A single white column on black bleeds into its neighbours, and is
+        // tinted by the composite signal.
+        let mut pixels = vec![0x0f; SCREEN_WIDTH * SCREEN_HEIGHT];
+        for y in 0 .. SCREEN_HEIGHT {
+            pixels[y * SCREEN_WIDTH + 100] = 0x30;
+        }
+
+        assert_eq!(ntsc_pixel(NTSCPreset::RGB, &pixels, &palette, 202, 0), Color::RGB(0, 0, 0));
+        assert_ne!(ntsc_pixel(NTSCPreset::Composite, &pixels, &palette, 202, 0), Color::RGB(0, 0, 0));
+
+        let white = ntsc_pixel(NTSCPreset::Composite, &pixels, &palette, 200, 0);
+        assert!(white.r != white.g || white.g != white.b);
+    }
+}
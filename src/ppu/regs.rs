@@ -1,5 +1,6 @@
 mod ctrl;
 mod data;
+mod latch;
 mod mask;
 mod oam;
 mod status;
@@ -9,6 +10,7 @@ pub use crate::ppu::regs::mask::PPUMask;
 pub use crate::ppu::regs::status::PPUStatus;
 pub use crate::ppu::regs::oam::OAM;
 pub use crate::ppu::regs::data::PPUData;
+pub use crate::ppu::regs::latch::IOLatch;
 
 pub use crate::ppu::regs::data::{
     BACKGROUND_PALETTE_ADDRESSES,
@@ -4,7 +4,7 @@ use std::io::{Read, Write};
 use std::io;
 use std::rc::Rc;
 
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, MapperEvent};
 use crate::mem::Memory;
 
 pub struct PPUData {
@@ -25,9 +25,20 @@ pub const PATTERN_TABLE_ADDRESSES: [u16; 2] =
 impl Memory for PPUData {
     fn read(&mut self, address: u16) -> u8 {
         let address = address % 0x4000;
+
+        if address < 0x3f00 {
+            self.mapper.borrow_mut().notify(MapperEvent::PPURead(address));
+        }
+
         match address {
-            0x0000 ..= 0x1fff => self.mapper.borrow_mut().read(address),
+            // The low byte of the address shares pins with the data, so it's
+            // read back when nothing on the cartridge answers.
+            0x0000 ..= 0x1fff => self.mapper.borrow_mut().read(address).unwrap_or(address as u8),
             0x2000 ..= 0x3eff => {
+                if let Some(val) = self.mapper.borrow_mut().read_nametable(address) {
+                    return val;
+                }
+
                 let mirrored_address = self.nametable_mirror_address(address);
                 self.nametables[mirrored_address]
             }
@@ -49,10 +60,19 @@ impl Memory for PPUData {
 
     fn write(&mut self, address: u16, val: u8) {
         let address = address % 0x4000;
+
+        if address < 0x3f00 {
+            self.mapper.borrow_mut().notify(MapperEvent::PPUWrite(address, val));
+        }
+
         match address {
             0x0000 ..= 0x1fff => self.mapper.borrow_mut().write(address, val),
             0x2000 ..= 0x3eff => {
                 debug!("writing 0x{:02X} to nametable 0x{:04X}", val, address);
+                if self.mapper.borrow_mut().write_nametable(address, val) {
+                    return;
+                }
+
                 let mirrored_address = self.nametable_mirror_address(address);
                 self.nametables[mirrored_address] = val;
             },
@@ -109,6 +129,13 @@ impl PPUData {
         }
     }
 
+    // Reads the pattern tables without the cartridge seeing it, for fetches
+    // that the hardware never makes.
+    pub fn peek_pattern(&mut self, address: u16) -> u8 {
+        let address = address % 0x2000;
+        self.mapper.borrow_mut().read(address).unwrap_or(address as u8)
+    }
+
     fn nametable_mirror_address(&self, address: u16) -> usize {
         // Calculates the mirrored nametable address (as an index into the
         // nametable array)
@@ -117,10 +144,8 @@ impl PPUData {
         let address = (address - 0x2000) % 0x1000;
         let table = address / 0x400;
         let offset = address % 0x400;
-        let index = 0x2000
-            + self.mapper.borrow().mirror_mode().coefficients()[table as usize] * 0x400
-            + offset as usize;
+        let page = self.mapper.borrow().nametable_page(table as usize);
 
-        index % 2048
+        (page * 0x400 + offset as usize) % self.nametables.len()
     }
 }
@@ -0,0 +1,90 @@
+// PPU I/O latch
+//
+// http://wiki.nesdev.com/w/index.php/PPU_registers#Ports
+//
+// The PPU's data bus to the CPU holds on to the last value written to or read
+// from any of its registers, and that value is what's read back from the
+// write-only registers and the bits of the others that aren't driven. Nothing
+// holds the charge up though, so each bit decays to 0 if it isn't refreshed
+// for somewhere around 600ms.
+
+use std::io;
+use std::fs::File;
+
+use crate::serde;
+
+// How many frames a bit lasts without being refreshed
+const DECAY_FRAMES: u64 = 36;
+
+pub struct IOLatch {
+    value: u8,
+
+    // The frame each bit was last refreshed on
+    refreshed: [u64; 8],
+}
+
+impl IOLatch {
+    pub fn new_io_latch() -> Self {
+        Self {
+            value: 0,
+            refreshed: [0; 8],
+        }
+    }
+
+    pub fn value(&mut self, frame: u64) -> u8 {
+        for bit in 0 .. 8 {
+            if frame.saturating_sub(self.refreshed[bit]) >= DECAY_FRAMES {
+                self.value &= !(1 << bit);
+            }
+        }
+
+        self.value
+    }
+
+    // Drives the bits in the mask onto the bus, leaving the others as they
+    // were.
+    pub fn refresh(&mut self, val: u8, mask: u8, frame: u64) {
+        self.value = (self.value & !mask) | (val & mask);
+
+        for bit in 0 .. 8 {
+            if mask & (1 << bit) != 0 {
+                self.refreshed[bit] = frame;
+            }
+        }
+    }
+
+    pub fn save(&self, output: &mut File) -> io::Result<()> {
+        serde::encode_u8(output, self.value)?;
+        for bit in 0 .. 8 {
+            serde::encode_u64(output, self.refreshed[bit])?;
+        }
+        Ok(())
+    }
+
+    pub fn load(&mut self, input: &mut File) -> io::Result<()> {
+        self.value = serde::decode_u8(input)?;
+        for bit in 0 .. 8 {
+            self.refreshed[bit] = serde::decode_u64(input)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decay() {
+        let mut latch = IOLatch::new_io_latch();
+        latch.refresh(0xff, 0xff, 0);
+        latch.refresh(0x00, 0x0f, 10);
+        assert_eq!(latch.value(10), 0xf0);
+
+        // Only the bits that were refreshed later survive
+        latch.refresh(0xff, 0x0f, 20);
+        assert_eq!(latch.value(DECAY_FRAMES - 1), 0xff);
+        assert_eq!(latch.value(DECAY_FRAMES), 0x0f);
+        assert_eq!(latch.value(DECAY_FRAMES + 20), 0x00);
+    }
+}
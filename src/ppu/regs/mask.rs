@@ -19,6 +19,13 @@ impl PPUMask {
         (val & 0x20) != 0
     }
 
+    // The three emphasis bits together, as the upper bits of an index into
+    // the full palette.
+    pub fn emphasis(&self) -> u8 {
+        let &PPUMask(val) = self;
+        val >> 5
+    }
+
     pub fn show_sprites(&self) -> bool {
         let &PPUMask(val) = self;
         (val & 0x10) != 0
@@ -39,7 +46,6 @@ impl PPUMask {
         (val & 0x02) != 0
     }
 
-    #[allow(dead_code)]
     pub fn greyscale(&self) -> bool {
         let &PPUMask(val) = self;
         (val & 0x01) != 0
@@ -0,0 +1,624 @@
+// The ways of scaling up the picture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scaler {
+    // Each pixel becomes a square block of the same colour
+    Nearest(usize),
+
+    // Scale2x and Scale3x, which round off the corners of diagonal edges
+    // without adding any new colours.
+    //
+    // https://www.scale2x.it/algorithm
+    Scale2x,
+    Scale3x,
+
+    // hq2x, which blends the edges it finds between colours that differ
+    // enough in YUV, looking up what to do with each corner of a pixel in a
+    // table of the 256 ways its neighbours can differ from it.
+    //
+    // https://en.wikipedia.org/wiki/Hqx
+    Hq2x,
+}
+
+impl Scaler {
+    pub fn factor(&self) -> usize {
+        match *self {
+            Scaler::Nearest(n) => n,
+            Scaler::Scale2x    => 2,
+            Scaler::Scale3x    => 3,
+            Scaler::Hq2x       => 2,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Scaler::Nearest(n) => format!("{}x", n),
+            Scaler::Scale2x    => "scale2x".to_string(),
+            Scaler::Scale3x    => "scale3x".to_string(),
+            Scaler::Hq2x       => "hq2x".to_string(),
+        }
+    }
+}
+
+// The scalers that can be switched between
+pub const SCALERS: [Scaler; 7] = [
+    Scaler::Nearest(1),
+    Scaler::Nearest(2),
+    Scaler::Nearest(3),
+    Scaler::Nearest(4),
+    Scaler::Scale2x,
+    Scaler::Scale3x,
+    Scaler::Hq2x,
+];
+
+// Darkening the picture to look more like a CRT
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scanlines {
+    Off,
+
+    // Darkens the gap between each line
+    Scanlines,
+
+    // The same, and also a mask over the columns, which dims two of the red,
+    // green and blue channels in turn like the phosphors of a TV.
+    CRT,
+}
+
+impl Scanlines {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Scanlines::Off       => "off",
+            Scanlines::Scanlines => "scanlines",
+            Scanlines::CRT       => "crt",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match *self {
+            Scanlines::Off       => Scanlines::Scanlines,
+            Scanlines::Scanlines => Scanlines::CRT,
+            Scanlines::CRT       => Scanlines::Off,
+        }
+    }
+}
+
+// How much of each channel is left on the darkened lines, and on the channels
+// dimmed by the CRT mask, in eighths.
+const SCANLINE_LEVEL: u32 = 5;
+const MASK_LEVEL: u32 = 6;
+
+// The thresholds for two colours being different enough to have an edge
+// between them in hq2x, for each of Y, U and V, the same as hqx's.
+const EDGE_THRESHOLDS: (i32, i32, i32) = (48, 7, 6);
+
+//
+// Post-processing of the RGB that a Filter outputs, on the CPU, before it's
+// drawn to the screen. The picture is scaled up, then has scanlines added,
+// and can be stretched to the 8:7 pixel aspect ratio of a TV when it's drawn.
+//
+pub struct PostProcessor {
+    pub scaler: Scaler,
+    pub scanlines: Scanlines,
+    pub aspect_correction: bool,
+
+    // The input and output, with each pixel as 0xRRGGBB
+    input: Vec<u32>,
+    output: Vec<u32>,
+}
+
+impl PostProcessor {
+    pub fn new_post_processor(scaler: Scaler, scanlines: Scanlines, aspect_correction: bool) -> Self {
+        Self {
+            scaler: scaler,
+            scanlines: scanlines,
+            aspect_correction: aspect_correction,
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    // Parses a list of settings like "hq2x,crt,8:7", with a scaler, "scanlines"
+    // or "crt", and "8:7" to correct the aspect ratio. Anything that isn't
+    // given is left off, and the scaler defaults to 1x.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut post = Self::new_post_processor(Scaler::Nearest(1), Scanlines::Off, false);
+
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match setting {
+                "scanlines" => post.scanlines = Scanlines::Scanlines,
+                "crt"       => post.scanlines = Scanlines::CRT,
+                "8:7"       => post.aspect_correction = true,
+                _           => {
+                    post.scaler = *SCALERS.iter().find(|s| s.name() == setting)?;
+                },
+            }
+        }
+
+        Some(post)
+    }
+
+    pub fn output_size(&self, input_size: (usize, usize)) -> (usize, usize) {
+        let (width, height) = input_size;
+        let factor = self.scaler.factor();
+        (width * factor, height * factor)
+    }
+
+    // Scales `input', a packed RGB24 image of the given size, into `buffer'
+    pub fn apply(&mut self, input: &[u8], input_size: (usize, usize), buffer: &mut [u8], pitch: usize) {
+        let (width, height) = input_size;
+        let (output_width, output_height) = self.output_size(input_size);
+
+        self.input.clear();
+        self.input.extend(input[.. width * height * 3].chunks(3).map(|c| {
+            ((c[0] as u32) << 16) | ((c[1] as u32) << 8) | c[2] as u32
+        } ));
+        self.output.resize(output_width * output_height, 0);
+
+        match self.scaler {
+            Scaler::Nearest(n) => nearest(&self.input, width, height, n, &mut self.output),
+            Scaler::Scale2x    => scale2x(&self.input, width, height, &mut self.output),
+            Scaler::Scale3x    => scale3x(&self.input, width, height, &mut self.output),
+            Scaler::Hq2x       => hq2x(&self.input, width, height, &mut self.output),
+        }
+
+        let factor = self.scaler.factor();
+
+        for y in 0 .. output_height {
+            // There's no gap between the lines to darken until the picture's
+            // been scaled up.
+            let scanline = self.scanlines != Scanlines::Off && factor > 1 && y % factor == factor - 1;
+
+            for x in 0 .. output_width {
+                let mut levels = [8, 8, 8];
+
+                if scanline {
+                    levels = [SCANLINE_LEVEL; 3];
+                }
+
+                if self.scanlines == Scanlines::CRT {
+                    for channel in 0 .. 3 {
+                        if channel != x % 3 {
+                            levels[channel] = levels[channel] * MASK_LEVEL / 8;
+                        }
+                    }
+                }
+
+                let color  = self.output[y * output_width + x];
+                let offset = y * pitch + x * 3;
+
+                buffer[offset]   = (((color >> 16) & 0xff) * levels[0] / 8) as u8;
+                buffer[offset+1] = (((color >> 8)  & 0xff) * levels[1] / 8) as u8;
+                buffer[offset+2] = ((color         & 0xff) * levels[2] / 8) as u8;
+            }
+        }
+    }
+}
+
+// The 3x3 block of pixels around a pixel, repeating the pixels at the edges
+//
+//   A B C
+//   D E F
+//   G H I
+fn neighbours(input: &[u32], width: usize, height: usize, x: usize, y: usize) -> [u32; 9] {
+    let left  = x.saturating_sub(1);
+    let right = std::cmp::min(x + 1, width - 1);
+    let up    = y.saturating_sub(1);
+    let down  = std::cmp::min(y + 1, height - 1);
+
+    let pixel = |x: usize, y: usize| input[y * width + x];
+
+    [
+        pixel(left, up),   pixel(x, up),   pixel(right, up),
+        pixel(left, y),    pixel(x, y),    pixel(right, y),
+        pixel(left, down), pixel(x, down), pixel(right, down),
+    ]
+}
+
+// Writes a block of the scaled output, for the pixel at `x' and `y'
+fn write_block(output: &mut [u32], width: usize, factor: usize, x: usize, y: usize, block: &[u32]) {
+    for by in 0 .. factor {
+        let row = (y * factor + by) * width * factor;
+
+        for bx in 0 .. factor {
+            output[row + x * factor + bx] = block[by * factor + bx];
+        }
+    }
+}
+
+fn nearest(input: &[u32], width: usize, height: usize, factor: usize, output: &mut [u32]) {
+    let output_width = width * factor;
+
+    for y in 0 .. height * factor {
+        let row = &input[(y / factor) * width .. (y / factor + 1) * width];
+
+        for (x, pixel) in output[y * output_width .. (y + 1) * output_width].iter_mut().enumerate() {
+            *pixel = row[x / factor];
+        }
+    }
+}
+
+fn scale2x(input: &[u32], width: usize, height: usize, output: &mut [u32]) {
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let [_, b, _, d, e, f, _, h, _] = neighbours(input, width, height, x, y);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 4]
+            };
+
+            write_block(output, width, 2, x, y, &block);
+        }
+    }
+}
+
+fn scale3x(input: &[u32], width: usize, height: usize, output: &mut [u32]) {
+    for y in 0 .. height {
+        for x in 0 .. width {
+            let [a, b, c, d, e, f, g, h, i] = neighbours(input, width, height, x, y);
+
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+
+            write_block(output, width, 3, x, y, &block);
+        }
+    }
+}
+
+fn yuv(color: u32) -> (i32, i32, i32) {
+    let r = ((color >> 16) & 0xff) as i32;
+    let g = ((color >> 8) & 0xff) as i32;
+    let b = (color & 0xff) as i32;
+
+    (
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000 + 128,
+        (500 * r - 419 * g - 81 * b) / 1000 + 128,
+    )
+}
+
+fn differ(a: u32, b: u32) -> bool {
+    if a == b {
+        return false;
+    }
+
+    let (y1, u1, v1) = yuv(a);
+    let (y2, u2, v2) = yuv(b);
+    let (ty, tu, tv) = EDGE_THRESHOLDS;
+
+    (y1 - y2).abs() > ty || (u1 - u2).abs() > tu || (v1 - v2).abs() > tv
+}
+
+// Blends colours together, each with a weight
+fn blend(colors: &[(u32, u32)]) -> u32 {
+    let total: u32 = colors.iter().map(|&(_, weight)| weight).sum();
+
+    let channel = |shift: u32| {
+        let sum: u32 = colors.iter()
+            .map(|&(color, weight)| ((color >> shift) & 0xff) * weight)
+            .sum();
+        (sum / total) << shift
+    };
+
+    channel(16) | channel(8) | channel(0)
+}
+
+// The blends that hq2x makes for the top left corner of pixel `E', named
+// after hqx's PIXEL00_* macros, with its neighbours named as in neighbours()
+#[derive(Clone, Copy)]
+enum Hq2xBlend {
+    P0,     // E
+    P10,    // (3E + A) / 4
+    P11,    // (3E + D) / 4
+    P12,    // (3E + B) / 4
+    P20,    // (2E + D + B) / 4
+    P21,    // (2E + A + B) / 4
+    P22,    // (2E + A + D) / 4
+    P60,    // (5E + 2B + D) / 8
+    P61,    // (5E + 2D + B) / 8
+    P70,    // (6E + D + B) / 8
+    P90,    // (2E + 3D + 3B) / 8
+    P100,   // (14E + D + B) / 16
+}
+
+// What hq2x does with the top left corner: either always the same blend, or
+// the first blend if the two neighbours along an edge differ from each other
+// and the second if they don't. The edge is the corner's own, between `D'
+// and `B', or the one between `B' and `F' along the top, or between `H' and
+// `D' down the left.
+#[derive(Clone, Copy)]
+enum Hq2xCorner {
+    Always(Hq2xBlend),
+    Edge(Hq2xBlend, Hq2xBlend),
+    NextEdge(Hq2xBlend, Hq2xBlend),
+    PrevEdge(Hq2xBlend, Hq2xBlend),
+}
+
+use self::Hq2xBlend::*;
+use self::Hq2xCorner::*;
+
+// hq2x's table for the top left corner of a pixel, indexed by which of its
+// neighbours differ from it, with A as bit 0 through to I as bit 7. hqx has
+// a case for each corner, but the table is the same for all four once the
+// neighbours are turned round so that the corner is at the top left.
+const HQ2X: [Hq2xCorner; 256] = [
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P20),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Edge(P10, P90),     Edge(P0, P90),
+    Always(P20),        Always(P20),        Always(P22),        NextEdge(P11, P60),
+    Always(P20),        Always(P20),        Always(P22),        NextEdge(P11, P60),
+    Always(P21),        Always(P12),        Edge(P0, P20),      Edge(P0, P20),
+    Always(P21),        Always(P12),        Always(P10),        Edge(P0, P20),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P90),     Edge(P0, P90),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P100),
+    Always(P20),        Always(P20),        Always(P22),        NextEdge(P11, P60),
+    Always(P20),        Always(P20),        Always(P22),        NextEdge(P11, P60),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Always(P10),        Edge(P0, P100),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        PrevEdge(P12, P61), Edge(P0, P20),      Edge(P0, P20),
+    Always(P21),        PrevEdge(P12, P61), Edge(P10, P70),     Edge(P0, P20),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        PrevEdge(P12, P61), Always(P10),        Edge(P0, P20),
+    Always(P21),        PrevEdge(P12, P61), Always(P10),        Edge(P0, P100),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        NextEdge(P11, P60),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P21),        PrevEdge(P12, P61), Always(P10),        Edge(P0, P100),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P20),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Edge(P10, P90),     Edge(P0, P90),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P90),     Edge(P0, P90),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P100),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P90),
+    Always(P21),        Always(P12),        Always(P10),        Edge(P0, P100),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P90),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Always(P10),        Edge(P0, P20),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Edge(P10, P70),     Edge(P0, P20),
+    Always(P21),        Always(P12),        Always(P10),        Edge(P0, P100),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P20),        Always(P20),        Always(P22),        Always(P11),
+    Always(P21),        Always(P12),        Always(P10),        Edge(P0, P20),
+    Always(P21),        Always(P12),        Always(P10),        Edge(P0, P100),
+];
+
+// One corner of a pixel, from its neighbours turned round so that the
+// corner's at the top left
+fn hq2x_corner(w: &[u32; 9]) -> u32 {
+    let [a, b, c, d, e, f, g, h, i] = *w;
+
+    let pattern = [a, b, c, d, f, g, h, i].iter().enumerate()
+        .fold(0, |pattern, (bit, &n)| pattern | ((differ(e, n) as usize) << bit));
+
+    let kind = match HQ2X[pattern] {
+        Always(kind)            => kind,
+        Edge(edge, no_edge)     => if differ(d, b) { edge } else { no_edge },
+        NextEdge(edge, no_edge) => if differ(b, f) { edge } else { no_edge },
+        PrevEdge(edge, no_edge) => if differ(h, d) { edge } else { no_edge },
+    };
+
+    match kind {
+        P0   => e,
+        P10  => blend(&[(e, 3), (a, 1)]),
+        P11  => blend(&[(e, 3), (d, 1)]),
+        P12  => blend(&[(e, 3), (b, 1)]),
+        P20  => blend(&[(e, 2), (d, 1), (b, 1)]),
+        P21  => blend(&[(e, 2), (a, 1), (b, 1)]),
+        P22  => blend(&[(e, 2), (a, 1), (d, 1)]),
+        P60  => blend(&[(e, 5), (b, 2), (d, 1)]),
+        P61  => blend(&[(e, 5), (d, 2), (b, 1)]),
+        P70  => blend(&[(e, 6), (d, 1), (b, 1)]),
+        P90  => blend(&[(e, 2), (d, 3), (b, 3)]),
+        P100 => blend(&[(e, 14), (d, 1), (b, 1)]),
+    }
+}
+
+// Turns a 3x3 block a quarter anticlockwise
+fn rotate(w: &[u32; 9]) -> [u32; 9] {
+    [w[2], w[5], w[8], w[1], w[4], w[7], w[0], w[3], w[6]]
+}
+
+fn hq2x(input: &[u32], width: usize, height: usize, output: &mut [u32]) {
+    for y in 0 .. height {
+        for x in 0 .. width {
+            // Each turn brings the next corner clockwise round to the top left
+            let top_left     = neighbours(input, width, height, x, y);
+            let top_right    = rotate(&top_left);
+            let bottom_right = rotate(&top_right);
+            let bottom_left  = rotate(&bottom_right);
+
+            let block = [
+                hq2x_corner(&top_left),    hq2x_corner(&top_right),
+                hq2x_corner(&bottom_left), hq2x_corner(&bottom_right),
+            ];
+
+            write_block(output, width, 2, x, y, &block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaled(scaler: Scaler, scanlines: Scanlines, input: &[u8], size: (usize, usize)) -> Vec<u8> {
+        let mut post = PostProcessor::new_post_processor(scaler, scanlines, false);
+        let (width, height) = post.output_size(size);
+        let mut buffer = vec![0; width * height * 3];
+        post.apply(input, size, &mut buffer, width * 3);
+        buffer
+    }
+
+    // A 2x2 image with black on the diagonal, and white elsewhere
+    const DIAGONAL: [u8; 12] = [
+        0x00, 0x00, 0x00,  0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff,  0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_nearest() {
+        let buffer = scaled(Scaler::Nearest(3), Scanlines::Off, &DIAGONAL, (2, 2));
+        assert_eq!(buffer.len(), 6 * 6 * 3);
+        assert_eq!(buffer[2 * 3], 0x00);
+        assert_eq!(buffer[3 * 3], 0xff);
+        assert_eq!(buffer[(2 * 6 + 2) * 3], 0x00);
+        assert_eq!(buffer[(3 * 6 + 2) * 3], 0xff);
+    }
+
+    #[test]
+    fn test_scale2x() {
+        // The white pixels round off their corners into the black
+        let buffer = scaled(Scaler::Scale2x, Scanlines::Off, &DIAGONAL, (2, 2));
+        let pixel = |x: usize, y: usize| buffer[(y * 4 + x) * 3];
+        assert_eq!(pixel(0, 0), 0x00);
+        assert_eq!(pixel(1, 1), 0xff);
+        assert_eq!(pixel(3, 3), 0x00);
+
+        let buffer = scaled(Scaler::Scale3x, Scanlines::Off, &[0x80; 12], (2, 2));
+        assert!(buffer.iter().all(|&c| c == 0x80));
+    }
+
+    // Runs hq2x over an image with each pixel as 0xRRGGBB
+    fn hq2x_pixels(input: &[u32], size: (usize, usize)) -> Vec<u32> {
+        let rgb: Vec<u8> = input.iter()
+            .flat_map(|&c| vec![(c >> 16) as u8, (c >> 8) as u8, c as u8])
+            .collect();
+
+        scaled(Scaler::Hq2x, Scanlines::Off, &rgb, size).chunks(3)
+            .map(|c| ((c[0] as u32) << 16) | ((c[1] as u32) << 8) | c[2] as u32)
+            .collect()
+    }
+
+    const W: u32 = 0xffffff;
+    const K: u32 = 0x000000;
+    const R: u32 = 0xff0000;
+    const B: u32 = 0x0000ff;
+
+    #[test]
+    fn test_hq2x() {
+        // Solid colours are left alone
+        let buffer = scaled(Scaler::Hq2x, Scanlines::Off, &[0x80; 12], (2, 2));
+        assert!(buffer.iter().all(|&c| c == 0x80));
+
+        // A pixel on its own, which differs from all its neighbours, is
+        // rounded off at each corner with (14E + D + B) / 16
+        let output = hq2x_pixels(&[W, W, W, W, K, W, W, W, W], (3, 3));
+        assert_eq!(output[2 * 6 + 2 .. 2 * 6 + 4], [0x1f1f1f, 0x1f1f1f]);
+        assert_eq!(output[3 * 6 + 2 .. 3 * 6 + 4], [0x1f1f1f, 0x1f1f1f]);
+        assert!(output.iter().enumerate().all(|(n, &c)| {
+            (2 ..= 3).contains(&(n % 6)) && (2 ..= 3).contains(&(n / 6)) || c == W
+        } ));
+
+        // A diagonal line, and a corner of a different colour
+        let input = [
+            W, W, W, W,
+            W, K, W, W,
+            W, W, K, W,
+            W, W, W, R,
+        ];
+        let expected = [
+            W, W, W,        W,        W,        W,        W,        W,
+            W, W, W,        W,        W,        W,        W,        W,
+            W, W, 0x1f1f1f, 0x7f7f7f, W,        W,        W,        W,
+            W, W, 0x7f7f7f, K,        0x7f7f7f, W,        W,        W,
+            W, W, W,        0x7f7f7f, K,        0x7f7f7f, W,        W,
+            W, W, W,        W,        0x7f7f7f, 0x1f1f1f, W,        W,
+            W, W, W,        W,        W,        W,        0xff1f1f, R,
+            W, W, W,        W,        W,        W,        R,        R,
+        ];
+        assert_eq!(hq2x_pixels(&input, (4, 4)), expected.to_vec());
+
+        // A shallow staircase, which hqx blends along its steps
+        let input = [
+            W, W, W, B,
+            W, B, B, W,
+            B, W, W, W,
+            W, W, W, W,
+        ];
+        let expected = [
+            W,        W,        W,        W,        W,        W,        B,        B,
+            W,        W,        W,        W,        W,        0xbfbfff, 0x3f3fff, B,
+            W,        W,        0xbfbfff, 0x3f3fff, B,        B,        0x3f3fff, 0xbfbfff,
+            0xbfbfff, 0x3f3fff, B,        B,        0x3f3fff, 0xbfbfff, W,        W,
+            B,        B,        0x3f3fff, 0xbfbfff, W,        W,        W,        W,
+            0x3f3fff, 0xbfbfff, W,        W,        W,        W,        W,        W,
+            W,        W,        W,        W,        W,        W,        W,        W,
+            W,        W,        W,        W,        W,        W,        W,        W,
+        ];
+        assert_eq!(hq2x_pixels(&input, (4, 4)), expected.to_vec());
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let white = [0xff; 12];
+
+        let buffer = scaled(Scaler::Nearest(2), Scanlines::Scanlines, &white, (2, 2));
+        assert_eq!(buffer[0], 0xff);
+        assert_eq!(buffer[4 * 3], 0x9f);
+
+        let buffer = scaled(Scaler::Nearest(2), Scanlines::CRT, &white, (2, 2));
+        assert_eq!(buffer[0 .. 6], [0xff, 0xbf, 0xbf, 0xbf, 0xff, 0xbf]);
+
+        // There's nowhere to put scanlines without scaling
+        let buffer = scaled(Scaler::Nearest(1), Scanlines::Scanlines, &white, (2, 2));
+        assert!(buffer.iter().all(|&c| c == 0xff));
+    }
+
+    #[test]
+    fn test_parse() {
+        let post = PostProcessor::parse("hq2x, crt, 8:7").unwrap();
+        assert_eq!(post.scaler, Scaler::Hq2x);
+        assert_eq!(post.scanlines, Scanlines::CRT);
+        assert!(post.aspect_correction);
+
+        let post = PostProcessor::parse("").unwrap();
+        assert_eq!(post.scaler, Scaler::Nearest(1));
+        assert!(!post.aspect_correction);
+
+        assert!(PostProcessor::parse("5x").is_none());
+    }
+}
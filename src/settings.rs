@@ -0,0 +1,198 @@
+// Video settings that are remembered between runs, in a file of lines like
+// "scale=3".
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// The largest window scale that can be picked
+pub const MAX_SCALE: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    // Parses "top,bottom,left,right", in pixels. Less than half of the
+    // picture can be cut off from each side.
+    pub fn parse(s: &str) -> Option<Self> {
+        let edges: Vec<usize> = s.split(',')
+            .map(|edge| edge.trim().parse().ok())
+            .collect::<Option<_>>()?;
+
+        if edges.len() != 4 {
+            return None;
+        }
+
+        let overscan = Self {
+            top: edges[0],
+            bottom: edges[1],
+            left: edges[2],
+            right: edges[3],
+        };
+
+        if overscan.top + overscan.bottom >= SCREEN_HEIGHT / 2 || overscan.left + overscan.right >= SCREEN_WIDTH / 2 {
+            return None;
+        }
+
+        Some(overscan)
+    }
+
+    // The size of the picture that's left, in NES pixels
+    pub fn visible_size(&self) -> (usize, usize) {
+        (
+            SCREEN_WIDTH - self.left - self.right,
+            SCREEN_HEIGHT - self.top - self.bottom,
+        )
+    }
+
+    // The next of the OVERSCANS, or the first if this isn't one of them
+    pub fn next(&self) -> Self {
+        match OVERSCANS.iter().position(|o| o == self) {
+            Some(i) => OVERSCANS[(i + 1) % OVERSCANS.len()],
+            None    => OVERSCANS[0],
+        }
+    }
+}
+
+// Writes the overscan in the format that parse() reads
+impl fmt::Display for Overscan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.top, self.bottom, self.left, self.right)
+    }
+}
+
+// The overscans that can be cycled through while running. The first is the
+// default, cutting off the top and bottom 8 lines.
+pub const OVERSCANS: [Overscan; 3] = [
+    Overscan { top: 8, bottom: 8, left: 0, right: 0 },
+    Overscan { top: 0, bottom: 0, left: 0, right: 0 },
+    Overscan { top: 8, bottom: 8, left: 8, right: 8 },
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VideoSettings {
+    // How much to cut off each edge. Most TVs hid around 8 lines at the top
+    // and bottom, and games often left garbage there, like the scrolling
+    // seams in Super Mario Bros. 3.
+    pub overscan: Overscan,
+
+    // The size of the window, as a multiple of the NES's pixels
+    pub scale: u32,
+
+    pub fullscreen: bool,
+}
+
+impl VideoSettings {
+    pub fn new_video_settings() -> Self {
+        Self {
+            overscan: OVERSCANS[0],
+            scale: 3,
+            fullscreen: false,
+        }
+    }
+
+    // Settings missing from the file keep their defaults, and ones that can't
+    // be read are ignored.
+    pub fn parse(s: &str) -> Self {
+        let mut settings = Self::new_video_settings();
+
+        for line in s.lines() {
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let val = parts.next().unwrap_or("").trim();
+
+            match name {
+                "overscan" => {
+                    if let Some(overscan) = Overscan::parse(val) {
+                        settings.overscan = overscan;
+                    }
+                },
+                "scale" => {
+                    if let Ok(scale) = val.parse::<u32>() {
+                        settings.scale = scale.clamp(1, MAX_SCALE);
+                    }
+                },
+                "fullscreen" => settings.fullscreen = val == "1",
+                _ => {},
+            }
+        }
+
+        settings
+    }
+
+    pub fn load(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(s)  => Self::parse(&s),
+            Err(_) => Self::new_video_settings(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+// Writes the settings in the format that parse() reads
+impl fmt::Display for VideoSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "overscan={}\nscale={}\nfullscreen={}\n",
+            self.overscan,
+            self.scale,
+            self.fullscreen as u8,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overscan() {
+        let overscan = Overscan::parse("8, 16, 0, 4").unwrap();
+        assert_eq!(overscan, Overscan { top: 8, bottom: 16, left: 0, right: 4 });
+        assert_eq!(overscan.visible_size(), (252, 216));
+
+        assert!(Overscan::parse("8,8,0").is_none());
+        assert!(Overscan::parse("8,8,0,x").is_none());
+        assert!(Overscan::parse("100,20,0,0").is_none());
+
+        assert_eq!(Overscan::parse(&overscan.to_string()), Some(overscan));
+    }
+
+    #[test]
+    fn test_overscan_next() {
+        let mut overscan = OVERSCANS[0];
+        for i in 1 ..= OVERSCANS.len() {
+            overscan = overscan.next();
+            assert_eq!(overscan, OVERSCANS[i % OVERSCANS.len()]);
+        }
+
+        // One set in the environment goes back to the first
+        let overscan = Overscan::parse("1,2,3,4").unwrap();
+        assert_eq!(overscan.next(), OVERSCANS[0]);
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut settings = VideoSettings::new_video_settings();
+        settings.overscan.left = 8;
+        settings.scale = 4;
+        settings.fullscreen = true;
+        assert_eq!(VideoSettings::parse(&settings.to_string()), settings);
+
+        // Anything missing or invalid keeps its default
+        let settings = VideoSettings::parse("scale=100\noverscan=1,2\nvolume=11\n");
+        assert_eq!(settings.scale, MAX_SCALE);
+        assert_eq!(settings.overscan, VideoSettings::new_video_settings().overscan);
+    }
+}
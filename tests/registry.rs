@@ -0,0 +1,72 @@
+use std::io;
+use std::fs::File;
+
+use nes::{Cartridge, Mapper, MapperConstructor, MapperRegistry};
+
+// A board defined outside of the crate, with 32KB of PRG-ROM and nothing else
+struct TestBoard {
+    prg_rom: Vec<u8>,
+}
+
+impl Mapper for TestBoard {
+    fn read(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x8000 ..= 0xffff => Some(self.prg_rom[address as usize & 0x7fff]),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, _address: u16, _val: u8) { }
+
+    fn save(&self, _output: &mut File) -> io::Result<()> { Ok(()) }
+    fn load(&mut self, _input: &mut File) -> io::Result<()> { Ok(()) }
+}
+
+fn new_test_board(cart: Cartridge) -> Box<dyn Mapper> {
+    Box::new(TestBoard { prg_rom: cart.prg_rom })
+}
+
+fn cartridge(submapper: u8) -> Cartridge {
+    let mut prg_rom = vec![0; 0x8000];
+    prg_rom[0x7fff] = 0x42;
+
+    Cartridge {
+        prg_rom: prg_rom,
+        chr_rom: vec![],
+        mirror_mode: 0,
+        battery: false,
+        submapper: submapper,
+        prg_ram_size: 0,
+        chr_ram_size: 0x2000,
+    }
+}
+
+#[test]
+fn test_register_board() {
+    let mut registry = MapperRegistry::new_default_registry();
+    let new: MapperConstructor = new_test_board;
+    registry.register(0x1ff, Some(3), "Test board", new);
+
+    let entry = registry.find(0x1ff, 3).unwrap();
+    assert_eq!(entry.name, "Test board");
+    assert!(registry.find(0x1ff, 0).is_none());
+
+    let mut mapper = (entry.new)(cartridge(3));
+    assert_eq!(mapper.read(0xffff), Some(0x42));
+    assert_eq!(mapper.read(0x6000), None);
+
+    // The built-in boards are still there
+    assert_eq!(registry.find(0, 0).unwrap().name, "NROM");
+}
+
+#[test]
+fn test_replace_board() {
+    let mut registry = MapperRegistry::new_default_registry();
+    registry.register(0, None, "Test board", new_test_board);
+
+    let entry = registry.find(0, 0).unwrap();
+    assert_eq!(entry.name, "Test board");
+
+    let mut mapper = (entry.new)(cartridge(0));
+    assert_eq!(mapper.read(0xffff), Some(0x42));
+}